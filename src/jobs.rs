@@ -0,0 +1,197 @@
+//! Runs external commands in process groups of their own in interactive
+//! sessions.
+//!
+//! The commands of a pipeline share a process group, which is given the
+//! terminal while it runs. If the command line running them is cancelled, the
+//! whole group is killed, including any processes the commands started
+//! themselves.
+//!
+//! Outside of interactive sessions, commands stay in the shell's process group,
+//! which is where the terminal sends its signals.
+
+use crate::pipes::nix_error;
+use nix::sys::signal::{self, killpg, SigHandler, Signal};
+use nix::unistd::{self, Pid};
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use tokio::process::Command;
+
+/// A process group shared by the commands of a pipeline. A new group is
+/// created whenever a command starts while none of the others are running.
+#[derive(Debug)]
+pub(crate) struct ProcessGroup {
+    id: Cell<Option<Pid>>,
+    running: Cell<usize>,
+
+    /// Whether the group is given the terminal while it runs.
+    foreground: bool,
+}
+
+impl ProcessGroup {
+    /// Create a process group that is given the terminal while it runs.
+    pub(crate) fn foreground() -> io::Result<Self> {
+        // The shell is in the background when it takes the terminal back from
+        // a process group, which stops it unless it ignores SIGTTOU.
+        unsafe {
+            signal::signal(Signal::SIGTTOU, SigHandler::SigIgn).map_err(nix_error)?;
+        }
+
+        Ok(Self::with_terminal(true))
+    }
+
+    /// Create a process group that leaves the terminal to the shell.
+    pub(crate) fn background() -> Self {
+        Self::with_terminal(false)
+    }
+
+    fn with_terminal(foreground: bool) -> Self {
+        Self {
+            id: Cell::new(None),
+            running: Cell::new(0),
+            foreground,
+        }
+    }
+
+    /// Create another group for the commands of a new pipeline, which uses the
+    /// terminal the same way as this one.
+    pub(crate) fn new_pipeline(&self) -> Self {
+        Self::with_terminal(self.foreground)
+    }
+
+    /// Set up a command to start in this group.
+    pub(crate) fn prepare(&self, command: &mut Command) {
+        let id = self.id.get().unwrap_or_else(|| Pid::from_raw(0));
+        let foreground = self.foreground;
+
+        unsafe {
+            command.pre_exec(move || {
+                // All of the group's processes may have exited since, in which
+                // case the command starts a new group instead.
+                if unistd::setpgid(Pid::from_raw(0), id).is_err() {
+                    unistd::setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(nix_error)?;
+                }
+
+                if foreground {
+                    // Take the terminal before running anything that might
+                    // read from it.
+                    unistd::tcsetpgrp(0, unistd::getpgrp()).ok();
+
+                    // The shell can't resume stopped commands, so the terminal
+                    // never stops them. SIGTTOU is already ignored, like the
+                    // shell.
+                    signal::signal(Signal::SIGTSTP, SigHandler::SigIgn).map_err(nix_error)?;
+                    signal::signal(Signal::SIGTTIN, SigHandler::SigIgn).map_err(nix_error)?;
+                }
+
+                Ok(())
+            });
+        }
+    }
+
+    /// Add a started process to the group. The group is killed if the returned
+    /// member is dropped before the process finishes.
+    pub(crate) fn join(self: &Rc<Self>, pid: u32) -> io::Result<Member> {
+        let id = unistd::getpgid(Some(Pid::from_raw(pid as i32))).map_err(nix_error)?;
+
+        self.id.set(Some(id));
+        self.running.set(self.running.get() + 1);
+
+        if self.foreground {
+            unistd::tcsetpgrp(0, id).ok();
+        }
+
+        Ok(Member {
+            group: self.clone(),
+            id,
+            finished: false,
+        })
+    }
+}
+
+/// A running process in a process group.
+pub(crate) struct Member {
+    group: Rc<ProcessGroup>,
+    id: Pid,
+    finished: bool,
+}
+
+impl Member {
+    /// Record that the process has exited.
+    pub(crate) fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        if !self.finished {
+            log::debug!("killing process group {}", self.id);
+            killpg(self.id, Signal::SIGKILL).ok();
+        }
+
+        let running = self.group.running.get() - 1;
+        self.group.running.set(running);
+
+        // Give the terminal back to the shell once the group is done with it.
+        if running == 0 {
+            self.group.id.set(None);
+
+            if self.group.foreground {
+                unistd::tcsetpgrp(0, unistd::getpgrp()).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::testing::{block_on, execute, fiber};
+    use futures::future::{select, FutureExt};
+    use std::fs;
+    use std::time::Duration;
+
+    /// Check whether a process is still running, rather than gone or waiting
+    /// to be reaped.
+    fn is_running(pid: &str) -> bool {
+        match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => !stat.rsplit(')').next().unwrap().trim_start().starts_with('Z'),
+            Err(_) => false,
+        }
+    }
+
+    #[test]
+    fn cancelled_commands_have_their_process_group_killed() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+
+        block_on(async {
+            let mut fiber = fiber();
+            fiber.process_group = Some(Rc::new(ProcessGroup::background()));
+
+            let source = format!("sh -c 'sleep 100 & echo $! > {}; wait' | cat", pid_file.display());
+            let running = execute(&mut fiber, &source);
+
+            let started = async {
+                while !fs::read_to_string(&pid_file).map(|pid| pid.ends_with('\n')).unwrap_or(false) {
+                    tokio::time::delay_for(Duration::from_millis(10)).await;
+                }
+            };
+
+            select(running.boxed_local(), started.boxed_local()).await;
+        });
+
+        let pid = fs::read_to_string(&pid_file).unwrap();
+        let pid = pid.trim();
+
+        for _ in 0..100 {
+            if !is_running(pid) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        panic!("sleep {} is still running", pid);
+    }
+}
@@ -3,14 +3,21 @@
 #![allow(dead_code)]
 
 use crate::{
+    jobs::ProcessGroup,
     runtime::prelude::*,
     runtime::syntax::source::SourceFile,
-    shell::Editor,
+    shell::{builtins, plugins, Completer, Editor, Highlighter, History},
+    signals::Signals,
 };
+use directories::ProjectDirs;
+use nix::libc::{self, c_int};
 use std::{
+    env,
     io::Read,
     path::{Path, PathBuf},
     process::exit,
+    rc::Rc,
+    time::Instant,
 };
 use structopt::StructOpt;
 
@@ -19,12 +26,23 @@ mod macros;
 
 mod exit;
 mod io;
+mod jobs;
 mod logger;
 mod pipes;
 mod runtime;
 mod shell;
+mod signals;
 mod stdlib;
 
+/// Directory holding the system-wide startup scripts.
+const SYSTEM_CONFIG_DIR: &str = "/etc/riptide";
+
+/// Name of the startup script run by login shells.
+const PROFILE_FILE: &str = "profile.rip";
+
+/// Name of the startup script run by interactive sessions.
+const INIT_FILE: &str = "init.rip";
+
 #[derive(Debug, StructOpt)]
 struct Options {
     /// Evaluate the specified commands
@@ -32,9 +50,21 @@ struct Options {
     commands: Vec<String>,
 
     /// Run as a login shell
+    ///
+    /// Login shells run the profile scripts before anything else. The shell is
+    /// also a login shell if it is started with a name beginning with `-`.
     #[structopt(short = "l", long = "login")]
     login: bool,
 
+    /// Don't run the init scripts in an interactive session
+    #[structopt(long = "norc")]
+    norc: bool,
+
+    /// Run the given init script instead of the usual ones in an interactive
+    /// session
+    #[structopt(long = "rcfile", parse(from_os_str), conflicts_with = "norc")]
+    rcfile: Option<PathBuf>,
+
     /// Set the verbosity level
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     verbosity: usize,
@@ -43,6 +73,13 @@ struct Options {
     #[structopt(short = "q", long = "quiet")]
     quiet: bool,
 
+    /// Raise an exception when an external command fails
+    ///
+    /// In strict mode, any external command that exits with a non-zero status,
+    /// including any stage of a pipeline, raises a `command-failed` exception.
+    #[structopt(short = "e", long = "strict")]
+    strict: bool,
+
     /// File to execute
     #[structopt(parse(from_os_str))]
     file: Option<PathBuf>,
@@ -54,9 +91,23 @@ struct Options {
     /// session will be forgotten when the session terminates.
     #[structopt(long = "private")]
     private: bool,
+
+    /// Browse existing history in a private session.
+    ///
+    /// Commands from the history file are copied into the private session's
+    /// history when it starts. The history file itself is only read from.
+    #[structopt(long = "import-history", requires = "private")]
+    import_history: bool,
 }
 
 impl Options {
+    fn is_login(&self) -> bool {
+        self.login || env::args_os()
+            .next()
+            .map(|name| name.to_string_lossy().starts_with('-'))
+            .unwrap_or(false)
+    }
+
     fn log_level_filter(&self) -> log::LevelFilter {
         if self.quiet {
             log::LevelFilter::Off
@@ -83,32 +134,40 @@ async fn main() {
     log::set_max_level(options.log_level_filter());
 
     let mut fiber = Fiber::default();
+    fiber.set_strict(options.strict);
+
+    let mut signals = Signals::new();
+
+    // Startup scripts are run in the same scope as commands, scripts, and
+    // interactive command lines, so that what they define can be used there.
+    let scope = table!();
+
+    if options.is_login() {
+        for path in startup_files(PROFILE_FILE) {
+            source_startup_file(&mut fiber, &path, &scope).await;
+        }
+    }
 
     // If at least one command is given, execute those in order and exit.
     if !options.commands.is_empty() {
         for command in options.commands {
-            match fiber.execute(None, command).await {
-                Ok(_) => {}
-                Err(e) => {
-                    log::error!("{}", e);
-                    exit::set(1);
-                    break;
-                }
+            if !execute(&mut fiber, &mut signals, &scope, command).await {
+                break;
             }
         }
     }
     // If a file is given, execute it and exit.
     else if let Some(file) = options.file.as_ref() {
-        execute_file(&mut fiber, file).await;
+        execute_file(&mut fiber, &mut signals, &scope, file).await;
     }
     // Interactive mode.
     else if atty::is(atty::Stream::Stdin) {
-        interactive_main(&mut fiber).await;
+        interactive_main(&mut fiber, &mut signals, &options, scope).await;
     }
     // Execute stdin
     else {
         log::trace!("stdin is not a tty");
-        execute_stdin(&mut fiber).await;
+        execute_stdin(&mut fiber, &mut signals, &scope).await;
     }
 
     // End this process with a particular exit code if specified.
@@ -118,7 +177,38 @@ async fn main() {
     }
 }
 
-async fn execute_file(fiber: &mut Fiber, path: impl AsRef<Path>) {
+/// Execute a script in the given scope, calling signal handlers while it runs.
+/// Errors are reported and set the exit code.
+///
+/// Returns whether the script finished successfully.
+async fn execute(fiber: &mut Fiber, signals: &mut Signals, scope: &Table, file: impl Into<SourceFile>) -> bool {
+    // Run the script in a fork, leaving the fiber free to call signal
+    // handlers.
+    let mut running = fiber.fork();
+
+    match signals.run(fiber, true, running.execute_in_scope(None, file, scope.clone())).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            log::error!("{}", e);
+            exit::set(1);
+            false
+        }
+        Err(signal) => {
+            stopped_by(signal);
+            false
+        }
+    }
+}
+
+/// Exit because of a signal that wasn't handled, unless a signal handler has
+/// already asked to exit.
+fn stopped_by(signal: c_int) {
+    if exit::get().is_none() {
+        exit::set(signals::exit_status(signal));
+    }
+}
+
+async fn execute_file(fiber: &mut Fiber, signals: &mut Signals, scope: &Table, path: impl AsRef<Path>) {
     let path = path.as_ref();
     let source = match SourceFile::open(path) {
         Ok(s) => s,
@@ -129,13 +219,10 @@ async fn execute_file(fiber: &mut Fiber, path: impl AsRef<Path>) {
         }
     };
 
-    if let Err(e) = fiber.execute(None, source).await {
-        log::error!("{}", e);
-        exit::set(1);
-    }
+    execute(fiber, signals, scope, source).await;
 }
 
-async fn execute_stdin(fiber: &mut Fiber) {
+async fn execute_stdin(fiber: &mut Fiber, signals: &mut Signals, scope: &Table) {
     let mut stdin = std::io::stdin();
     let mut source = String::new();
 
@@ -145,10 +232,7 @@ async fn execute_stdin(fiber: &mut Fiber) {
         return;
     }
 
-    if let Err(e) = fiber.execute(None, SourceFile::named("<stdin>", source)).await {
-        log::error!("{}", e);
-        exit::set(1);
-    }
+    execute(fiber, signals, scope, SourceFile::named("<stdin>", source)).await;
 }
 
 /// Main loop for an interactive shell session.
@@ -156,25 +240,221 @@ async fn execute_stdin(fiber: &mut Fiber) {
 /// It is also worth noting that this function is infallible. Once set up, the
 /// shell ensures that it stays alive until the user actually requests it to
 /// exit.
-async fn interactive_main(fiber: &mut Fiber) {
-    // We want successive commands to act like they are being executed in the
-    // same file, so set up a shared scope to execute them in.
-    let scope = table!();
+///
+/// Commands are executed in the given scope, so that successive commands act
+/// like they are being executed in the same file.
+///
+/// An interrupt cancels the command line being run, while other signals end
+/// the session unless they are handled.
+async fn interactive_main(fiber: &mut Fiber, signals: &mut Signals, options: &Options, scope: Table) {
+    // External commands get process groups of their own, which are given the
+    // terminal while they run.
+    match ProcessGroup::foreground() {
+        Ok(group) => fiber.process_group = Some(Rc::new(group)),
+        Err(e) => log::warn!("can't ignore SIGTTOU, process groups are disabled: {}", e),
+    }
+
+    let history = open_history(options);
+    builtins::register(fiber, &history);
+    plugins::load_all(fiber).await;
+
+    // Init scripts run after plugins are loaded, so that they can change what
+    // the plugins set up.
+    for path in init_files(options) {
+        source_startup_file(fiber, &path, &scope).await;
+    }
 
     let mut editor = Editor::new(
         fiber.stdin().unwrap().try_clone().unwrap(),
         fiber.stdout().unwrap().try_clone().unwrap(),
+        fiber.fork(),
+        history,
+        Completer::new(fiber.fork(), scope.clone()),
+        Highlighter::new(fiber.globals().clone(), scope.clone()),
     );
 
     while exit::get().is_none() {
-        let line = editor.read_line().await;
+        let line = match signals.run(fiber, false, editor.read_line()).await {
+            Ok(line) => line,
+            Err(signal) => {
+                editor.close().await;
+                stopped_by(signal);
+                break;
+            }
+        };
 
         if !line.is_empty() {
-            match fiber.execute_in_scope(Some("main"), SourceFile::named("<input>", line), scope.clone()).await {
-                Ok(Value::Nil) => {}
-                Ok(value) => println!("{}", value),
-                Err(e) => log::error!("{}", e),
+            let entry = match line.trim() {
+                "" => None,
+                _ => Some(editor.history().add(line.as_str())),
+            };
+
+            plugins::run_hook(fiber, plugins::ON_COMMAND, &[Value::from(line.as_str())]).await;
+
+            // Run the command line in a fork, so that cancelling it leaves
+            // nothing behind in the fiber.
+            let mut running = fiber.fork();
+            let file = SourceFile::named("<input>", line);
+
+            let started = Instant::now();
+            let outcome = signals.run(fiber, true, running.execute_in_scope(Some("main"), file, scope.clone())).await;
+            let status = match &outcome {
+                Ok(result) => exit_status(result),
+                Err(signal) => signals::exit_status(*signal),
+            };
+            let duration = started.elapsed();
+
+            if let Some(entry) = entry {
+                editor.history().finish(entry, status, duration);
+            }
+
+            editor.command_finished(status, duration);
+
+            match outcome {
+                Ok(Ok(Value::Nil)) => {}
+                Ok(Ok(value)) => println!("{}", value),
+                Ok(Err(e)) => log::error!("{}", e),
+                Err(libc::SIGINT) => println!(),
+                Err(signal) => stopped_by(signal),
+            }
+        }
+    }
+}
+
+/// Get the exit status to record in history for the result of a command line.
+///
+/// External commands return their exit code, so a numeric result is taken as
+/// the status. For a pipeline, this is the result of the last command. Command
+/// lines that raise an exception have a status of 1, and all others 0.
+fn exit_status(result: &Result<Value, Exception>) -> i32 {
+    match result {
+        Ok(Value::Number(number)) => *number as i32,
+        Ok(Value::List(items)) => items.last()
+            .map(|item| exit_status(&Ok(item.clone())))
+            .unwrap_or(0),
+        Ok(_) => 0,
+        Err(_) => 1,
+    }
+}
+
+/// Get the paths of the system-wide and the user's startup script with the
+/// given name that exist, in the order they should run.
+fn startup_files(name: &str) -> Vec<PathBuf> {
+    let user_dir = ProjectDirs::from("", "", "Riptide").map(|dirs| dirs.config_dir().to_owned());
+
+    Some(PathBuf::from(SYSTEM_CONFIG_DIR)).into_iter()
+        .chain(user_dir)
+        .map(|dir| dir.join(name))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Get the paths of the init scripts to run in an interactive session, in the
+/// order they should run.
+fn init_files(options: &Options) -> Vec<PathBuf> {
+    match (options.norc, options.rcfile.as_ref()) {
+        (true, _) => Vec::new(),
+        (false, Some(rcfile)) => vec![rcfile.clone()],
+        (false, None) => startup_files(INIT_FILE),
+    }
+}
+
+/// Run a startup script. Errors are reported, but don't stop the shell from
+/// starting.
+async fn source_startup_file(fiber: &mut Fiber, path: &Path, scope: &Table) {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            log::error!("{}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    // The file is named by its full path so that errors say where they are.
+    let file = SourceFile::named(path.display().to_string(), source);
+
+    if let Err(e) = fiber.execute_in_scope(None, file, scope.clone()).await {
+        log::error!("{}", startup_error(path, &e));
+    }
+}
+
+/// Describe an error raised by a startup script, with the position of the
+/// statement that raised it. Parse errors already include their position.
+fn startup_error(path: &Path, exception: &Exception) -> String {
+    match exception.position() {
+        Some(position) => format!("{}:{}: {}", path.display(), position, exception),
+        None => format!("in {}: {}", path.display(), exception),
+    }
+}
+
+/// Open the command history for an interactive session.
+fn open_history(options: &Options) -> History {
+    // Private sessions never touch the history file, except to read from it if
+    // requested.
+    if options.private {
+        let history = History::in_memory().unwrap();
+
+        if options.import_history {
+            if let Err(e) = History::default_path().and_then(|path| history.import(path)) {
+                log::warn!("unable to import history: {}", e);
             }
         }
+
+        return history;
+    }
+
+    match History::open_default() {
+        Ok(history) => history,
+        Err(e) => {
+            log::warn!("unable to open history, history will not be saved: {}", e);
+            History::in_memory().unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::testing::{block_on, fiber};
+    use std::fs;
+
+    #[test]
+    fn init_files_can_be_replaced_or_skipped() {
+        let options = Options::from_iter(&["riptide", "--norc"]);
+        assert!(init_files(&options).is_empty());
+
+        let options = Options::from_iter(&["riptide", "--rcfile", "/tmp/rc.rip"]);
+        assert_eq!(init_files(&options), vec![PathBuf::from("/tmp/rc.rip")]);
+
+        assert!(Options::from_iter_safe(&["riptide", "--norc", "--rcfile", "/tmp/rc.rip"]).is_err());
+    }
+
+    #[test]
+    fn errors_in_startup_files_are_not_fatal() {
+        let dir = tempfile::tempdir().unwrap();
+        let broken = dir.path().join("broken.rip");
+        let working = dir.path().join("working.rip");
+        fs::write(&broken, "def before 1\nthrow broken\ndef after 1").unwrap();
+        fs::write(&working, "def working 1").unwrap();
+
+        let scope = table!();
+
+        block_on(async {
+            let mut fiber = fiber();
+            source_startup_file(&mut fiber, &broken, &scope).await;
+            source_startup_file(&mut fiber, &working, &scope).await;
+        });
+
+        assert!(!scope.get("before").is_nil());
+        assert!(scope.get("after").is_nil());
+        assert!(!scope.get("working").is_nil());
+    }
+
+    #[test]
+    fn startup_errors_are_reported_with_their_position() {
+        let path = Path::new("/etc/riptide/init.rip");
+
+        let exception = block_on(fiber().execute(None, "println ok\n  throw broken")).unwrap_err();
+        assert_eq!(startup_error(path, &exception), "/etc/riptide/init.rip:2:3: broken");
     }
 }
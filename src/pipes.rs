@@ -1,12 +1,16 @@
 //! File descriptor and pipe utilities.
 
-use nix::{fcntl::OFlag, unistd::pipe2};
+use nix::{
+    fcntl::{fcntl, FcntlArg, FdFlag, OFlag},
+    unistd::pipe2,
+};
 use std::{
     fmt,
     fs::File,
     io,
     os::unix::io::{AsRawFd, FromRawFd, RawFd},
     pin::Pin,
+    process::Stdio,
     task::{Context, Poll},
 };
 use tokio::io::{
@@ -30,13 +34,7 @@ pub fn stderr() -> PipeWriter {
 /// Open a new pipe and return a reader/writer pair.
 pub fn pipe() -> io::Result<(PipeReader, PipeWriter)> {
     pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)
-        .map_err(|e| {
-            if let nix::Error::Sys(err_no) = e {
-                io::Error::from(err_no)
-            } else {
-                panic!("unexpected nix error type: {:?}", e)
-            }
-        })
+        .map_err(nix_error)
         .map(|(read_fd, write_fd)| unsafe {
             (
                 PipeReader::from_raw_fd(read_fd),
@@ -45,6 +43,61 @@ pub fn pipe() -> io::Result<(PipeReader, PipeWriter)> {
         })
 }
 
+/// Duplicate a file descriptor so that it can be given to a child process as
+/// one of its standard streams.
+///
+/// The blocking mode of a descriptor is shared with all of its duplicates, and
+/// most programs misbehave if their standard streams are non-blocking. If the
+/// given descriptor is non-blocking then `None` is returned, and the caller
+/// must instead relay data to and from the child through a separate pipe.
+pub fn child_stdio(fd: &impl AsRawFd) -> io::Result<Option<Stdio>> {
+    let flags = fcntl(fd.as_raw_fd(), FcntlArg::F_GETFL).map_err(nix_error)?;
+
+    if OFlag::from_bits_truncate(flags).contains(OFlag::O_NONBLOCK) {
+        return Ok(None);
+    }
+
+    let new_fd = fcntl(fd.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(0)).map_err(nix_error)?;
+
+    Ok(Some(unsafe {
+        Stdio::from_raw_fd(new_fd)
+    }))
+}
+
+/// Allow a file descriptor to be inherited by programs executed afterward.
+///
+/// This only performs a single system call, so it is safe to call in a child
+/// process between forking and executing.
+pub fn set_inheritable(fd: RawFd) -> io::Result<()> {
+    fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))
+        .map(drop)
+        .map_err(nix_error)
+}
+
+/// Copy the contents of a file into a pipe.
+///
+/// Regular files cannot be polled for readiness, so data is relayed through a
+/// pipe instead when a file is used as a stream. The file is read on a blocking
+/// thread, so that a file that is slow to read, such as a FIFO or a terminal,
+/// doesn't hold anything else up.
+pub async fn copy_from_file(mut file: tokio::fs::File, mut writer: PipeWriter) -> io::Result<()> {
+    tokio::io::copy(&mut file, &mut writer).await.map(drop)
+}
+
+/// Copy everything read from a pipe into a file until the pipe is closed. The
+/// file is written on a blocking thread.
+pub async fn copy_to_file(mut reader: PipeReader, mut file: tokio::fs::File) -> io::Result<()> {
+    tokio::io::copy(&mut reader, &mut file).await.map(drop)
+}
+
+pub(crate) fn nix_error(error: nix::Error) -> io::Error {
+    if let nix::Error::Sys(err_no) = error {
+        io::Error::from(err_no)
+    } else {
+        panic!("unexpected nix error type: {:?}", error)
+    }
+}
+
 /// Reading end of an asynchronous pipe.
 #[derive(Debug)]
 pub struct PipeReader(PollEvented<EventedFd>);
@@ -56,6 +109,18 @@ impl PipeReader {
             .and_then(PollEvented::new)
             .map(PipeReader)
     }
+
+    /// Convert this reader into a blocking file, so that it can be handed to a
+    /// child process.
+    pub fn into_blocking(self) -> io::Result<File> {
+        let file = self.0.into_inner()?.0;
+        let flags = fcntl(file.as_raw_fd(), FcntlArg::F_GETFL).map_err(nix_error)?;
+
+        fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(OFlag::from_bits_truncate(flags) - OFlag::O_NONBLOCK))
+            .map_err(nix_error)?;
+
+        Ok(file)
+    }
 }
 
 impl FromRawFd for PipeReader {
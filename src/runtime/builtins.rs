@@ -64,7 +64,14 @@ async fn export(fiber: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
     let value = args.get(1).cloned()
         .unwrap_or(fiber.get(&name));
 
-    fiber.current_scope().unwrap().module.set(name, value);
+    // The current scope is the scope of this function, so export from the
+    // module of the scope that called it.
+    match fiber.backtrace().nth(1) {
+        Some(scope) => {
+            scope.module.set(name, value);
+        }
+        None => throw!("export must be called from a module"),
+    }
 
     Ok(Value::Nil)
 }
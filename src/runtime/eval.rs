@@ -5,21 +5,36 @@ use super::{
     exceptions::Exception,
     fiber::Fiber,
     foreign::ForeignFn,
+    glob,
     scope::Scope,
+    string::RipString,
     syntax,
     syntax::ast::*,
     syntax::source::*,
     table::Table,
     value::*,
 };
+use crate::pipes;
 use futures::{
     future::{
         FutureExt,
         LocalBoxFuture,
+        join,
+        join_all,
+        select,
         try_join_all,
+        Either,
     },
 };
-use std::rc::Rc;
+use std::{
+    cell::Cell,
+    io,
+    mem,
+    os::unix::{ffi::OsStringExt, io::AsRawFd},
+    rc::Rc,
+};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 /// Compile the given source code as a closure.
 pub(crate) fn compile(fiber: &mut Fiber, file: impl Into<SourceFile>, scope: Option<Table>) -> Result<Closure, Exception> {
@@ -55,11 +70,17 @@ pub(crate) async fn invoke(fiber: &mut Fiber, value: &Value, args: &[Value]) ->
 
 /// Invoke a block with an array of arguments.
 pub(crate) async fn invoke_closure(fiber: &mut Fiber, closure: &Closure, args: &[Value]) -> Result<Value, Exception> {
+    invoke_closure_with_bindings(fiber, closure, args, Table::default()).await
+}
+
+/// Invoke a block with an array of arguments, keeping the variables it defines
+/// in the given table.
+pub(crate) async fn invoke_closure_with_bindings(fiber: &mut Fiber, closure: &Closure, args: &[Value], bindings: Table) -> Result<Value, Exception> {
+    bindings.set("args", args.to_vec());
+
     let scope = Scope {
         name: Some(String::from("<closure>")),
-        bindings: table! {
-            "args" => args.to_vec(),
-        },
+        bindings,
         module: closure.scope.module.clone(),
         parent: Some(closure.scope.clone()),
     };
@@ -75,10 +96,14 @@ pub(crate) async fn invoke_closure(fiber: &mut Fiber, closure: &Closure, args: &
     let mut last_return_value = Value::Nil;
 
     // Evaluate each statement in order.
-    for statement in closure.block.statements.iter() {
+    for (i, statement) in closure.block.statements.iter().enumerate() {
         match evaluate_pipeline(fiber, &statement).await {
             Ok(return_value) => last_return_value = return_value,
-            Err(exception) => {
+            Err(mut exception) => {
+                if let Some(position) = closure.block.positions.get(i) {
+                    exception.set_position(*position);
+                }
+
                 // Exception thrown; abort and unwind stack.
                 fiber.stack.pop();
                 return Err(exception);
@@ -113,10 +138,29 @@ async fn evaluate_pipeline(fiber: &mut Fiber, pipeline: &Pipeline) -> Result<Val
         evaluate_call(fiber, pipeline.0[0].clone()).await
     } else {
         let mut futures = Vec::new();
+        let mut next_stdin = None;
+
+        // The commands of the pipeline share a process group of their own.
+        let process_group = fiber.process_group.as_ref().map(|group| Rc::new(group.new_pipeline()));
 
-        for call in pipeline.0.iter() {
+        for (i, call) in pipeline.0.iter().enumerate() {
             let mut fiber = fiber.fork();
+            fiber.process_group = process_group.clone();
+
+            // Connect the output of each call to the input of the next.
+            if let Some(stdin) = next_stdin.take() {
+                fiber.stdin = Some(stdin);
+            }
+
+            if i + 1 < pipeline.0.len() {
+                let (reader, writer) = pipes::pipe()?;
+                fiber.stdout = Some(writer);
+                next_stdin = Some(reader);
+            }
+
             futures.push(async move {
+                // The fiber is dropped once the call returns, closing its end
+                // of any pipes.
                 evaluate_call(&mut fiber, call.clone()).await
             });
         }
@@ -127,46 +171,353 @@ async fn evaluate_pipeline(fiber: &mut Fiber, pipeline: &Pipeline) -> Result<Val
     }
 }
 
+/// Evaluate a pipeline, capturing everything it writes to standard output.
+///
+/// Returns the pipeline's return value along with its captured output, and
+/// whether it started any external commands.
+async fn capture_pipeline(fiber: &mut Fiber, pipeline: &Pipeline) -> Result<(Value, Vec<u8>, bool), Exception> {
+    let (mut reader, writer) = pipes::pipe()?;
+
+    let mut fiber = fiber.fork();
+    fiber.stdout = Some(writer);
+
+    // Only count the commands started by the pipeline itself.
+    let commands_started = Rc::new(Cell::new(0));
+    fiber.commands_started = commands_started.clone();
+
+    let evaluate = async move {
+        evaluate_pipeline(&mut fiber, pipeline).await
+    };
+
+    let mut output = Vec::new();
+    let (result, read_result) = join(evaluate, reader.read_to_end(&mut output)).await;
+
+    let value = result?;
+    read_result?;
+
+    Ok((value, output, commands_started.get() > 0))
+}
+
+/// Invoke a function with its standard output captured, and return the result
+/// the same as a pipeline substitution.
+pub(crate) async fn invoke_captured(fiber: &mut Fiber, value: &Value, args: &[Value]) -> Result<Value, Exception> {
+    let (mut reader, writer) = pipes::pipe()?;
+
+    let mut fiber = fiber.fork();
+    fiber.stdout = Some(writer);
+
+    let commands_started = Rc::new(Cell::new(0));
+    fiber.commands_started = commands_started.clone();
+
+    let invoke = async move {
+        invoke(&mut fiber, value, args).await
+    };
+
+    let mut output = Vec::new();
+    let (result, read_result) = join(invoke, reader.read_to_end(&mut output)).await;
+
+    let value = result?;
+    read_result?;
+
+    Ok(captured_value(value, output, commands_started.get() > 0))
+}
+
+/// Get the result of a substitution from the value and output it captured.
+fn captured_value(value: Value, mut output: Vec<u8>, started_commands: bool) -> Value {
+    // The output is the result of anything that runs an external command,
+    // even if it is empty, since the return value would be an exit status.
+    // Otherwise functions that write nothing can return a value instead.
+    if output.is_empty() && !started_commands {
+        value
+    } else {
+        while output.last() == Some(&b'\n') {
+            output.pop();
+            if output.last() == Some(&b'\r') {
+                output.pop();
+            }
+        }
+
+        Value::from(RipString::from(output))
+    }
+}
+
 fn evaluate_call(fiber: &mut Fiber, call: Call) -> LocalBoxFuture<Result<Value, Exception>> {
     async move {
-        let (function, args) = match call {
-            Call::Named {function, args} => (fiber.get(function), args),
-            Call::Unnamed {function, args} => (
-                {
-                    let mut function = evaluate_expr(fiber, *function).await?;
-
-                    // If the function is a string, resolve binding names first before we try to eval the item as a function.
-                    if let Some(value) = function.as_string().map(|name| fiber.get(name)) {
-                        function = value;
-                    }
-
-                    function
-                },
-                args,
-            ),
+        let (name, function, args, redirections) = match call {
+            Call::Named {function, args, redirections} => {
+                let value = fiber.get(&function);
+                (Some(function), value, args, redirections)
+            }
+            Call::Unnamed {function, args, redirections} => {
+                let mut function = evaluate_expr(fiber, *function).await?;
+                let mut name = None;
+
+                // If the function is a string, resolve binding names first before we try to eval the item as a function.
+                if let Some(string) = function.as_string().cloned() {
+                    function = fiber.get(&string);
+                    name = string.as_utf8().map(String::from);
+                }
+
+                (name, function, args, redirections)
+            }
+        };
+
+        let mut arg_values = Vec::with_capacity(args.len() + 1);
+
+        let (function, external) = match (function, name) {
+            // Names not bound to anything are executed as external commands,
+            // using the global `command` so that local variables can't get in
+            // the way.
+            (Value::Nil, Some(name)) => {
+                arg_values.push(Value::from(name));
+                (fiber.globals().get("command"), true)
+            }
+            (function, _) => (function, false),
         };
 
-        let mut arg_values = Vec::with_capacity(args.len());
+        let mut substitutions = Vec::new();
+        let mut substitution_pipes = Vec::new();
+
         for expr in args {
-            arg_values.push(evaluate_expr(fiber, expr).await?);
+            arg_values.push(match expr {
+                Expr::ProcessSubstitution(ProcessSubstitution(pipeline)) => {
+                    let (reader, writer) = pipes::pipe()?;
+                    let reader = reader.into_blocking()?;
+
+                    let mut fiber = fiber.fork();
+                    fiber.stdout = Some(writer);
+
+                    let path = format!("/dev/fd/{}", reader.as_raw_fd());
+                    substitution_pipes.push(reader);
+
+                    // In strict mode, a failed substituted pipeline fails the
+                    // call, unless the consumer just stopped reading early.
+                    substitutions.push(async move {
+                        match evaluate_pipeline(&mut fiber, &pipeline).await {
+                            Err(e) if fiber.is_strict() && !is_broken_pipe(&e) => Err(e),
+                            Err(e) => {
+                                log::warn!("in process substitution: {}", e);
+                                Ok(())
+                            }
+                            Ok(_) => Ok(()),
+                        }
+                    }.boxed_local());
+
+                    Value::from(path)
+                }
+                // Globs are only expanded into paths for external commands,
+                // since only they expect to receive lists of file names.
+                Expr::Glob(Glob(pattern)) if external => expand_glob(&pattern)?,
+                expr => evaluate_expr(fiber, expr).await?,
+            });
         }
 
-        invoke(fiber, &function, &arg_values).await
+        let call = async {
+            let inherited_fds = fiber.inherited_fds.len();
+            fiber.inherited_fds.extend(substitution_pipes.iter().map(AsRawFd::as_raw_fd));
+
+            let result = if redirections.is_empty() {
+                invoke(fiber, &function, &arg_values).await
+            } else {
+                invoke_redirected(fiber, &function, &arg_values, redirections).await
+            };
+
+            fiber.inherited_fds.truncate(inherited_fds);
+
+            // Close our copies of the substitution pipes so that substituted
+            // pipelines whose output is never read are not blocked forever.
+            drop(substitution_pipes);
+
+            result
+        };
+
+        // Process substitutions run alongside the call, since the call reads
+        // their output while they execute.
+        let (result, substituted) = join(call, join_all(substitutions)).await;
+        let result = result?;
+
+        for substitution in substituted {
+            substitution?;
+        }
+
+        Ok(result)
     }.boxed_local()
 }
 
+/// Check whether an exception was caused by writing to a pipe that had been
+/// closed by its reader.
+fn is_broken_pipe(exception: &Exception) -> bool {
+    let message = exception.message();
+
+    if message.get("type") == "command-failed" {
+        message.get("signal") == Value::from(nix::libc::SIGPIPE as f64)
+    } else {
+        message.as_string() == Some(&RipString::from(io::Error::from_raw_os_error(nix::libc::EPIPE).to_string()))
+    }
+}
+
+/// Expand a glob into a list of the paths it matches. An exception is raised if
+/// nothing matches, or if any of its brace alternatives with wildcards matches
+/// nothing, so that a mistyped alternative isn't silently left out.
+fn expand_glob(pattern: &str) -> Result<Value, Exception> {
+    let patterns = match glob::expand_patterns(pattern) {
+        Ok(patterns) => patterns,
+        Err(e) => throw!("invalid glob pattern {}: {}", pattern, e),
+    };
+
+    let mut paths = Vec::new();
+
+    for (pattern, matches) in patterns {
+        if matches.is_empty() {
+            throw!("no matches found: {}", pattern);
+        }
+
+        paths.extend(matches);
+    }
+
+    Ok(paths.into_iter().map(|path| RipString::from(path.into_os_string().into_vec())).collect())
+}
+
+/// Invoke a function with the standard streams of the fiber redirected for the
+/// duration of the call.
+async fn invoke_redirected(fiber: &mut Fiber, function: &Value, args: &[Value], redirections: Vec<Redirection>) -> Result<Value, Exception> {
+    // Evaluate all targets before touching any streams, so that targets are
+    // evaluated with the streams of the caller.
+    let mut targets = Vec::with_capacity(redirections.len());
+    for redirection in &redirections {
+        targets.push(match redirection.target() {
+            Some(expr) => Some(evaluate_expr(fiber, expr.clone()).await?.to_string()),
+            None => None,
+        });
+    }
+
+    let mut stdin = fiber.stdin.as_ref().map(pipes::PipeReader::try_clone).transpose()?;
+    let mut stdout = fiber.stdout.as_ref().map(pipes::PipeWriter::try_clone).transpose()?;
+    let mut stderr = fiber.stderr.as_ref().map(pipes::PipeWriter::try_clone).transpose()?;
+
+    // Files cannot be used as streams directly, so they are connected to the
+    // call with pipes and relays that copy data to and from them.
+    let mut input_relays = Vec::new();
+    let mut output_relays = Vec::new();
+
+    for (redirection, target) in redirections.iter().zip(targets) {
+        let path = target.unwrap_or_default();
+
+        match redirection {
+            Redirection::HereString(_) => {
+                stdin = Some(feed_input(path + "\n", &mut input_relays)?);
+            }
+            Redirection::HereDocument(document) => {
+                let text = evaluate_interpolated_string(fiber, document.clone()).await?.to_string();
+                stdin = Some(feed_input(text, &mut input_relays)?);
+            }
+            Redirection::ReadStdin(_) => {
+                let file = open_file(&path, OpenOptions::new().read(true)).await?;
+                let (reader, writer) = pipes::pipe()?;
+                input_relays.push(pipes::copy_from_file(file, writer).boxed_local());
+                stdin = Some(reader);
+            }
+            Redirection::WriteStdout(_) => {
+                stdout = Some(redirect_output(&path, false, &mut output_relays).await?);
+            }
+            Redirection::AppendStdout(_) => {
+                stdout = Some(redirect_output(&path, true, &mut output_relays).await?);
+            }
+            Redirection::WriteStderr(_) => {
+                stderr = Some(redirect_output(&path, false, &mut output_relays).await?);
+            }
+            Redirection::AppendStderr(_) => {
+                stderr = Some(redirect_output(&path, true, &mut output_relays).await?);
+            }
+            Redirection::StderrToStdout => {
+                stderr = stdout.as_ref().map(pipes::PipeWriter::try_clone).transpose()?;
+            }
+            Redirection::StdoutToStderr => {
+                stdout = stderr.as_ref().map(pipes::PipeWriter::try_clone).transpose()?;
+            }
+        }
+    }
+
+    let stdin = mem::replace(&mut fiber.stdin, stdin);
+    let stdout = mem::replace(&mut fiber.stdout, stdout);
+    let stderr = mem::replace(&mut fiber.stderr, stderr);
+
+    let call = async move {
+        let result = invoke(fiber, function, args).await;
+
+        // Restore the original streams. This closes our ends of the redirected
+        // streams, which lets the output relays finish.
+        fiber.stdin = stdin;
+        fiber.stdout = stdout;
+        fiber.stderr = stderr;
+
+        result
+    };
+
+    let outputs = join_all(output_relays).map(|results| {
+        results.into_iter().collect::<Result<Vec<()>, _>>()
+    });
+
+    let running = join(call, outputs).boxed_local();
+
+    // The call might not consume all of its input, so input relays are
+    // abandoned once the call returns.
+    let (result, output_result) = match select(join_all(input_relays), running).await {
+        Either::Left((_, running)) => running.await,
+        Either::Right((results, _)) => results,
+    };
+
+    let value = result?;
+    output_result?;
+
+    Ok(value)
+}
+
+/// Return a stream that reads the given text, adding a relay for the stream to
+/// the given list.
+fn feed_input(text: String, relays: &mut Vec<LocalBoxFuture<'static, io::Result<()>>>) -> Result<pipes::PipeReader, Exception> {
+    let (reader, mut writer) = pipes::pipe()?;
+
+    relays.push(async move {
+        writer.write_all(text.as_bytes()).await
+    }.boxed_local());
+
+    Ok(reader)
+}
+
+/// Open a file for writing and return a stream that writes to it, adding a
+/// relay for the stream to the given list.
+async fn redirect_output(path: &str, append: bool, relays: &mut Vec<LocalBoxFuture<'static, io::Result<()>>>) -> Result<pipes::PipeWriter, Exception> {
+    let file = open_file(path, OpenOptions::new().create(true).write(true).append(append).truncate(!append)).await?;
+    let (reader, writer) = pipes::pipe()?;
+    relays.push(pipes::copy_to_file(reader, file).boxed_local());
+
+    Ok(writer)
+}
+
+/// Open a file on a blocking thread, since opening some files, such as FIFOs,
+/// waits for another process.
+async fn open_file(path: &str, options: &OpenOptions) -> Result<File, Exception> {
+    match options.open(path).await {
+        Ok(file) => Ok(file),
+        Err(e) => throw!("{}: {}", path, e),
+    }
+}
+
 fn evaluate_expr(fiber: &mut Fiber, expr: Expr) -> LocalBoxFuture<Result<Value, Exception>> {
     async move {
         match expr {
             Expr::Number(number) => Ok(Value::Number(number)),
             Expr::String(string) => Ok(Value::from(string)),
             Expr::Substitution(substitution) => evaluate_substitution(fiber, substitution).await,
+            Expr::Glob(Glob(pattern)) => Ok(Value::from(glob::expand_tilde(&pattern))),
             Expr::Table(literal) => evaluate_table_literal(fiber, literal).await,
             Expr::List(list) => evaluate_list_literal(fiber, list).await,
             Expr::InterpolatedString(string) => evaluate_interpolated_string(fiber, string).await,
             Expr::MemberAccess(MemberAccess(lhs, rhs)) => evaluate_member_access(fiber, *lhs, rhs).await,
             Expr::Block(block) => evaluate_block(fiber, block),
             Expr::Pipeline(ref pipeline) => evaluate_pipeline(fiber, pipeline).await,
+            Expr::ProcessSubstitution(_) => throw!("process substitution is only allowed as a function argument"),
         }
     }.boxed_local()
 }
@@ -190,7 +541,11 @@ async fn evaluate_member_access(fiber: &mut Fiber, lhs: Expr, rhs: String) -> Re
 async fn evaluate_substitution(fiber: &mut Fiber, substitution: Substitution) -> Result<Value, Exception> {
     match substitution {
         Substitution::Variable(name) => Ok(fiber.get(name)),
-        Substitution::Pipeline(ref pipeline) => evaluate_pipeline(fiber, pipeline).await,
+        Substitution::Pipeline(ref pipeline) => {
+            let (value, output, started_commands) = capture_pipeline(fiber, pipeline).await?;
+
+            Ok(captured_value(value, output, started_commands))
+        }
         _ => unimplemented!(),
     }
 }
@@ -230,3 +585,258 @@ async fn evaluate_interpolated_string(fiber: &mut Fiber, string: InterpolatedStr
 
     Ok(Value::from(rendered))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::testing::{block_on, execute, fiber, run};
+    use std::fs;
+
+    /// Run a script in a new fiber in strict mode.
+    fn run_strict(source: &str) -> Result<Value, Exception> {
+        block_on(async {
+            let mut fiber = fiber();
+            fiber.set_strict(true);
+            execute(&mut fiber, source).await.0
+        })
+    }
+
+    #[test]
+    fn failed_commands_return_their_status() {
+        assert_eq!(run("false").0.unwrap(), Value::from(1.0));
+        assert_eq!(run("true").0.unwrap(), Value::from(0.0));
+    }
+
+    #[test]
+    fn unbound_names_are_run_as_external_commands() {
+        assert_eq!(run("echo hello").1, "hello\n");
+        assert_eq!(run("def command local; echo hello").1, "hello\n");
+    }
+
+    #[test]
+    fn failed_commands_raise_exceptions_in_strict_mode() {
+        let exception = run_strict("true; false --flag").unwrap_err();
+
+        assert_eq!(exception.message().get("type"), "command-failed");
+        assert_eq!(exception.message().get("command"), "false --flag");
+        assert_eq!(exception.message().get("status"), Value::from(1.0));
+        assert!(exception.message().get("signal").is_nil());
+
+        let exception = run_strict("sh -c 'kill -TERM $$'").unwrap_err();
+
+        assert!(exception.message().get("status").is_nil());
+        assert_eq!(exception.message().get("signal"), Value::from(nix::libc::SIGTERM as f64));
+    }
+
+    #[test]
+    fn any_failed_pipeline_stage_raises_an_exception_in_strict_mode() {
+        let exception = run_strict("false | true").unwrap_err();
+        assert_eq!(exception.message().get("command"), "false");
+
+        assert!(run_strict("true | true").is_ok());
+    }
+
+    #[test]
+    fn strict_mode_can_be_set_for_a_block() {
+        let source = "def process (require process); $process->strict true { false }";
+        assert_eq!(run(source).0.unwrap_err().message().get("type"), "command-failed");
+
+        // The previous setting is restored after the block.
+        let source = "def process (require process); $process->strict false { false }";
+        assert_eq!(run_strict(source).unwrap(), Value::from(1.0));
+
+        let source = "def process (require process); $process->strict false { true }; false";
+        assert!(run_strict(source).is_err());
+    }
+
+    #[test]
+    fn substitutions_capture_output() {
+        assert_eq!(run("println $(printf 'hello')").1, "hello\n");
+        assert_eq!(run("println \"<$(echo hello)>\"").1, "<hello>\n");
+        assert_eq!(run("println $(printf 'a b' | tr ' ' -)").1, "a-b\n");
+    }
+
+    #[test]
+    fn substitutions_trim_trailing_newlines() {
+        assert_eq!(run("println \"<$(printf 'one\\ntwo\\n\\n')>\"").1, "<one\ntwo>\n");
+        assert_eq!(run("println \"<$(printf 'line\\r\\n')>\"").1, "<line>\n");
+        assert_eq!(run("println \"<$(printf '\\n  \\n')>\"").1, "<\n  >\n");
+    }
+
+    #[test]
+    fn substitutions_of_commands_that_write_nothing_are_empty() {
+        assert_eq!(run("println \"<$(false)>\"").1, "<>\n");
+        assert_eq!(run("println \"<$(true | true)>\"").1, "<>\n");
+        assert_eq!(run("println \"<$(grep nothing /dev/null)>\"").1, "<>\n");
+        assert_eq!(run("println \"<$(call { true })>\"").1, "<>\n");
+    }
+
+    #[test]
+    fn substitutions_of_functions_that_write_nothing_are_their_return_value() {
+        assert_eq!(run("println $(typeof 1)").1, "number\n");
+    }
+
+    #[test]
+    fn captured_output_can_be_split_into_lines() {
+        let source = "def string (require string); $string->lines $(printf 'a\\nb\\r\\nc\\n\\n')";
+        let lines = Value::from(vec![Value::from("a"), Value::from("b"), Value::from("c")]);
+
+        assert_eq!(run(source).0.unwrap(), lines);
+        assert_eq!(run("def string (require string); $string->lines $(true)").0.unwrap(), Value::List(Vec::new()));
+    }
+
+    #[test]
+    fn output_can_be_redirected_to_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+
+        let source = format!("println one > '{0}'; println two >> '{0}'; printf three >> '{0}'", path.display());
+        assert_eq!(run(&source).1, "");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\nthree");
+
+        assert!(run(&format!("printf four > '{}'", path.display())).0.is_ok());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "four");
+
+        assert_eq!(run("println hidden > /dev/null; println shown").1, "shown\n");
+    }
+
+    #[test]
+    fn errors_can_be_redirected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("errors.txt");
+
+        assert_eq!(run("sh -c 'echo out; echo err >&2' 2>&1").1, "out\nerr\n");
+
+        let source = format!("sh -c 'echo out; echo err >&2' 2> '{}'", path.display());
+        assert_eq!(run(&source).1, "out\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "err\n");
+
+        // Redirections are applied in order.
+        let source = format!("sh -c 'echo out; echo err >&2' 2>&1 > '{}'", path.display());
+        assert_eq!(run(&source).1, "err\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "out\n");
+    }
+
+    #[test]
+    fn input_can_be_read_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("in.txt");
+        fs::write(&path, "one\ntwo\n").unwrap();
+
+        assert_eq!(run(&format!("cat < '{}'", path.display())).1, "one\ntwo\n");
+        assert_eq!(run(&format!("tr a-z A-Z < '{}' | head -n 1", path.display())).1, "ONE\n");
+        assert!(run("cat < /no/such/file").0.is_err());
+    }
+
+    #[test]
+    fn waiting_on_redirected_files_does_not_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fifo");
+        nix::unistd::mkfifo(&path, nix::sys::stat::Mode::S_IRUSR | nix::sys::stat::Mode::S_IWUSR).unwrap();
+
+        // Each side of a FIFO waits for the other to open it.
+        let source = format!("println hello > '{0}' | cat < '{0}'", path.display());
+        assert_eq!(run(&source).1, "hello\n");
+    }
+
+    #[test]
+    fn here_strings_are_fed_to_standard_input() {
+        assert_eq!(run("cat <<< hello").1, "hello\n");
+        assert_eq!(run("def name world; tr a-z A-Z <<< \"hello $name\"").1, "HELLO WORLD\n");
+    }
+
+    #[test]
+    fn here_documents_are_fed_to_standard_input() {
+        let source = "def name world\ncat <<EOF\nHello, $name\n  $(printf indented)\nEOF\nprintln done";
+        assert_eq!(run(source).1, "Hello, world\n  indented\ndone\n");
+
+        let source = "def name world\ncat <<'EOF'\nHello, $name\nEOF";
+        assert_eq!(run(source).1, "Hello, $name\n");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+
+        let source = format!("cat <<EOF > '{}'\nsaved\nEOF", path.display());
+        assert_eq!(run(&source).1, "");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "saved\n");
+    }
+
+    #[test]
+    fn process_substitutions_are_passed_as_paths() {
+        assert_eq!(run("cat <(println hello)").1, "hello\n");
+        assert_eq!(run("paste <(printf 'a\\nb\\n') <(printf '1\\n2\\n')").1, "a\t1\nb\t2\n");
+
+        let (result, output) = run("diff <(printf 'a\\n') <(printf 'b\\n')");
+        assert_eq!(result.unwrap(), Value::from(1.0));
+        assert!(output.contains("< a\n") && output.contains("> b\n"));
+    }
+
+    #[test]
+    fn process_substitutions_that_are_not_read_do_not_block() {
+        assert_eq!(run("true <(yes)").0.unwrap(), Value::from(0.0));
+        assert!(run("println <(println hello)").1.starts_with("/dev/fd/"));
+    }
+
+    #[test]
+    fn failed_process_substitutions_raise_exceptions_in_strict_mode() {
+        assert_eq!(run("cat <(false)").0.unwrap(), Value::from(0.0));
+
+        let exception = run_strict("cat <(false)").unwrap_err();
+        assert_eq!(exception.message().get("command"), "false");
+
+        // Substitutions cut off by a consumer that stops reading still succeed.
+        assert!(run_strict("head -c 1 <(yes)").is_ok());
+        assert!(run_strict("true <(println hello)").is_ok());
+    }
+
+    #[test]
+    fn globs_are_expanded_for_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in &["a1.txt", "a2.txt", "b.txt", "b.md"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+
+        let source = format!("basename -a {}/a?.txt", dir.path().display());
+        assert_eq!(run(&source).1, "a1.txt\na2.txt\n");
+
+        let source = format!("basename -a {0}/b.{{md,txt}} {0}/*.md", dir.path().display());
+        assert_eq!(run(&source).1, "b.md\nb.txt\nb.md\n");
+
+        // Globs are passed to functions as they are.
+        assert_eq!(run("println a?.txt").1, "a?.txt\n");
+    }
+
+    #[test]
+    fn globs_that_match_nothing_raise_exceptions() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+
+        let path = dir.path().display();
+
+        let (result, _) = run(&format!("basename -a {}/*.md", path));
+        assert_eq!(result.unwrap_err().to_string(), format!("no matches found: {}/*.md", path));
+
+        // Each alternative with wildcards must match something.
+        let (result, output) = run(&format!("basename -a {}/{{*.txt,*.md}}", path));
+        assert_eq!(result.unwrap_err().to_string(), format!("no matches found: {}/*.md", path));
+        assert_eq!(output, "");
+
+        // Alternatives without wildcards are passed through.
+        assert_eq!(run(&format!("basename -a {}/{{*.txt,new.md}}", path)).1, "a.txt\nnew.md\n");
+    }
+
+    #[test]
+    fn unchecked_commands_may_fail_in_strict_mode() {
+        let source = "def process (require process); $process->unchecked false";
+        assert_eq!(run_strict(source).unwrap(), Value::from(1.0));
+    }
+
+    #[test]
+    fn exceptions_record_the_statement_of_the_script_they_came_from() {
+        let (result, _) = run("println a\n  throw broken");
+        assert_eq!(result.unwrap_err().position().unwrap().to_string(), "2:3");
+
+        let (result, _) = run("def f {\n    throw broken\n}\nf");
+        assert_eq!(result.unwrap_err().position().unwrap().to_string(), "4:1");
+    }
+}
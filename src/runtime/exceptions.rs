@@ -1,3 +1,4 @@
+use super::syntax::ast::Position;
 use super::value::Value;
 use std::fmt;
 use std::io;
@@ -10,6 +11,10 @@ use std::io;
 pub struct Exception {
     message: Value,
     cause: Option<Box<Exception>>,
+
+    /// Where the statement that raised the exception starts in the script
+    /// being executed.
+    position: Option<Position>,
 }
 
 impl Exception {
@@ -20,6 +25,7 @@ impl Exception {
         Self {
             message: message.into(),
             cause: None,
+            position: None,
         }
     }
 
@@ -31,6 +37,7 @@ impl Exception {
         Self {
             message: message.into(),
             cause: Some(Box::new(cause)),
+            position: None,
         }
     }
 
@@ -40,6 +47,20 @@ impl Exception {
         &self.message
     }
 
+    /// Get where the top-level statement that raised the exception starts in
+    /// its script, if known.
+    #[inline]
+    pub fn position(&self) -> Option<Position> {
+        self.position
+    }
+
+    /// Record the statement the exception passed through. Statements further
+    /// out replace inner ones, so that the position ends up being in the
+    /// script that was executed.
+    pub(crate) fn set_position(&mut self, position: Position) {
+        self.position = Some(position);
+    }
+
     /// Get the cause of the exception, if present.
     #[inline]
     pub fn cause(&self) -> Option<&Exception> {
@@ -87,14 +108,24 @@ impl fmt::Debug for Exception {
 
 impl fmt::Display for Exception {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.message)?;
+        write_message(f, &self.message)?;
 
         let mut cause = self.cause.as_ref();
         while let Some(c) = cause {
-            write!(f, "caused by: {}", c.message)?;
+            write!(f, "caused by: ")?;
+            write_message(f, &c.message)?;
             cause = c.cause.as_ref();
         }
 
         Ok(())
     }
 }
+
+/// Write an exception message. Exceptions thrown with a table as their value
+/// are displayed using the table's `message` field, if it has one.
+fn write_message(f: &mut fmt::Formatter, message: &Value) -> fmt::Result {
+    match message.get("message") {
+        Value::Nil => write!(f, "{}", message),
+        text => write!(f, "{}", text),
+    }
+}
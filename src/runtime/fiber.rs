@@ -1,3 +1,4 @@
+use crate::jobs::ProcessGroup;
 use crate::pipes::{
     PipeReader,
     PipeWriter,
@@ -19,7 +20,9 @@ use super::{
 };
 use futures::executor::block_on;
 use std::{
+    cell::Cell,
     env,
+    os::unix::io::RawFd,
     rc::Rc,
     time::Instant,
 };
@@ -45,18 +48,46 @@ pub struct Fiber {
 
     /// Standard error stream for this fiber.
     pub(crate) stderr: Option<PipeWriter>,
+
+    /// Whether external commands that exit unsuccessfully raise an exception.
+    strict: bool,
+
+    /// Additional file descriptors that child processes spawned by this fiber
+    /// inherit, such as the pipes of process substitutions.
+    pub(crate) inherited_fds: Vec<RawFd>,
+
+    /// Number of external commands started by this fiber and the fibers forked
+    /// from it.
+    pub(crate) commands_started: Rc<Cell<usize>>,
+
+    /// The process group external commands started by this fiber join, shared
+    /// with the other calls of the pipeline it is running. Commands stay in the
+    /// shell's process group if there is none.
+    pub(crate) process_group: Option<Rc<ProcessGroup>>,
 }
 
 impl Default for Fiber {
     fn default() -> Self {
+        Self::with_stdio(Some(stdin()), Some(stdout()), Some(stderr()))
+    }
+}
+
+impl Fiber {
+    /// Create a new runtime with a fiber that uses the given standard streams,
+    /// rather than those of the process.
+    pub(crate) fn with_stdio(stdin: Option<PipeReader>, stdout: Option<PipeWriter>, stderr: Option<PipeWriter>) -> Self {
         let start_time = Instant::now();
 
         let mut fiber = Self {
             globals: Default::default(),
             stack: Vec::new(),
-            stdin: Some(stdin()),
-            stdout: Some(stdout()),
-            stderr: Some(stderr()),
+            stdin,
+            stdout,
+            stderr,
+            strict: false,
+            inherited_fds: Vec::new(),
+            commands_started: Default::default(),
+            process_group: Default::default(),
         };
 
         // Set up globals
@@ -81,9 +112,7 @@ impl Default for Fiber {
 
         fiber
     }
-}
 
-impl Fiber {
     /// Get the table that holds all global variables.
     pub fn globals(&self) -> &Table {
         &self.globals
@@ -111,9 +140,27 @@ impl Fiber {
             stdin: self.stdin.as_ref().map(|p| p.try_clone().unwrap()),
             stdout: self.stdout.as_ref().map(|p| p.try_clone().unwrap()),
             stderr: self.stderr.as_ref().map(|p| p.try_clone().unwrap()),
+            strict: self.strict,
+            inherited_fds: self.inherited_fds.clone(),
+            commands_started: self.commands_started.clone(),
+            process_group: self.process_group.clone(),
         }
     }
 
+    /// Check if this fiber is running in strict mode.
+    ///
+    /// In strict mode, an external command that exits with a non-zero status
+    /// raises a `command-failed` exception instead of returning its status.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Enable or disable strict mode for this fiber. Fibers forked from this
+    /// one afterward inherit the setting.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
     /// Get the current exit code for the runtime. If no exit has been
     /// requested, then `None` will be returned.
     ///
@@ -178,9 +225,11 @@ impl Fiber {
     ///
     /// If a compilation error occurs with the given file, an exception will be returned.
     pub async fn execute_in_scope(&mut self, _module: Option<&str>, file: impl Into<SourceFile>, scope: Table) -> Result<Value, Exception> {
-        let closure = eval::compile(self, file, Some(scope))?;
+        let closure = eval::compile(self, file, Some(scope.clone()))?;
 
-        eval::invoke_closure(self, &closure, &[]).await
+        // Variables defined at the top level of the script are kept in the
+        // scope, so that they can be used by later scripts executed in it.
+        eval::invoke_closure_with_bindings(self, &closure, &[], scope).await
     }
 
     /// Invoke the given value as a function with the given arguments.
@@ -188,6 +237,12 @@ impl Fiber {
         eval::invoke(self, value, args).await
     }
 
+    /// Invoke the given value as a function with the given arguments, returning
+    /// what it writes to standard output the same as a pipeline substitution.
+    pub async fn invoke_captured(&mut self, value: &Value, args: &[Value]) -> Result<Value, Exception> {
+        eval::invoke_captured(self, value, args).await
+    }
+
     /// Lookup a variable name in the current scope.
     #[deprecated]
     pub fn get(&self, name: impl AsRef<[u8]>) -> Value {
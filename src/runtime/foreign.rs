@@ -16,6 +16,17 @@ pub struct ForeignFn(Rc<dyn for<'a> Fn(&'a mut Fiber, &'a [Value]) -> LocalBoxFu
 type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
 
 impl ForeignFn {
+    /// Create a foreign function from a closure returning a boxed future.
+    ///
+    /// Unlike plain async functions, such closures may capture state to be
+    /// used by the function.
+    pub fn from_closure<F>(f: F) -> Self
+    where
+        F: 'static + for<'a> Fn(&'a mut Fiber, &'a [Value]) -> LocalBoxFuture<'a, Result<Value, Exception>>
+    {
+        ForeignFn(Rc::new(f))
+    }
+
     pub async fn call(&self, runtime: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
         (&self.0)(runtime, args).await
     }
@@ -0,0 +1,194 @@
+//! Expansion of glob patterns in unquoted words.
+
+use nix::libc;
+use std::{
+    ffi::{CStr, CString},
+    mem::MaybeUninit,
+    path::PathBuf,
+    ptr,
+};
+
+/// Expand a word into the list of paths it refers to.
+///
+/// Brace alternatives are expanded first, and then tildes. Each resulting
+/// pattern containing wildcards is replaced by the existing paths that match
+/// it in sorted order, while patterns without any wildcards are kept as-is.
+pub(crate) fn expand(word: &str) -> Result<Vec<PathBuf>, glob::PatternError> {
+    Ok(expand_patterns(word)?.into_iter().flat_map(|(_, paths)| paths).collect())
+}
+
+/// Expand a word the same as `expand`, but keep the paths of each pattern
+/// resulting from brace expansion separate, along with the pattern.
+pub(crate) fn expand_patterns(word: &str) -> Result<Vec<(String, Vec<PathBuf>)>, glob::PatternError> {
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        require_literal_leading_dot: true,
+        ..glob::MatchOptions::new()
+    };
+
+    let mut patterns = Vec::new();
+
+    for pattern in expand_braces(word) {
+        let pattern = expand_tilde(&pattern);
+
+        let paths = if pattern.contains(&['*', '?', '['][..]) {
+            glob::glob_with(&pattern, options)?.filter_map(Result::ok).collect()
+        } else {
+            vec![PathBuf::from(&pattern)]
+        };
+
+        patterns.push((pattern, paths));
+    }
+
+    Ok(patterns)
+}
+
+/// Expand brace alternatives in a word, such as `{a,b}.txt` into `a.txt` and
+/// `b.txt`. Braces may be nested.
+pub(crate) fn expand_braces(word: &str) -> Vec<String> {
+    match find_braces(word) {
+        Some((start, end, alternatives)) => alternatives
+            .into_iter()
+            .flat_map(|alternative| {
+                expand_braces(&format!("{}{}{}", &word[..start], alternative, &word[end + 1..]))
+            })
+            .collect(),
+        None => vec![word.to_owned()],
+    }
+}
+
+/// Find the first pair of braces containing more than one alternative, and
+/// return their positions along with the alternatives.
+fn find_braces(word: &str) -> Option<(usize, usize, Vec<&str>)> {
+    for (start, _) in word.match_indices('{') {
+        let mut depth = 0;
+        let mut alternatives = Vec::new();
+        let mut alternative_start = start + 1;
+
+        for (i, c) in word.char_indices().skip_while(|&(i, _)| i < start) {
+            match c {
+                '{' => depth += 1,
+                ',' if depth == 1 => {
+                    alternatives.push(&word[alternative_start..i]);
+                    alternative_start = i + 1;
+                }
+                '}' => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        if !alternatives.is_empty() {
+                            alternatives.push(&word[alternative_start..i]);
+                            return Some((start, i, alternatives));
+                        }
+
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    None
+}
+
+/// Expand a leading `~` or `~user` in a word into the home directory of the
+/// current user or the named user. Words naming an unknown user are returned
+/// unchanged.
+pub(crate) fn expand_tilde(word: &str) -> String {
+    if !word.starts_with('~') {
+        return word.to_owned();
+    }
+
+    let end = word.find('/').unwrap_or(word.len());
+    let user = &word[1..end];
+
+    let home = if user.is_empty() {
+        directories::UserDirs::new().map(|dirs| dirs.home_dir().to_string_lossy().into_owned())
+    } else {
+        user_home_dir(user)
+    };
+
+    match home {
+        Some(home) => format!("{}{}", home, &word[end..]),
+        None => word.to_owned(),
+    }
+}
+
+/// Look up the home directory of a user in the system user database, which
+/// may include users from other sources than `/etc/passwd`, such as LDAP.
+fn user_home_dir(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    let mut buffer = vec![0; 1024];
+
+    loop {
+        let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+
+        let error = unsafe {
+            libc::getpwnam_r(name.as_ptr(), passwd.as_mut_ptr(), buffer.as_mut_ptr(), buffer.len(), &mut result)
+        };
+
+        // The buffer holds the strings of the entry, and might be too small.
+        if error == libc::ERANGE {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+
+        if error != 0 || result.is_null() {
+            return None;
+        }
+
+        let home = unsafe { CStr::from_ptr((*result).pw_dir) };
+
+        return Some(home.to_string_lossy().into_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn braces_are_expanded_in_order() {
+        assert_eq!(expand_braces("src/{main,lib}.rs"), vec!["src/main.rs", "src/lib.rs"]);
+        assert_eq!(expand_braces("{a,b{c,d}}x"), vec!["ax", "bcx", "bdx"]);
+        assert_eq!(expand_braces("file.{,bak}"), vec!["file.", "file.bak"]);
+    }
+
+    #[test]
+    fn braces_without_alternatives_are_kept() {
+        assert_eq!(expand_braces("{a}"), vec!["{a}"]);
+        assert_eq!(expand_braces("a{b,c"), vec!["a{b,c"]);
+    }
+
+    #[test]
+    fn tilde_is_only_expanded_at_start() {
+        assert_eq!(expand_tilde("a~b"), "a~b");
+        assert_eq!(expand_tilde("~no-such-user-exists/x"), "~no-such-user-exists/x");
+    }
+
+    #[test]
+    fn tilde_is_expanded_for_named_users() {
+        let root = expand_tilde("~root/x");
+
+        assert!(!root.starts_with('~'));
+        assert!(root.ends_with("/x"));
+    }
+
+    #[test]
+    fn paths_are_kept_with_their_alternative() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.rs"), "").unwrap();
+        std::fs::write(dir.path().join("b.rs"), "").unwrap();
+
+        let word = format!("{}/{{*.rs,*.txt,c?.md}}", dir.path().display());
+        let patterns = expand_patterns(&word).unwrap();
+
+        assert_eq!(patterns.len(), 3);
+        assert_eq!(patterns[0].1, vec![dir.path().join("a.rs"), dir.path().join("b.rs")]);
+        assert!(patterns[1].0.ends_with("/*.txt"));
+        assert!(patterns[1].1.is_empty());
+        assert!(patterns[2].1.is_empty());
+    }
+}
@@ -5,11 +5,15 @@ pub mod builtins;
 pub mod closure;
 pub mod exceptions;
 pub mod foreign;
+pub mod glob;
 pub mod scope;
 pub mod string;
 pub mod table;
 pub mod value;
 
+#[cfg(test)]
+pub(crate) mod testing;
+
 // Re-export syntax crate.
 pub mod syntax {
     pub use riptide_syntax::*;
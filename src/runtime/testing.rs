@@ -0,0 +1,50 @@
+//! Helpers for tests that run scripts.
+
+use super::prelude::*;
+use crate::pipes;
+use futures::future::join;
+use std::future::Future;
+use tokio::io::AsyncReadExt;
+
+/// Run a future to completion on a new runtime, like the one the shell uses.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// Create a fiber without any standard streams.
+pub fn fiber() -> Fiber {
+    Fiber::with_stdio(None, None, None)
+}
+
+/// Execute a script in a fork of the given fiber, and return its result along
+/// with everything it writes to standard output.
+pub async fn execute(fiber: &mut Fiber, source: &str) -> (Result<Value, Exception>, String) {
+    let (mut reader, writer) = pipes::pipe().unwrap();
+
+    let mut fiber = fiber.fork();
+    fiber.stdout = Some(writer);
+
+    let source = source.to_owned();
+    let running = async move {
+        fiber.execute(None, source).await
+    };
+
+    let mut output = Vec::new();
+    let (result, read_result) = join(running, reader.read_to_end(&mut output)).await;
+    read_result.unwrap();
+
+    (result, String::from_utf8(output).unwrap())
+}
+
+/// Execute a script in a new fiber, and return its result along with
+/// everything it writes to standard output.
+pub fn run(source: &str) -> (Result<Value, Exception>, String) {
+    block_on(async {
+        execute(&mut fiber(), source).await
+    })
+}
@@ -1,11 +1,47 @@
+use std::borrow::Cow;
 use std::mem;
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 
 /// State of a prompt buffer.
+///
+/// The cursor is a byte position in the text, and is always kept on a boundary
+/// between grapheme clusters, so that it moves over characters the same way the
+/// user sees them.
+///
+/// Every change to the text is recorded so that it can be undone. Consecutive
+/// insertions, or consecutive deletions, are undone together.
 pub struct Buffer {
     // Current command line buffer text.
     text: String,
     // Current cursor position in the buffer.
     cursor: usize,
+
+    /// States to return to when undoing, the most recent last.
+    undo: Vec<Snapshot>,
+
+    /// States undone since the last change, the most recently undone last.
+    redo: Vec<Snapshot>,
+
+    /// The kind of the last change, if nothing else has happened since.
+    last_edit: Option<Edit>,
+
+    /// Whether edits are being grouped into a single change.
+    changing: bool,
+}
+
+/// A saved state of a buffer.
+struct Snapshot {
+    text: String,
+    cursor: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Edit {
+    Insert,
+    Delete,
+    Other,
 }
 
 impl Buffer {
@@ -13,6 +49,10 @@ impl Buffer {
         Self {
             text: String::new(),
             cursor: 0,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            last_edit: None,
+            changing: false,
         }
     }
 
@@ -21,12 +61,17 @@ impl Buffer {
         &self.text
     }
 
-    /// Take the text buffer out of the prompt.
+    /// Take the text buffer out of the prompt, starting over with an empty
+    /// buffer and no changes to undo.
     pub fn take_text(&mut self) -> String {
         let mut text = String::new();
         mem::swap(&mut self.text, &mut text);
 
         self.cursor = 0;
+        self.undo.clear();
+        self.redo.clear();
+        self.last_edit = None;
+        self.changing = false;
 
         text
     }
@@ -39,58 +84,482 @@ impl Buffer {
     /// Move the cursor to the given position.
     ///
     /// Returns the new cursor position. The actual position may differ if the requested position was beyond the end of
-    /// the buffer.
+    /// the buffer, or inside a grapheme cluster, in which case the cursor is moved to the start of the cluster.
     pub fn move_cursor_to(&mut self, pos: usize) -> usize {
-        self.cursor = self.text.len().min(pos);
+        self.last_edit = None;
+
+        let pos = self.text.len().min(pos);
+
+        self.cursor = if pos == self.text.len() {
+            pos
+        } else {
+            self.text.grapheme_indices(true)
+                .map(|(i, _)| i)
+                .take_while(|&i| i <= pos)
+                .last()
+                .unwrap_or(0)
+        };
         self.cursor
     }
 
-    /// Adjust the cursor position by a relative amount.
+    /// Move the cursor forward by the given number of grapheme clusters, or
+    /// backward if negative.
     ///
     /// Returns the new cursor position.
     pub fn move_cursor_relative(&mut self, offset: isize) -> usize {
-        let pos = 0.max(self.cursor as isize + offset) as usize;
+        let pos = self.grapheme_offset(offset);
         self.move_cursor_to(pos)
     }
 
+    /// Get the position the given number of grapheme clusters after the
+    /// cursor, or before it if negative.
+    fn grapheme_offset(&self, offset: isize) -> usize {
+        if offset >= 0 {
+            self.text[self.cursor..].grapheme_indices(true)
+                .nth(offset as usize)
+                .map_or(self.text.len(), |(i, _)| self.cursor + i)
+        } else {
+            self.text[..self.cursor].grapheme_indices(true)
+                .rev()
+                .nth(offset.unsigned_abs() - 1)
+                .map_or(0, |(i, _)| i)
+        }
+    }
+
+    /// Get the position where the word before the cursor starts.
+    ///
+    /// Words are made of letters and digits, unless `whitespace` is true, in
+    /// which case they are anything between whitespace.
+    pub fn previous_word_start(&self, whitespace: bool) -> usize {
+        let is_word = word_predicate(whitespace);
+        let before = self.text[..self.cursor].trim_end_matches(|c| !is_word(c));
+        before.trim_end_matches(is_word).len()
+    }
+
+    /// Get the position where the word after the cursor ends.
+    pub fn next_word_end(&self) -> usize {
+        let is_word = word_predicate(false);
+        let after = self.text[self.cursor..].trim_start_matches(|c| !is_word(c));
+        self.text.len() - after.trim_start_matches(is_word).len()
+    }
+
+    /// Get the position where the line the cursor is on starts.
+    pub fn line_start(&self) -> usize {
+        self.text[..self.cursor].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    /// Get the position where the line the cursor is on ends, before its
+    /// newline.
+    pub fn line_end(&self) -> usize {
+        self.text[self.cursor..].find('\n').map_or(self.text.len(), |i| self.cursor + i)
+    }
+
+    /// Get the line the cursor is on, counting from zero.
+    pub fn cursor_line(&self) -> usize {
+        self.text[..self.cursor].matches('\n').count()
+    }
+
+    /// Get the number of lines in the buffer.
+    pub fn line_count(&self) -> usize {
+        self.text.matches('\n').count() + 1
+    }
+
     pub fn move_to_start_of_line(&mut self) {
-        self.move_cursor_to(0);
+        let pos = self.line_start();
+        self.move_cursor_to(pos);
     }
 
     pub fn move_to_end_of_line(&mut self) {
-        let pos = self.text.len();
+        let pos = self.line_end();
         self.move_cursor_to(pos);
     }
 
+    /// Move the cursor to the same column of the previous line, or of the next
+    /// line if `down` is true, or as close to it as the line allows.
+    ///
+    /// Returns false if there is no line to move to.
+    pub fn move_to_adjacent_line(&mut self, down: bool) -> bool {
+        let column = display_width(&self.text[self.line_start()..self.cursor]);
+
+        let line_start = if down {
+            let end = self.line_end();
+            if end == self.text.len() {
+                return false;
+            }
+            end + 1
+        } else {
+            let start = self.line_start();
+            if start == 0 {
+                return false;
+            }
+            self.text[..start - 1].rfind('\n').map_or(0, |i| i + 1)
+        };
+
+        let line = &self.text[line_start..];
+        let line = &line[..line.find('\n').unwrap_or(line.len())];
+
+        let mut width = 0;
+        self.cursor = line_start + line.len();
+
+        for (i, grapheme) in line.grapheme_indices(true) {
+            if width >= column {
+                self.cursor = line_start + i;
+                break;
+            }
+            width += display_width(grapheme);
+        }
+
+        true
+    }
+
     /// Insert a character after the cursor.
     pub fn insert_char(&mut self, c: char) {
+        // Typing is undone a word at a time.
+        if c.is_whitespace() {
+            self.last_edit = None;
+        }
+
+        self.record(Edit::Insert);
         self.text.insert(self.cursor, c);
-        self.move_cursor_relative(1);
+        self.cursor += c.len_utf8();
     }
 
     /// Insert a string after the cursor.
     pub fn insert_str<S: AsRef<str>>(&mut self, string: S) {
         let string = string.as_ref();
-        self.text.insert_str(self.cursor as usize, string);
-        self.move_cursor_relative(string.len() as isize);
+        self.record(Edit::Insert);
+        self.text.insert_str(self.cursor, string);
+        self.cursor += string.len();
+    }
+
+    /// Insert pasted text after the cursor, which is undone separately from
+    /// any typing around it.
+    pub fn paste(&mut self, string: &str) {
+        self.last_edit = None;
+        self.insert_str(string);
+        self.last_edit = None;
+    }
+
+    /// Replace the text between the given position and the cursor, moving the
+    /// cursor to the end of the replacement.
+    pub fn replace_before_cursor(&mut self, start: usize, string: &str) {
+        self.record(Edit::Other);
+        self.text.replace_range(start..self.cursor, string);
+        self.cursor = start + string.len();
     }
 
+    /// Delete the grapheme cluster before the cursor.
     pub fn delete_before_cursor(&mut self) {
-        if self.cursor > 0 {
-            self.text.remove(self.cursor - 1);
-            self.move_cursor_relative(-1);
-        }
+        let start = self.grapheme_offset(-1);
+        self.remove(start..self.cursor);
     }
 
+    /// Delete the grapheme cluster after the cursor.
     pub fn delete_after_cursor(&mut self) {
-        if self.cursor < self.text.len() {
-            self.text.remove(self.cursor);
+        let end = self.grapheme_offset(1);
+        self.remove(self.cursor..end);
+    }
+
+    /// Remove a range of text, returning it. A cursor inside or after the range
+    /// is moved back along with the text.
+    pub fn remove(&mut self, range: Range<usize>) -> String {
+        if range.is_empty() {
+            return String::new();
+        }
+
+        self.record(Edit::Delete);
+
+        if self.cursor >= range.end {
+            self.cursor -= range.len();
+        } else if self.cursor > range.start {
+            self.cursor = range.start;
+        }
+
+        self.text.drain(range).collect()
+    }
+
+    /// Swap the grapheme clusters on either side of the cursor and move the
+    /// cursor forward. At the end of a line, the two before the cursor are
+    /// swapped instead.
+    pub fn transpose(&mut self) {
+        let original = self.cursor;
+
+        if self.cursor == self.line_end() && self.cursor > self.line_start() {
+            self.cursor = self.grapheme_offset(-1);
+        }
+
+        let middle = self.cursor;
+        let start = self.grapheme_offset(-1);
+        let end = self.grapheme_offset(1);
+        self.cursor = original;
+
+        if start == middle || end == middle || self.text[start..end].contains('\n') {
+            return;
         }
+
+        self.record(Edit::Other);
+
+        let swapped = format!("{}{}", &self.text[middle..end], &self.text[start..middle]);
+        self.text.replace_range(start..end, &swapped);
+        self.cursor = end;
     }
 
     /// Clears the buffer text and moves the cursor to the beginning.
     pub fn clear(&mut self) {
+        if !self.text.is_empty() {
+            self.record(Edit::Other);
+        }
+
         self.text.clear();
         self.cursor = 0;
     }
+
+    /// Undo the last change, returning false if there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop() {
+            Some(snapshot) => {
+                let current = self.restore(snapshot);
+                self.redo.push(current);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Redo the last undone change, returning false if there is nothing to
+    /// redo.
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(snapshot) => {
+                let current = self.restore(snapshot);
+                self.undo.push(current);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start a change made up of several edits, which are undone together
+    /// until `end_change` is called.
+    pub fn begin_change(&mut self) {
+        self.record(Edit::Other);
+        self.changing = true;
+    }
+
+    /// Finish a change started with `begin_change`.
+    pub fn end_change(&mut self) {
+        if !self.changing {
+            return;
+        }
+
+        self.changing = false;
+        self.last_edit = None;
+
+        // Don't leave anything to undo if nothing was changed.
+        if self.undo.last().map(|snapshot| snapshot.text == self.text).unwrap_or(false) {
+            self.undo.pop();
+        }
+    }
+
+    /// Save the current state before a change so that it can be undone, unless
+    /// the change continues the previous one.
+    fn record(&mut self, edit: Edit) {
+        if !self.changing && (edit == Edit::Other || self.last_edit != Some(edit)) {
+            self.undo.push(Snapshot {
+                text: self.text.clone(),
+                cursor: self.cursor,
+            });
+        }
+
+        self.redo.clear();
+        self.last_edit = Some(edit);
+    }
+
+    /// Replace the current state with a saved one, returning the current state.
+    fn restore(&mut self, snapshot: Snapshot) -> Snapshot {
+        self.last_edit = None;
+
+        Snapshot {
+            text: mem::replace(&mut self.text, snapshot.text),
+            cursor: mem::replace(&mut self.cursor, snapshot.cursor),
+        }
+    }
+}
+
+fn word_predicate(whitespace: bool) -> fn(char) -> bool {
+    if whitespace {
+        |c| !c.is_whitespace()
+    } else {
+        char::is_alphanumeric
+    }
+}
+
+/// Get the number of columns that text takes up when shown in a terminal.
+///
+/// Each grapheme cluster is as wide as its first character, so combining marks
+/// and joined emoji take up no extra space. Clusters with an emoji presentation
+/// selector are shown as wide emoji. Escape sequences, such as those that set
+/// colours, take up no space.
+pub fn display_width(text: &str) -> usize {
+    strip_escapes(text).graphemes(true).map(grapheme_width).sum()
+}
+
+/// Get the number of columns that a grapheme cluster takes up.
+pub(crate) fn grapheme_width(grapheme: &str) -> usize {
+    if grapheme.contains('\u{fe0f}') {
+        2
+    } else {
+        grapheme.chars().next().and_then(UnicodeWidthChar::width).unwrap_or(0)
+    }
+}
+
+/// Remove terminal escape sequences from text.
+pub(crate) fn strip_escapes(text: &str) -> Cow<'_, str> {
+    if !text.contains('\x1b') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            output.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            // Control sequences end with a character from `@` to `~`.
+            Some('[') => {
+                chars.by_ref().find(|c| ('@'..='~').contains(c));
+            }
+            // Operating system commands end with a bell or `ESC \`.
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next() == Some('\\')) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Cow::Owned(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_moves_over_grapheme_clusters() {
+        let mut buffer = Buffer::new();
+        buffer.insert_str("ae\u{301}\u{1f468}\u{200d}\u{1f467}");
+        buffer.insert_char('\u{e9}');
+
+        buffer.move_cursor_relative(-1);
+        buffer.move_cursor_relative(-1);
+        assert_eq!(&buffer.text()[buffer.cursor()..], "\u{1f468}\u{200d}\u{1f467}\u{e9}");
+
+        buffer.delete_before_cursor();
+        assert_eq!(buffer.text(), "a\u{1f468}\u{200d}\u{1f467}\u{e9}");
+
+        buffer.delete_after_cursor();
+        assert_eq!(buffer.text(), "a\u{e9}");
+        assert_eq!(buffer.cursor(), 1);
+    }
+
+    #[test]
+    fn cursor_is_moved_to_the_start_of_grapheme_clusters() {
+        let mut buffer = Buffer::new();
+        buffer.insert_str("ae\u{301}\u{1f468}\u{200d}\u{1f467}");
+
+        // Inside the combining accent.
+        assert_eq!(buffer.move_cursor_to(3), 1);
+        // Inside the joined emoji.
+        assert_eq!(buffer.move_cursor_to(8), 4);
+        assert_eq!(buffer.move_cursor_to(4), 4);
+        assert_eq!(buffer.move_cursor_to(100), buffer.text().len());
+    }
+
+    #[test]
+    fn words_are_found_around_the_cursor() {
+        let mut buffer = Buffer::new();
+        buffer.insert_str("echo foo-bar  baz");
+        buffer.move_cursor_to(10);
+
+        assert_eq!(buffer.previous_word_start(false), 9);
+        assert_eq!(buffer.previous_word_start(true), 5);
+        assert_eq!(buffer.next_word_end(), 12);
+
+        assert_eq!(buffer.remove(9..12), "bar");
+        assert_eq!(buffer.text(), "echo foo-  baz");
+        assert_eq!(buffer.cursor(), 9);
+    }
+
+    #[test]
+    fn transpose_swaps_characters() {
+        let mut buffer = Buffer::new();
+        buffer.insert_str("abc");
+        buffer.transpose();
+        assert_eq!(buffer.text(), "acb");
+
+        buffer.move_cursor_to(1);
+        buffer.transpose();
+        assert_eq!(buffer.text(), "cab");
+        assert_eq!(buffer.cursor(), 2);
+    }
+
+    #[test]
+    fn changes_are_undone_and_redone() {
+        let mut buffer = Buffer::new();
+        buffer.insert_char('a');
+        buffer.insert_char('b');
+        buffer.insert_char(' ');
+        buffer.insert_char('x');
+        assert!(buffer.undo());
+        assert_eq!(buffer.text(), "ab");
+
+        buffer.move_cursor_relative(-1);
+        buffer.insert_char('c');
+        buffer.delete_after_cursor();
+
+        assert_eq!(buffer.text(), "ac");
+        assert!(buffer.undo());
+        assert_eq!(buffer.text(), "acb");
+        assert!(buffer.undo());
+        assert_eq!(buffer.text(), "ab");
+        assert_eq!(buffer.cursor(), 1);
+        assert!(buffer.undo());
+        assert_eq!(buffer.text(), "");
+        assert!(!buffer.undo());
+
+        assert!(buffer.redo());
+        assert_eq!(buffer.text(), "ab");
+        buffer.insert_char('d');
+        assert!(!buffer.redo());
+    }
+
+    #[test]
+    fn lines_are_aligned_by_display_width() {
+        let mut buffer = Buffer::new();
+        buffer.insert_str("\u{4e2d}\u{6587}x\nabcdef");
+        buffer.move_cursor_to(11);
+
+        assert!(buffer.move_to_adjacent_line(false));
+        assert_eq!(&buffer.text()[buffer.cursor()..], "x\nabcdef");
+        assert!(!buffer.move_to_adjacent_line(false));
+    }
+
+    #[test]
+    fn display_width_of_wide_and_zero_width_characters() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("\u{4e2d}\u{6587}"), 4);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_width("\u{1f468}\u{200d}\u{1f467}"), 2);
+        assert_eq!(display_width("\u{2764}\u{fe0f}"), 2);
+        assert_eq!(display_width("\x1b[1;34m~/src\x1b[0m $ "), 8);
+        assert_eq!(display_width("\x1b]0;title\x07$ "), 2);
+    }
 }
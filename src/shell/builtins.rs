@@ -0,0 +1,572 @@
+//! Functions that are only available in interactive sessions.
+
+use super::completion::COMPLETERS_GLOBAL;
+use super::editor::{self, EDITOR_GLOBAL};
+use super::highlight::{self, THEME_GLOBAL};
+use super::history::{self, CommandEntry, Filter, History, Shell};
+use super::keymap::{self, KEYMAPS, KEYMAPS_KEY};
+use super::plugins::{self, PLUGINS_GLOBAL};
+use crate::runtime::foreign::ForeignFn;
+use crate::runtime::prelude::*;
+use futures::future::FutureExt;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+/// Add the interactive functions to the globals of a fiber, using the given
+/// history.
+pub fn register(fiber: &Fiber, history: &History) {
+    let globals = fiber.globals();
+
+    globals.set("history", self::history(history.clone()));
+    globals.set("cd", cd(history.clone(), globals.get("cd")));
+    globals.set("command", plugins::command(globals.get("command")));
+    globals.set("z", jump(history.clone()));
+    globals.set("jump", jump(history.clone()));
+    globals.set("bookmark", bookmark(history.clone()));
+    globals.set("complete", Value::foreign_fn(complete));
+    globals.set("bind", Value::foreign_fn(bind));
+    globals.set("style", Value::foreign_fn(style));
+    globals.set(COMPLETERS_GLOBAL, table!());
+    globals.set(THEME_GLOBAL, highlight::default_theme());
+    globals.set(EDITOR_GLOBAL, editor::default_settings());
+    globals.set(PLUGINS_GLOBAL, table!());
+}
+
+/// Create the `history` function for browsing and editing the given history.
+fn history(history: History) -> ForeignFn {
+    ForeignFn::from_closure(move |fiber, args| {
+        let history = history.clone();
+
+        async move {
+            history_command(&history, fiber, args).await
+        }.boxed_local()
+    })
+}
+
+/// Lists, searches, deletes, imports, or exports entries in the command
+/// history.
+///
+/// Usage:
+///
+/// - `history [OPTIONS]`: list matching commands, oldest first.
+/// - `history search TEXT [OPTIONS]`: list matching commands containing text.
+/// - `history delete [ID...] [OPTIONS]`: delete the given commands, or all
+///   matching commands. Given commands that don't match stop anything from
+///   being deleted.
+/// - `history import SHELL [PATH]`: import commands from the history file of
+///   `bash`, `zsh`, or `fish`.
+/// - `history export [plain|json] [OPTIONS]`: print matching commands, oldest
+///   first, either as they were entered or as one JSON object per line.
+///
+/// Options:
+///
+/// - `--session`: only commands run in this session.
+/// - `--here`: only commands run in the current directory.
+/// - `--cwd DIR`: only commands run in the given directory.
+/// - `--since TIME`, `--until TIME`: only commands run within a time range.
+///   Times are Unix timestamps, or durations ago such as `30m` or `2d`.
+/// - `--status CODE`: only commands that exited with the given status.
+/// - `--failed`: only commands that exited with a non-zero status.
+/// - `--limit N`: list at most the N most recent matching commands.
+async fn history_command(history: &History, fiber: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    let mut args = args.iter().map(Value::to_string).peekable();
+
+    let action = match args.peek().map(String::as_str) {
+        Some("list") | Some("search") | Some("delete") | Some("import") | Some("export") => args.next().unwrap(),
+        _ => String::from("list"),
+    };
+
+    let mut filter = Filter::default();
+    let mut limit = None;
+    let mut words = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| match args.next() {
+            Some(value) => Ok(value),
+            None => Err(Exception::from(format!("history: {} requires a value", name))),
+        };
+
+        match arg.as_str() {
+            "--session" => filter.session = true,
+            "--here" => filter.cwd = env::current_dir().ok().and_then(|path| path.to_str().map(String::from)),
+            "--cwd" => filter.cwd = Some(value("--cwd")?),
+            "--since" => filter.since = Some(parse_time(&value("--since")?)?),
+            "--until" => filter.until = Some(parse_time(&value("--until")?)?),
+            "--status" => filter.exit_status = Some(parse_number(&value("--status")?)?),
+            "--failed" => filter.failed = true,
+            "--limit" => limit = Some(parse_number(&value("--limit")?)?),
+            option if option.starts_with("--") => throw!("history: unknown option: {}", option),
+            _ => words.push(arg),
+        }
+    }
+
+    match action.as_str() {
+        "search" => {
+            if words.len() != 1 {
+                throw!("history: search requires exactly one search text");
+            }
+            filter.text = words.pop();
+        }
+        "delete" => {
+            if words.is_empty() && filter.is_empty() {
+                throw!("history: refusing to delete all history, give IDs or options to select commands");
+            }
+
+            if words.is_empty() {
+                history.delete(&filter).map_err(|e| e.to_string())?;
+            } else {
+                let ids = words.iter().map(|word| parse_number(word)).collect::<Result<Vec<_>, _>>()?;
+
+                if let Some(id) = history.delete_entries(&ids, &filter).map_err(|e| e.to_string())? {
+                    throw!("history: no matching entry: {}, nothing was deleted", id);
+                }
+            }
+
+            return Ok(Value::Nil);
+        }
+        "import" => {
+            if words.is_empty() || words.len() > 2 {
+                throw!("history: import requires a shell and an optional path");
+            }
+
+            return import(history, fiber, &words[0], words.get(1)).await;
+        }
+        "export" => {
+            let json = match words.first().map(String::as_str) {
+                None | Some("plain") if words.len() <= 1 => false,
+                Some("json") if words.len() == 1 => true,
+                _ => throw!("history: export format must be plain or json"),
+            };
+
+            let mut entries = history.entries(&filter)
+                .take(limit.unwrap_or(usize::MAX))
+                .collect::<Vec<_>>();
+            entries.reverse();
+
+            if let Some(stdout) = fiber.stdout() {
+                for entry in entries {
+                    let line = if json {
+                        history::to_json(&entry)
+                    } else {
+                        entry.command().to_owned()
+                    };
+
+                    stdout.write_all(line.as_bytes()).await?;
+                    stdout.write_all(b"\n").await?;
+                }
+                stdout.flush().await?;
+            }
+
+            return Ok(Value::Nil);
+        }
+        _ => {
+            if !words.is_empty() {
+                throw!("history: unexpected argument: {}", words[0]);
+            }
+        }
+    }
+
+    let mut entries = history.entries(&filter)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect::<Vec<_>>();
+    entries.reverse();
+
+    if let Some(stdout) = fiber.stdout() {
+        for entry in entries {
+            stdout.write_all(format_entry(&entry).as_bytes()).await?;
+        }
+        stdout.flush().await?;
+    }
+
+    Ok(Value::Nil)
+}
+
+/// Wrap a `cd` function so that the new working directory is recorded in the
+/// directory history.
+fn cd(history: History, cd: Value) -> ForeignFn {
+    ForeignFn::from_closure(move |fiber, args| {
+        let history = history.clone();
+        let cd = cd.clone();
+
+        async move {
+            let previous = env::current_dir().ok();
+            let result = fiber.invoke(&cd, args).await?;
+
+            if let Ok(current) = env::current_dir() {
+                if Some(&current) != previous.as_ref() {
+                    history.add_directory(current.to_string_lossy());
+                }
+            }
+
+            Ok(result)
+        }.boxed_local()
+    })
+}
+
+/// Create the `jump` function, also available as `z`.
+fn jump(history: History) -> ForeignFn {
+    ForeignFn::from_closure(move |fiber, args| {
+        let history = history.clone();
+
+        async move {
+            jump_command(&history, fiber, args).await
+        }.boxed_local()
+    })
+}
+
+/// Changes to the best matching directory from the directory history.
+///
+/// Usage:
+///
+/// - `jump`: list the most frecent directories with their scores.
+/// - `jump NAME`: change to the bookmark with the given name, if one exists.
+/// - `jump TERM...`: change to the most frecent directory matching all terms.
+async fn jump_command(history: &History, fiber: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    let terms = args.iter().map(Value::to_string).collect::<Vec<_>>();
+
+    if terms.is_empty() {
+        let directories = history.frecent_directories().take(10).collect::<Vec<_>>();
+
+        if let Some(stdout) = fiber.stdout() {
+            for directory in directories.into_iter().rev() {
+                stdout.write_all(format!("{:>8.2}  {}\n", directory.score(), directory.path()).as_bytes()).await?;
+            }
+            stdout.flush().await?;
+        }
+
+        return Ok(Value::Nil);
+    }
+
+    let bookmark = match terms.as_slice() {
+        [name] => history.bookmark(name),
+        _ => None,
+    };
+
+    let path = match bookmark {
+        Some(path) => path,
+        None => {
+            let terms = terms.iter().map(String::as_str).collect::<Vec<_>>();
+
+            match history.find_directory(&terms) {
+                Some(path) => path,
+                None => throw!("jump: no matching directory"),
+            }
+        }
+    };
+
+    let cd = fiber.globals().get("cd");
+    fiber.invoke(&cd, &[Value::from(path)]).await
+}
+
+/// Create the `bookmark` function.
+fn bookmark(history: History) -> ForeignFn {
+    ForeignFn::from_closure(move |fiber, args| {
+        let history = history.clone();
+
+        async move {
+            bookmark_command(&history, fiber, args).await
+        }.boxed_local()
+    })
+}
+
+/// Lists, adds, or removes directory bookmarks.
+///
+/// Usage:
+///
+/// - `bookmark`: list all bookmarks.
+/// - `bookmark add NAME [PATH]`: bookmark a directory, which defaults to the
+///   current directory.
+/// - `bookmark remove NAME`: remove a bookmark.
+async fn bookmark_command(history: &History, fiber: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    let args = args.iter().map(Value::to_string).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    match args.as_slice() {
+        [] => {
+            let bookmarks = history.bookmarks();
+
+            if let Some(stdout) = fiber.stdout() {
+                for (name, path) in bookmarks {
+                    stdout.write_all(format!("{}  {}\n", name, path).as_bytes()).await?;
+                }
+                stdout.flush().await?;
+            }
+        }
+        ["add", name] | ["add", name, _] => {
+            let path = match args.get(2) {
+                Some(path) => fs::canonicalize(path),
+                None => env::current_dir(),
+            };
+
+            let path = match path {
+                Ok(ref path) if path.is_dir() => path.to_string_lossy().into_owned(),
+                Ok(path) => throw!("bookmark: {}: not a directory", path.display()),
+                Err(e) => throw!("bookmark: {}", e),
+            };
+
+            history.set_bookmark(name, &path).map_err(|e| e.to_string())?;
+        }
+        ["remove", name] => {
+            if !history.remove_bookmark(name).map_err(|e| e.to_string())? {
+                throw!("bookmark: no such bookmark: {}", name);
+            }
+        }
+        _ => throw!("bookmark: usage: bookmark [add NAME [PATH] | remove NAME]"),
+    }
+
+    Ok(Value::Nil)
+}
+
+/// Lists, registers, or removes functions that complete the arguments of a
+/// command.
+///
+/// A completer is called with the word being completed and a list of the
+/// literal arguments before it, and returns a list of candidates. Candidates
+/// that do not start with the word are ignored. If a completer returns
+/// anything other than a list, file paths are completed instead.
+///
+/// Usage:
+///
+/// - `complete`: list the commands that have completers.
+/// - `complete COMMAND FUNCTION`: complete the arguments of a command using a
+///   function.
+/// - `complete --remove COMMAND`: remove the completer for a command.
+async fn complete(fiber: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    let completers = match fiber.globals().get(COMPLETERS_GLOBAL).as_table() {
+        Some(completers) => completers,
+        None => throw!("complete: ${} is not a table", COMPLETERS_GLOBAL),
+    };
+
+    match args {
+        [] => {
+            if let Some(stdout) = fiber.stdout() {
+                for name in completers.keys() {
+                    stdout.write_all(format!("{}\n", name).as_bytes()).await?;
+                }
+                stdout.flush().await?;
+            }
+        }
+        [option, name] if *option == "--remove" => {
+            if completers.set(name.to_string(), Value::Nil).is_nil() {
+                throw!("complete: no completer for {}", name);
+            }
+        }
+        [name, function @ Value::Block(_)] | [name, function @ Value::ForeignFn(_)] => {
+            completers.set(name.to_string(), function.clone());
+        }
+        _ => throw!("complete: usage: complete [COMMAND FUNCTION | --remove COMMAND]"),
+    }
+
+    Ok(Value::Nil)
+}
+
+/// Binds keys to editor actions or functions in the line editor.
+///
+/// A function bound to keys is called with a table holding the `text` of the
+/// command line and the `cursor` position in it, and changes made to the table
+/// are made to the command line. If the function returns the name of an
+/// action, the action is then performed.
+///
+/// Usage:
+///
+/// - `bind [--keymap NAME]`: list the keys bound in a keymap.
+/// - `bind [--keymap NAME] KEYS`: print what keys are bound to.
+/// - `bind [--keymap NAME] KEYS ACTION`: bind keys to an editor action.
+/// - `bind [--keymap NAME] KEYS FUNCTION`: bind keys to a function.
+/// - `bind --remove [--keymap NAME] KEYS`: remove the binding for keys.
+/// - `bind --actions`: list the names of the editor actions.
+///
+/// Keys are named like `a`, `ctrl-a`, `alt-a`, or `enter`, and several keys
+/// pressed in sequence are separated by spaces, such as `"ctrl-x ctrl-e"`. The
+/// keymap is one of `emacs`, `vi-insert`, or `vi-normal`, and defaults to the
+/// keymap for typing text in the current editing mode.
+async fn bind(fiber: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    let settings = fiber.globals().get(EDITOR_GLOBAL);
+
+    let mut name = String::from(if settings.get("mode") == "vi" { "vi-insert" } else { "emacs" });
+    let mut remove = false;
+    let mut words = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_string().and_then(|arg| arg.as_utf8()) {
+            Some("--keymap") => match args.next() {
+                Some(value) => name = value.to_string(),
+                None => throw!("bind: --keymap requires a value"),
+            },
+            Some("--remove") => remove = true,
+            Some("--actions") => {
+                if let Some(stdout) = fiber.stdout() {
+                    for action in editor::ACTIONS {
+                        stdout.write_all(format!("{}\n", action.0).as_bytes()).await?;
+                    }
+                    stdout.flush().await?;
+                }
+                return Ok(Value::Nil);
+            }
+            Some(option) if option.starts_with("--") => throw!("bind: unknown option: {}", option),
+            _ => words.push(arg),
+        }
+    }
+
+    if !KEYMAPS.contains(&name.as_str()) {
+        throw!("bind: unknown keymap: {}", name);
+    }
+
+    let keymap = match settings.get(KEYMAPS_KEY).get(&name).as_table() {
+        Some(keymap) => keymap,
+        None => throw!("bind: ${}->{}->{} is not a table", EDITOR_GLOBAL, KEYMAPS_KEY, name),
+    };
+
+    let keys = match words.first().map(|keys| keys.to_string()) {
+        Some(keys) => match keymap::parse_keys(&keys) {
+            Some(keys) => Some(keys),
+            None => throw!("bind: invalid keys: {}", keys),
+        },
+        None => None,
+    };
+
+    match (keys, words.as_slice()) {
+        (None, []) if !remove => {
+            if let Some(stdout) = fiber.stdout() {
+                let mut bindings = keymap.keys()
+                    .map(|keys| format!("{}\t{}\n", keys, keymap.get(&keys)))
+                    .collect::<Vec<_>>();
+                bindings.sort();
+
+                for binding in bindings {
+                    stdout.write_all(binding.as_bytes()).await?;
+                }
+                stdout.flush().await?;
+            }
+        }
+        (Some(keys), [_]) if !remove => {
+            let binding = keymap.get(keys.as_str());
+
+            if binding.is_nil() {
+                throw!("bind: no binding for {}", keys);
+            }
+
+            if let Some(stdout) = fiber.stdout() {
+                stdout.write_all(format!("{}\n", binding).as_bytes()).await?;
+                stdout.flush().await?;
+            }
+        }
+        (Some(keys), [_]) => {
+            if keymap.set(keys.as_str(), Value::Nil).is_nil() {
+                throw!("bind: no binding for {}", keys);
+            }
+        }
+        (Some(keys), [_, binding]) if !remove => {
+            if !keymap::is_valid_binding(binding) {
+                throw!("bind: {} is not an editor action or function", binding);
+            }
+
+            keymap.set(keys.as_str(), (*binding).clone());
+        }
+        _ => throw!("bind: usage: bind [--keymap NAME] [KEYS [ACTION | FUNCTION] | --remove KEYS | --actions]"),
+    }
+
+    Ok(Value::Nil)
+}
+
+/// Wraps text in the escape sequences for a style, written the same way as in
+/// the theme, such as `bold blue`. Useful for colouring prompts.
+///
+/// Usage:
+///
+/// - `style STYLE TEXT`: return the text in the given style.
+async fn style(_: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    match args {
+        [style, text] => Ok(Value::from(highlight::style(&style.to_string(), &text.to_string()))),
+        _ => throw!("style: usage: style STYLE TEXT"),
+    }
+}
+
+/// Import the history file of another shell.
+async fn import(history: &History, fiber: &mut Fiber, name: &str, path: Option<&String>) -> Result<Value, Exception> {
+    let shell = name.parse::<Shell>().map_err(|e| format!("history: {}", e))?;
+
+    let path = match path.map(PathBuf::from).or_else(|| shell.default_path()) {
+        Some(path) => path,
+        None => throw!("history: unable to locate the history file for {}", name),
+    };
+
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) => throw!("history: {}: {}", path.display(), e),
+    };
+
+    let added = history.import_commands(&shell.parse(&bytes)).map_err(|e| e.to_string())?;
+
+    if let Some(stdout) = fiber.stdout() {
+        stdout.write_all(format!("imported {} commands from {}\n", added, path.display()).as_bytes()).await?;
+        stdout.flush().await?;
+    }
+
+    Ok(Value::Nil)
+}
+
+/// Format an entry as a line with its ID, exit status, duration, and command.
+fn format_entry(entry: &CommandEntry) -> String {
+    let status = entry.exit_status()
+        .map(|status| status.to_string())
+        .unwrap_or_else(|| String::from("-"));
+
+    let duration = entry.duration()
+        .map(|duration| format!("{:.1}s", duration.as_secs_f64()))
+        .unwrap_or_else(|| String::from("-"));
+
+    format!("{:>6}  {:>3}  {:>7}  {}\n", entry.id(), status, duration, entry.command())
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> Result<T, Exception> {
+    match s.parse() {
+        Ok(number) => Ok(number),
+        Err(_) => throw!("history: invalid number: {}", s),
+    }
+}
+
+/// Parse a time, given either as a Unix timestamp or as a duration before now
+/// with a unit suffix of `s`, `m`, `h`, or `d`.
+fn parse_time(s: &str) -> Result<SystemTime, Exception> {
+    let unit: u64 = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        _ => return UNIX_EPOCH.checked_add(Duration::from_secs(parse_number(s)?)).ok_or_else(|| invalid_time(s)),
+    };
+
+    let amount: u64 = parse_number(&s[..s.len() - 1])?;
+
+    amount.checked_mul(unit)
+        .and_then(|seconds| SystemTime::now().checked_sub(Duration::from_secs(seconds)))
+        .ok_or_else(|| invalid_time(s))
+}
+
+fn invalid_time(s: &str) -> Exception {
+    Exception::from(format!("history: invalid time: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_are_parsed_as_timestamps_or_durations_ago() {
+        assert_eq!(parse_time("60").unwrap(), UNIX_EPOCH + Duration::from_secs(60));
+        assert!(parse_time("2d").unwrap() < SystemTime::now() - Duration::from_secs(47 * 60 * 60));
+        assert!(parse_time("2x").is_err());
+    }
+
+    #[test]
+    fn times_out_of_range_are_invalid() {
+        assert_eq!(parse_time("99999999999999999d").unwrap_err().to_string(), "history: invalid time: 99999999999999999d");
+        assert!(parse_time("18446744073709551615s").is_err());
+        assert!(parse_time("18446744073709551615").is_err());
+    }
+}
@@ -1,4 +1,11 @@
 pub enum Command {
     ClearAfterCursor,
     MoveCursorLeft(usize),
+    MoveCursorRight(usize),
+    MoveCursorUp(usize),
+    MoveCursorDown(usize),
+    SetDim,
+    ResetStyle,
+    EnableBracketedPaste,
+    DisableBracketedPaste,
 }
@@ -0,0 +1,469 @@
+//! Tab completion for the interactive editor.
+//!
+//! The text before the cursor is split into the word being completed and the
+//! text before it. What kind of candidates are offered depends on where the
+//! word is:
+//!
+//! - After a `$`, the names of variables.
+//! - After a `->`, the keys of the table on the left of the operator.
+//! - At the start of a call, the names of functions and of executables on the
+//!   `PATH`.
+//! - Anywhere else, the result of the completer registered for the command
+//!   being called, or else file paths.
+
+use crate::runtime::prelude::*;
+use crate::runtime::syntax::{self, ast::*, source::SourceFile};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Name of the global table holding per-command completers.
+pub(crate) const COMPLETERS_GLOBAL: &str = "completers";
+
+/// A possible replacement for the word being completed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub text: String,
+
+    /// Whether the candidate is a whole word that should be followed by a
+    /// space. Directories and tables are usually completed further, so they
+    /// are not.
+    pub complete: bool,
+}
+
+/// The candidates for completing the word before the cursor.
+pub struct Completions {
+    /// Position in the line where the word being completed starts.
+    pub start: usize,
+
+    /// Possible replacements for the word, sorted.
+    pub candidates: Vec<Candidate>,
+}
+
+/// Completes words in command lines using the state of an interactive session.
+pub struct Completer {
+    fiber: Fiber,
+
+    /// The scope that command lines are executed in.
+    scope: Table,
+}
+
+impl Completer {
+    pub fn new(fiber: Fiber, scope: Table) -> Self {
+        Self {
+            fiber,
+            scope,
+        }
+    }
+
+    /// Find the candidates for completing the word before the cursor.
+    pub async fn complete(&mut self, line: &str, cursor: usize) -> Completions {
+        let text = &line[..cursor];
+        let start = word_start(text);
+        let word = &text[start..];
+        let before = &text[..start];
+
+        let (start, mut candidates) = if let Some(name) = word.strip_prefix('$') {
+            (start + 1, self.variables(name))
+        } else if before.ends_with("->") {
+            (start, self.members(before, word))
+        } else if is_command_position(before) {
+            (start, self.commands(word))
+        } else {
+            (start, self.arguments(before, word).await)
+        };
+
+        candidates.sort_by(|a, b| a.text.cmp(&b.text));
+        candidates.dedup_by(|a, b| a.text == b.text);
+
+        Completions {
+            start,
+            candidates,
+        }
+    }
+
+    /// Complete the name of a variable.
+    fn variables(&self, prefix: &str) -> Vec<Candidate> {
+        [&self.scope, self.fiber.globals()].iter()
+            .flat_map(|table| table.keys())
+            .filter_map(|key| key.as_utf8().map(String::from))
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| Candidate {
+                text: name,
+                complete: true,
+            })
+            .collect()
+    }
+
+    /// Complete a key of the table on the left of a trailing `->` operator.
+    fn members(&self, before: &str, prefix: &str) -> Vec<Candidate> {
+        // Give the operator a key so that the line can be parsed.
+        let call = match current_call(&format!("{}x", before)) {
+            Some(call) => call,
+            None => return Vec::new(),
+        };
+
+        let table = match last_expr(&call) {
+            Some(Expr::MemberAccess(MemberAccess(base, _))) => match self.resolve(base) {
+                Some(value) => value.as_table(),
+                None => None,
+            },
+            _ => None,
+        };
+
+        let table = match table {
+            Some(table) => table,
+            None => return Vec::new(),
+        };
+
+        table.keys()
+            .filter_map(|key| key.as_utf8().map(String::from))
+            .filter(|key| key.starts_with(prefix))
+            .map(|key| Candidate {
+                complete: table.get(&key).as_table().is_none(),
+                text: key,
+            })
+            .collect()
+    }
+
+    /// Complete the name of a function or executable.
+    fn commands(&self, prefix: &str) -> Vec<Candidate> {
+        if prefix.contains('/') {
+            return paths(prefix, true);
+        }
+
+        let mut names = BTreeSet::new();
+
+        for table in &[&self.scope, self.fiber.globals()] {
+            for key in table.keys() {
+                match (table.get(&key), key.as_utf8()) {
+                    (Value::Block(_), Some(name)) | (Value::ForeignFn(_), Some(name)) if name.starts_with(prefix) => {
+                        names.insert(name.to_owned());
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(path) = env::var_os("PATH") {
+            for dir in env::split_paths(&path) {
+                for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+                    if let Some(name) = entry.file_name().to_str() {
+                        if name.starts_with(prefix) && is_executable(&entry.path()) {
+                            names.insert(name.to_owned());
+                        }
+                    }
+                }
+            }
+        }
+
+        names.into_iter()
+            .map(|name| Candidate {
+                text: name,
+                complete: true,
+            })
+            .collect()
+    }
+
+    /// Complete an argument to a call, using the completer registered for the
+    /// command if there is one.
+    async fn arguments(&mut self, before: &str, prefix: &str) -> Vec<Candidate> {
+        if let Some(Call::Named { function, args, .. }) = current_call(before) {
+            let completer = self.fiber.globals().get(COMPLETERS_GLOBAL).get(&function);
+
+            if !completer.is_nil() {
+                let args = args.iter()
+                    .filter_map(|arg| match arg {
+                        Expr::String(s) => Some(Value::from(s.as_str())),
+                        Expr::Number(n) => Some(Value::Number(*n)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+
+                match self.fiber.invoke(&completer, &[Value::from(prefix), Value::from(args)]).await {
+                    Ok(Value::List(items)) => {
+                        return items.iter()
+                            .map(Value::to_string)
+                            .filter(|item| item.starts_with(prefix))
+                            .map(|item| Candidate {
+                                text: item,
+                                complete: true,
+                            })
+                            .collect();
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("completer for {} failed: {}", function, e),
+                }
+            }
+        }
+
+        paths(prefix, false)
+    }
+
+    /// Find the value of an expression without running anything, for
+    /// variables, member accesses and modules that have already been required.
+    fn resolve(&self, expr: &Expr) -> Option<Value> {
+        let mut keys = Vec::new();
+        let mut expr = expr;
+
+        while let Expr::MemberAccess(MemberAccess(base, key)) = expr {
+            keys.push(key);
+            expr = base;
+        }
+
+        let mut value = match expr {
+            Expr::Substitution(Substitution::Variable(name)) => match self.scope.get(name) {
+                Value::Nil => self.fiber.globals().get(name),
+                value => value,
+            },
+            Expr::Pipeline(Pipeline(calls)) => match calls.as_slice() {
+                [Call::Named { function, args, .. }] if function == "require" => match args.as_slice() {
+                    [Expr::String(name)] => self.module(name)?,
+                    _ => return None,
+                },
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        for key in keys.into_iter().rev() {
+            value = value.get(key);
+        }
+
+        Some(value)
+    }
+
+    /// Get a module by name if it has already been loaded. Modules are never
+    /// loaded here, as that would run their code on every completion.
+    fn module(&self, name: &str) -> Option<Value> {
+        match self.fiber.globals().get("modules").get("loaded").get(name) {
+            Value::Nil => None,
+            value => Some(value),
+        }
+    }
+}
+
+/// Find where the word before the end of the text starts.
+///
+/// Words are delimited by whitespace and punctuation that cannot appear in an
+/// unquoted string.
+fn word_start(text: &str) -> usize {
+    text.char_indices()
+        .rev()
+        .find(|&(_, c)| c.is_whitespace() || "|;{}()[]<>'\"".contains(c))
+        .map(|(i, c)| i + c.len_utf8())
+        .unwrap_or(0)
+}
+
+/// Check whether a word following the given text would be the function of a
+/// call.
+fn is_command_position(before: &str) -> bool {
+    match before.trim_end_matches(&[' ', '\t'][..]).chars().last() {
+        None => true,
+        Some(c) => "|;{(\n".contains(c),
+    }
+}
+
+/// Parse a partial command line and find the innermost call that the end of the
+/// line is in.
+fn current_call(text: &str) -> Option<Call> {
+    let openers = unclosed_brackets(text)?;
+
+    let closers = openers.iter()
+        .rev()
+        .map(|opener| match opener {
+            '{' => '}',
+            '(' => ')',
+            _ => ']',
+        })
+        .collect::<String>();
+
+    let block = syntax::parse(SourceFile::named("<completion>", format!("{}{}", text, closers))).ok()?;
+    let mut call = block.statements.last()?.0.last()?.clone();
+
+    for opener in openers {
+        let pipeline = match (opener, last_expr(&call)?) {
+            ('{', Expr::Block(block)) => block.statements.last()?,
+            ('(', Expr::Pipeline(pipeline))
+            | ('(', Expr::Substitution(Substitution::Pipeline(pipeline)))
+            | ('(', Expr::ProcessSubstitution(ProcessSubstitution(pipeline))) => pipeline,
+            _ => return None,
+        };
+
+        call = pipeline.0.last()?.clone();
+    }
+
+    Some(call)
+}
+
+/// Get the last argument of a call, or the function if it has no arguments.
+fn last_expr(call: &Call) -> Option<&Expr> {
+    match call {
+        Call::Named { args, .. } => args.last(),
+        Call::Unnamed { function, args, .. } => args.last().or(Some(function)),
+    }
+}
+
+/// Find the brackets that are still open at the end of the text, in the order
+/// they were opened. Returns `None` if the text ends inside a string.
+fn unclosed_brackets(text: &str) -> Option<Vec<char>> {
+    let mut openers = Vec::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '(' | '[' => openers.push(c),
+            '}' | ')' | ']' => {
+                openers.pop();
+            }
+            '\'' | '"' => loop {
+                match chars.next() {
+                    Some('\\') => {
+                        chars.next();
+                    }
+                    Some(quote) if quote == c => break,
+                    Some(_) => {}
+                    None => return None,
+                }
+            },
+            _ => {}
+        }
+    }
+
+    Some(openers)
+}
+
+/// Complete a file path. If `commands` is true, only directories and
+/// executable files are included.
+fn paths(word: &str, commands: bool) -> Vec<Candidate> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word),
+    };
+
+    let search_dir = match (dir, home_dir()) {
+        ("", _) => PathBuf::from("."),
+        (dir, Some(home)) if dir.starts_with("~/") => home.join(&dir[2..]),
+        (dir, _) => PathBuf::from(dir),
+    };
+
+    let mut candidates = Vec::new();
+
+    for entry in fs::read_dir(search_dir).into_iter().flatten().flatten() {
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+
+        // Hidden files are only offered when asked for.
+        if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            continue;
+        }
+
+        let path = entry.path();
+        let is_dir = path.is_dir();
+
+        if commands && !is_dir && !is_executable(&path) {
+            continue;
+        }
+
+        let mut text = format!("{}{}", dir, name);
+        if is_dir {
+            text.push('/');
+        }
+
+        candidates.push(Candidate {
+            text: quote(text),
+            complete: !is_dir,
+        });
+    }
+
+    candidates
+}
+
+fn home_dir() -> Option<PathBuf> {
+    directories::BaseDirs::new().map(|dirs| dirs.home_dir().to_owned())
+}
+
+pub(super) fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+/// Wrap a word in single quotes if it contains characters that cannot appear in
+/// an unquoted string.
+fn quote(word: String) -> String {
+    let plain = word.chars().enumerate().all(|(i, c)| {
+        c.is_ascii_alphanumeric() || "_-?!./".contains(c) || (i == 0 && c == '~')
+    });
+
+    if plain {
+        word
+    } else {
+        format!("'{}'", word.replace('\'', "\\'"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::testing::{block_on, execute, fiber};
+
+    #[test]
+    fn words_end_at_the_cursor() {
+        assert_eq!(word_start("ls src/ma"), 3);
+        assert_eq!(word_start("echo $fs->gl"), 10);
+        assert_eq!(word_start("ls | gr"), 5);
+        assert_eq!(word_start(""), 0);
+    }
+
+    #[test]
+    fn command_positions() {
+        assert!(is_command_position(""));
+        assert!(is_command_position("ls | "));
+        assert!(is_command_position("each $list { "));
+        assert!(is_command_position("echo $("));
+        assert!(!is_command_position("ls "));
+    }
+
+    #[test]
+    fn innermost_call_is_found() {
+        let name = |text| match current_call(text) {
+            Some(Call::Named { function, .. }) => Some(function),
+            _ => None,
+        };
+
+        assert_eq!(name("git commit ").as_deref(), Some("git"));
+        assert_eq!(name("each $list { echo (ls ").as_deref(), Some("ls"));
+        assert_eq!(name("echo '{ (' ").as_deref(), Some("echo"));
+        assert_eq!(name("echo 'unclosed "), None);
+    }
+
+    #[test]
+    fn unusual_paths_are_quoted() {
+        assert_eq!(quote(String::from("~/src/main.rs")), "~/src/main.rs");
+        assert_eq!(quote(String::from("my file")), "'my file'");
+        assert_eq!(quote(String::from("it's")), "'it\\'s'");
+    }
+
+    #[test]
+    fn only_loaded_modules_are_completed() {
+        block_on(async {
+            let mut fiber = fiber();
+            let mut completer = Completer::new(fiber.fork(), table!());
+
+            let completions = completer.complete("echo (require string)->", 23).await;
+            assert!(completions.candidates.is_empty());
+            assert!(fiber.globals().get("modules").get("loaded").get("string").is_nil());
+
+            execute(&mut fiber, "require string").await.0.unwrap();
+
+            let completions = completer.complete("echo (require string)->", 23).await;
+            assert!(!completions.candidates.is_empty());
+        });
+    }
+}
@@ -1,114 +1,1361 @@
-use super::buffer::Buffer;
+use super::buffer::{display_width, Buffer};
 use crate::shell::{
     command::Command,
+    completion::{Candidate, Completer},
     event::Event,
+    highlight::Highlighter,
+    history::{CommandEntry, Cursor, History, Ranking},
+    keymap::{self, Binding, KEYMAPS_KEY},
+    layout::Layout,
     os::{TerminalInput, TerminalOutput},
+    plugins::{self, ON_PROMPT},
+    prompt::{self, LastCommand, PROMPT_KEY, RIGHT_PROMPT_KEY},
+    vi::{Mode, Outcome, Vi},
 };
+use crate::runtime::prelude::*;
+use crate::runtime::syntax::{self, source::SourceFile};
+use crate::stdlib::fs::change_dir;
 use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::env;
 use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::time::Duration;
 use tokio::io::{
     AsyncRead,
     AsyncWrite,
     AsyncWriteExt,
 };
+use tokio::signal::unix::{signal, Signal, SignalKind};
 
-/// The default prompt string if none is defined.
+/// Name of the global table holding editor settings.
+pub(crate) const EDITOR_GLOBAL: &str = "editor";
+
+/// The prompt used if none is set.
 const DEFAULT_PROMPT: &str = "$ ";
 
+/// The prompt shown at the start of each line after the first.
+const DEFAULT_CONTINUATION_PROMPT: &str = "> ";
+
+/// The most text the kill ring holds before forgetting the oldest.
+const KILL_RING_SIZE: usize = 32;
+
+/// Width of the terminal assumed if it can't be found out.
+const DEFAULT_COLUMNS: usize = 80;
+
+/// An editing command that can be bound to a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    AcceptLine,
+    CancelLine,
+    BackwardChar,
+    ForwardChar,
+    BackwardWord,
+    ForwardWord,
+    BeginningOfLine,
+    EndOfLine,
+    PreviousLine,
+    NextLine,
+    BackwardDeleteChar,
+    DeleteChar,
+    BackwardKillLine,
+    KillLine,
+    BackwardKillWord,
+    KillWord,
+    UnixWordRubout,
+    TransposeChars,
+    Yank,
+    YankPop,
+    Undo,
+    Redo,
+    Complete,
+    CompleteBackward,
+    HistorySearch,
+    PreviousDirectory,
+    NextDirectory,
+}
+
+/// The name of each action.
+pub(crate) const ACTIONS: &[(&str, Action)] = &[
+    ("accept-line", Action::AcceptLine),
+    ("cancel-line", Action::CancelLine),
+    ("backward-char", Action::BackwardChar),
+    ("forward-char", Action::ForwardChar),
+    ("backward-word", Action::BackwardWord),
+    ("forward-word", Action::ForwardWord),
+    ("beginning-of-line", Action::BeginningOfLine),
+    ("end-of-line", Action::EndOfLine),
+    ("previous-line", Action::PreviousLine),
+    ("next-line", Action::NextLine),
+    ("backward-delete-char", Action::BackwardDeleteChar),
+    ("delete-char", Action::DeleteChar),
+    ("backward-kill-line", Action::BackwardKillLine),
+    ("kill-line", Action::KillLine),
+    ("backward-kill-word", Action::BackwardKillWord),
+    ("kill-word", Action::KillWord),
+    ("unix-word-rubout", Action::UnixWordRubout),
+    ("transpose-chars", Action::TransposeChars),
+    ("yank", Action::Yank),
+    ("yank-pop", Action::YankPop),
+    ("undo", Action::Undo),
+    ("redo", Action::Redo),
+    ("complete", Action::Complete),
+    ("complete-backward", Action::CompleteBackward),
+    ("history-search", Action::HistorySearch),
+    ("previous-directory", Action::PreviousDirectory),
+    ("next-directory", Action::NextDirectory),
+];
+
+impl Action {
+    /// Look up an action by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTIONS.iter().find(|(n, _)| *n == name).map(|(_, action)| *action)
+    }
+
+    /// Get the name of this action.
+    pub fn name(self) -> &'static str {
+        ACTIONS.iter().find(|(_, action)| *action == self).unwrap().0
+    }
+
+    fn is_kill(self) -> bool {
+        [
+            Action::BackwardKillLine,
+            Action::KillLine,
+            Action::BackwardKillWord,
+            Action::KillWord,
+            Action::UnixWordRubout,
+        ].contains(&self)
+    }
+}
+
+/// Create the default editor settings table.
+pub fn default_settings() -> Table {
+    table! {
+        "mode" => "emacs",
+        KEYMAPS_KEY => keymap::default_keymaps(),
+    }
+}
+
 /// Controls the interactive command line editor.
 pub struct Editor<I, O: AsRawFd> {
     stdin: TerminalInput<I>,
     stdout: TerminalOutput<O>,
+
+    /// Fiber for running prompt functions and functions bound to keys.
+    fiber: Fiber,
+
+    history: History,
     buffer: Buffer,
+
+    /// The prompt shown before the first line of the command line, and the
+    /// prompt shown at the right edge of the terminal.
+    prompt: String,
+    right_prompt: String,
+
+    /// How the last command that was run finished, shown in prompts.
+    last_command: Option<LastCommand>,
+
+    navigation: Option<Navigation>,
+    search: Option<Search>,
+
+    /// The rest of a command from history that begins with the current line,
+    /// shown after the cursor as a suggestion.
+    suggestion: Option<String>,
+
+    directories: DirectoryHistory,
+    completer: Completer,
+    completion: Option<Completion>,
+    highlighter: Highlighter,
+    kill_ring: KillRing,
+
+    /// The action performed for the previous key, if any.
+    last_action: Option<Action>,
+
+    /// State of vi-style editing, if enabled.
+    vi: Option<Vi>,
+
+    /// Keys pressed so far that begin a longer bound sequence of keys.
+    chord: Vec<Event>,
+
+    /// Row of the terminal cursor in the command line as last drawn, counting
+    /// from the row of the prompt. Rows wrapped by the terminal are counted.
+    cursor_row: usize,
+
+    /// Number of rows in the command line as last drawn.
+    rows: usize,
+
+    /// Signals that the terminal has been resized, if they can be received.
+    window_change: Option<Signal>,
+}
+
+/// Candidates listed after pressing Tab, which further presses cycle through.
+struct Completion {
+    /// Position in the buffer where the word being completed starts.
+    start: usize,
+    candidates: Vec<Candidate>,
+
+    /// Index of the candidate currently inserted in the buffer, if any.
+    selected: Option<usize>,
+}
+
+/// Text removed by kill actions, the most recent first, for inserting again
+/// with yank.
+#[derive(Default)]
+struct KillRing {
+    entries: VecDeque<String>,
+
+    /// Where the last yanked text was inserted in the buffer, and its index in
+    /// the ring.
+    yanked: Option<(usize, usize)>,
+}
+
+impl KillRing {
+    /// Add killed text. If `merge` is true, it is joined onto the most recent
+    /// text instead, before it if `backward` is true.
+    fn push(&mut self, text: String, merge: bool, backward: bool) {
+        match self.entries.front_mut() {
+            Some(last) if merge => {
+                if backward {
+                    last.insert_str(0, &text);
+                } else {
+                    last.push_str(&text);
+                }
+            }
+            _ => {
+                self.entries.push_front(text);
+                self.entries.truncate(KILL_RING_SIZE);
+            }
+        }
+    }
+}
+
+/// Working directories visited during the session, for moving back and forward
+/// between them like in a web browser.
+#[derive(Default)]
+struct DirectoryHistory {
+    back: Vec<PathBuf>,
+    forward: Vec<PathBuf>,
+    current: Option<PathBuf>,
+}
+
+/// State of an in-progress navigation through command history.
+struct Navigation {
+    cursor: Cursor<CommandEntry>,
+
+    /// The line that was being edited when navigation started, restored once
+    /// the user navigates back past the most recent command.
+    draft: String,
+}
+
+/// State of an in-progress incremental history search.
+struct Search {
+    query: String,
+    ranking: Ranking,
+    cursor: Cursor<CommandEntry>,
+
+    /// The currently selected match, if any.
+    current: Option<CommandEntry>,
+
+    /// Whether the most recent attempt to find a match failed.
+    failed: bool,
+
+    /// The line that was being edited when the search started, restored if the
+    /// search is cancelled.
+    draft: String,
+}
+
+impl Search {
+    fn new(history: &History, draft: String) -> Self {
+        let mut cursor = history.search("", Ranking::Recency);
+        let current = cursor.next();
+
+        Self {
+            query: String::new(),
+            ranking: Ranking::Recency,
+            cursor,
+            failed: current.is_none(),
+            current,
+            draft,
+        }
+    }
+
+    /// Run the search again, selecting the highest ranked match.
+    fn restart(&mut self, history: &History) {
+        self.cursor = history.search(&self.query, self.ranking);
+        self.current = self.cursor.next();
+        self.failed = self.current.is_none();
+    }
+
+    /// Select the next lower ranked match.
+    fn next(&mut self) {
+        match self.cursor.next() {
+            Some(entry) => {
+                self.current = Some(entry);
+                self.failed = false;
+            }
+            None => self.failed = true,
+        }
+    }
+
+    /// Select the next higher ranked match.
+    fn prev(&mut self) {
+        match self.cursor.prev() {
+            Some(entry) => {
+                self.current = Some(entry);
+                self.failed = false;
+            }
+            // Already at the highest ranked match, so move back onto it.
+            None => {
+                self.cursor.next();
+            }
+        }
+    }
 }
 
 impl<I, O: AsRawFd> Editor<I, O> {
-    pub fn new(stdin: I, stdout: O) -> Self {
+    pub fn new(stdin: I, stdout: O, fiber: Fiber, history: History, completer: Completer, highlighter: Highlighter) -> Self {
         Self {
             stdin: TerminalInput::new(stdin),
             stdout: TerminalOutput::new(stdout).unwrap(),
+            fiber,
+            history,
             buffer: Buffer::new(),
+            prompt: String::from(DEFAULT_PROMPT),
+            right_prompt: String::new(),
+            last_command: None,
+            navigation: None,
+            search: None,
+            suggestion: None,
+            directories: DirectoryHistory::default(),
+            completer,
+            completion: None,
+            highlighter,
+            kill_ring: KillRing::default(),
+            last_action: None,
+            vi: None,
+            chord: Vec::new(),
+            cursor_row: 0,
+            rows: 1,
+            window_change: signal(SignalKind::window_change()).ok(),
+        }
+    }
+
+    /// Get the width of the terminal.
+    fn columns(&self) -> usize {
+        match self.stdout.size() {
+            Ok((columns, _)) if columns > 0 => columns,
+            _ => DEFAULT_COLUMNS,
+        }
+    }
+
+    /// Get the command history used by this editor.
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Record how the command line last returned by `read_line` finished, for
+    /// showing in the next prompt.
+    pub fn command_finished(&mut self, exit_status: i32, duration: Duration) {
+        self.last_command = Some(LastCommand {
+            exit_status,
+            duration,
+        });
+    }
+
+    fn default_prompt(&self) -> String {
+        // Show the current mode when editing in vi mode.
+        match self.vi.as_ref().map(Vi::mode) {
+            Some(Mode::Insert) => format!("[I] {}", DEFAULT_PROMPT),
+            Some(Mode::Normal) => format!("[N] {}", DEFAULT_PROMPT),
+            Some(Mode::Visual) => format!("[V] {}", DEFAULT_PROMPT),
+            None => String::from(DEFAULT_PROMPT),
+        }
+    }
+
+    /// Switch between vi and the default key bindings according to the
+    /// `mode` editor setting, and start a new command line in insert mode.
+    fn configure_mode(&mut self) {
+        let settings = self.fiber.globals().get(EDITOR_GLOBAL);
+
+        if settings.get("mode") == "vi" {
+            self.vi.get_or_insert_with(Vi::new).reset();
+        } else {
+            self.vi = None;
         }
+
+        self.publish_mode();
     }
 
-    fn get_prompt_str(&self) -> Cow<'static, str> {
-        // match interpreter::function_call(PROMPT_FUNCTION, &[], &mut Streams::null()) {
-        //     Ok(Expression::Atom(s)) => s,
-        //     _ => Cow::Borrowed(DEFAULT_PROMPT),
-        // }
+    /// Set the `vi-mode` editor setting to the name of the current vi mode, so
+    /// that prompts can show it.
+    fn publish_mode(&self) {
+        if let Some(settings) = self.fiber.globals().get(EDITOR_GLOBAL).as_table() {
+            settings.set("vi-mode", match self.vi.as_ref() {
+                Some(vi) => Value::from(vi.mode().name()),
+                None => Value::Nil,
+            });
+        }
+    }
+
+    /// Get the name of the keymap for the current editing mode.
+    fn keymap_name(&self) -> &'static str {
+        match self.vi.as_ref().map(Vi::mode) {
+            Some(Mode::Insert) => "vi-insert",
+            Some(Mode::Normal) | Some(Mode::Visual) => "vi-normal",
+            None => "emacs",
+        }
+    }
 
-        Cow::Borrowed(DEFAULT_PROMPT)
+    fn get_continuation_prompt_str(&self) -> Cow<'static, str> {
+        Cow::Borrowed(DEFAULT_CONTINUATION_PROMPT)
+    }
+
+    /// Check whether the buffer is the start of a command line that isn't
+    /// finished yet, such as one with an unclosed block or string.
+    fn is_incomplete(&self) -> bool {
+        match syntax::parse(SourceFile::named("<input>", self.buffer.text().to_owned())) {
+            Ok(_) => false,
+            Err(e) => e.is_incomplete(),
+        }
     }
 }
 
-impl<I: AsyncRead + Unpin, O: AsyncWrite + AsRawFd + Unpin> Editor<I, O> {
+impl<I: AsyncRead + AsRawFd + Unpin, O: AsyncWrite + AsRawFd + Unpin> Editor<I, O> {
     /// Show a command prompt to the user and await for the user to input a
     /// command. The typed command is returned once submitted.
     pub async fn read_line(&mut self) -> String {
-        let prompt = self.get_prompt_str();
-        self.stdout.write_all(prompt.as_bytes()).await.unwrap();
-        self.stdout.flush().await.unwrap();
+        self.configure_mode();
+
+        // Changing the directory by running a command clears the directories
+        // to move forward to, the same as following a link in a browser.
+        if let Ok(cwd) = env::current_dir() {
+            if self.directories.current.as_ref() != Some(&cwd) {
+                if let Some(previous) = self.directories.current.replace(cwd) {
+                    self.directories.back.push(previous);
+                }
+                self.directories.forward.clear();
+            }
+        }
+
+        let context = self.prompt_context();
+        plugins::run_hook(&mut self.fiber, ON_PROMPT, &[Value::from(context)]).await;
 
-        // Enter raw mode.
+        let header = self.update_prompt().await;
+        self.stdout.write_all(header.as_bytes()).await.unwrap();
+
+        // Enter raw mode, and stop reading from blocking so that resizes are
+        // noticed while waiting for keys.
         self.stdout.set_raw_mode(true).unwrap();
+        self.stdin.set_nonblocking(true).unwrap();
+        self.stdout.command(Command::EnableBracketedPaste).await.unwrap();
+        self.redraw().await;
 
-        // Handle keyboard events.
-        while let Ok(event) = self.stdin.next_event().await {
-            match event {
-                Event::Char('\n') => {
-                    self.stdout.write_all(b"\r\n").await.unwrap();
-                    break;
+        // Handle keyboard events, and redraw when the terminal is resized.
+        loop {
+            let mut event = tokio::select! {
+                event = self.stdin.next_event() => match event {
+                    Ok(event) => event,
+                    Err(_) => break,
+                },
+                _ = window_changed(&mut self.window_change) => {
+                    self.resize().await;
+                    continue;
                 }
-                Event::Left => {
-                    self.buffer.move_cursor_relative(-1);
+            };
+
+            if let Event::Paste(text) = event {
+                self.paste(text);
+                self.update_suggestion();
+                self.redraw().await;
+                continue;
+            }
+
+            if self.search.is_some() {
+                match self.search_event(event) {
+                    // The search has ended, so show the resulting buffer before
+                    // handling the event as usual.
+                    Some(unhandled) => {
+                        self.redraw().await;
+                        event = unhandled;
+                    }
+                    None => {
+                        self.redraw().await;
+                        continue;
+                    }
                 }
-                Event::Right => {
-                    self.buffer.move_cursor_relative(1);
+            }
+
+            let mode = self.vi.as_ref().map(Vi::mode);
+
+            if self.handle_key(event).await {
+                break;
+            }
+
+            self.publish_mode();
+
+            if self.vi.as_ref().map(Vi::mode) != mode {
+                self.update_prompt().await;
+            }
+            self.update_suggestion();
+            self.redraw().await;
+        }
+
+        self.finish_line().await
+    }
+
+    /// Put the terminal back the way it was if reading a line is abandoned part
+    /// way through, such as when the shell has to exit.
+    pub async fn close(&mut self) {
+        self.move_below_input().await;
+        self.search = None;
+        self.completion = None;
+        self.finish_line().await;
+        self.stdout.flush().await.unwrap();
+    }
+
+    /// Leave the terminal modes used while reading a line, and take the line
+    /// that was read.
+    async fn finish_line(&mut self) -> String {
+        self.stdout.command(Command::DisableBracketedPaste).await.unwrap();
+        self.stdin.set_nonblocking(false).unwrap();
+        self.stdout.set_raw_mode(false).unwrap();
+        self.navigation = None;
+        self.last_action = None;
+        self.chord.clear();
+
+        // Move the command line out of out buffer and return it.
+        self.buffer.take_text()
+    }
+
+    /// Render the prompts from the `prompt` and `right-prompt` editor settings.
+    ///
+    /// The command line is drawn after the last line of the prompt, so any
+    /// lines before it are returned to be shown once instead.
+    async fn update_prompt(&mut self) -> String {
+        let settings = self.fiber.globals().get(EDITOR_GLOBAL);
+
+        let prompt = self.render_prompt(settings.get(PROMPT_KEY)).await;
+        self.prompt = prompt.unwrap_or_else(|| self.default_prompt());
+        self.right_prompt = self.render_prompt(settings.get(RIGHT_PROMPT_KEY)).await.unwrap_or_default();
+
+        match self.prompt.rfind('\n') {
+            Some(index) => self.prompt.drain(..=index).collect::<String>().replace('\n', "\r\n"),
+            None => String::new(),
+        }
+    }
+
+    /// Create the table of information about the session passed to prompt
+    /// functions.
+    fn prompt_context(&self) -> Table {
+        let cwd = env::current_dir().ok();
+        let vi_mode = self.vi.as_ref().map(|vi| vi.mode().name());
+
+        prompt::context(self.last_command, cwd.as_deref(), vi_mode)
+    }
+
+    /// Render a prompt setting, which is either a string or a function called
+    /// with information about the session. The prompt is what the function
+    /// prints, or what it returns if it prints nothing.
+    async fn render_prompt(&mut self, prompt: Value) -> Option<String> {
+        match prompt {
+            Value::Nil => None,
+            Value::Block(_) | Value::ForeignFn(_) => {
+                let context = self.prompt_context();
+
+                match self.fiber.invoke_captured(&prompt, &[Value::from(context)]).await {
+                    Ok(Value::Nil) => None,
+                    Ok(value) => Some(value.to_string()),
+                    Err(e) => {
+                        log::error!("prompt function failed: {}", e);
+                        None
+                    }
                 }
-                Event::Home => {
-                    self.buffer.move_to_start_of_line();
+            }
+            value => Some(value.to_string()),
+        }
+    }
+
+    /// Handle a key pressed while editing, looking up what it does in the
+    /// keymap for the current mode. Returns true if the key submits the command
+    /// line.
+    async fn handle_key(&mut self, event: Event) -> bool {
+        let mut events = VecDeque::from(vec![event]);
+
+        while let Some(event) = events.pop_front() {
+            // Keys that continue a vi command are never looked up.
+            if self.vi.as_ref().map(Vi::is_pending).unwrap_or(false) {
+                if self.handle_default(event).await {
+                    return true;
                 }
-                Event::End => {
-                    self.buffer.move_to_end_of_line();
+                continue;
+            }
+
+            self.chord.push(event);
+
+            let keymap = self.fiber.globals()
+                .get(EDITOR_GLOBAL)
+                .get(KEYMAPS_KEY)
+                .get(self.keymap_name());
+
+            match keymap::lookup(&keymap, &self.chord) {
+                Binding::Found(binding) => {
+                    self.chord.clear();
+                    if self.run_binding(binding).await {
+                        return true;
+                    }
                 }
-                Event::Char(c) => {
+                Binding::Prefix => {}
+                // Keys that turn out not to be bound together are handled one
+                // at a time instead. The first key is not bound on its own, but
+                // the keys after it are looked up again.
+                Binding::Unbound => {
+                    let mut chord = std::mem::take(&mut self.chord).into_iter();
+                    let first = chord.next().unwrap();
+
+                    for event in chord.rev() {
+                        events.push_front(event);
+                    }
+
+                    if self.handle_default(first).await {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Handle a key that is not bound in the keymap. Typed characters are
+    /// inserted, and in vi mode keys are vi commands.
+    async fn handle_default(&mut self, event: Event) -> bool {
+        let outcome = match (self.vi.as_mut(), &event) {
+            // Escape quickly followed by another key arrives as the key pressed
+            // with Alt.
+            (Some(vi), Event::Alt(c)) if !vi.is_pending() => {
+                vi.handle(Event::Escape, &mut self.buffer);
+                vi.handle(Event::Char(*c), &mut self.buffer)
+            }
+            (Some(vi), _) => vi.handle(event.clone(), &mut self.buffer),
+            (None, _) => Outcome::Unhandled,
+        };
+
+        match outcome {
+            Outcome::Action(action) => return self.perform(action).await,
+            Outcome::Invalid => self.bell().await,
+            Outcome::Unhandled => {
+                if let Event::Char(c) = event {
                     self.buffer.insert_char(c);
                 }
-                Event::Backspace => {
-                    self.buffer.delete_before_cursor();
+            }
+            Outcome::Handled => {}
+        }
+
+        self.completion = None;
+        self.last_action = None;
+        false
+    }
+
+    /// Run what a key is bound to, either the name of an action or a function.
+    /// Returns true if the command line is submitted.
+    async fn run_binding(&mut self, binding: Value) -> bool {
+        let action = match binding {
+            Value::Block(_) | Value::ForeignFn(_) => match self.call_binding(&binding).await {
+                Ok(Value::Nil) => None,
+                Ok(value) => Some(value.to_string()),
+                Err(e) => {
+                    self.move_below_input().await;
+                    log::error!("{}", e);
+                    None
                 }
-                Event::Delete => {
-                    self.buffer.delete_after_cursor();
+            },
+            value => Some(value.to_string()),
+        };
+
+        match action.as_ref().map(|name| (name, Action::from_name(name))) {
+            Some((_, Some(action))) => self.perform(action).await,
+            Some((name, None)) => {
+                self.move_below_input().await;
+                log::error!("unknown editor action: {}", name);
+                false
+            }
+            None => {
+                self.completion = None;
+                self.last_action = None;
+                false
+            }
+        }
+    }
+
+    /// Call a function bound to a key with a table holding the `text` of the
+    /// buffer and the `cursor` position in it. Changes the function makes to
+    /// the table are made to the buffer afterwards.
+    async fn call_binding(&mut self, function: &Value) -> Result<Value, Exception> {
+        let line = table! {
+            "text" => self.buffer.text(),
+            "cursor" => self.buffer.cursor() as f64,
+        };
+
+        let result = self.fiber.invoke(function, &[Value::from(line.clone())]).await?;
+
+        let text = line.get("text").to_string();
+        if text != self.buffer.text() {
+            self.buffer.begin_change();
+            self.buffer.clear();
+            self.buffer.insert_str(text);
+            self.buffer.end_change();
+        }
+
+        if let Some(cursor) = line.get("cursor").as_number() {
+            self.buffer.move_cursor_to((cursor.max(0.0) as usize).min(self.buffer.text().len()));
+        }
+
+        if let Some(vi) = self.vi.as_ref() {
+            vi.adjust_cursor(&mut self.buffer);
+        }
+
+        Ok(result)
+    }
+
+    /// Perform an editing action. Returns true if the action submits the
+    /// command line.
+    async fn perform(&mut self, action: Action) -> bool {
+        if ![Action::Complete, Action::CompleteBackward].contains(&action) {
+            self.completion = None;
+        }
+
+        let accepted = self.apply(action).await;
+
+        self.last_action = Some(action);
+
+        if let Some(vi) = self.vi.as_ref() {
+            vi.adjust_cursor(&mut self.buffer);
+        }
+
+        accepted
+    }
+
+    /// Make the changes for an action. Returns true if the action submits the
+    /// command line.
+    async fn apply(&mut self, action: Action) -> bool {
+        match action {
+            // Keep editing on a new line until the command line is finished.
+            Action::AcceptLine if self.is_incomplete() => {
+                self.buffer.insert_char('\n');
+            }
+            Action::AcceptLine => {
+                // Remove any suggestion from the screen before running.
+                if self.suggestion.take().is_some() {
+                    self.redraw().await;
+                }
+
+                self.move_below_input().await;
+                return true;
+            }
+            Action::CancelLine => {
+                self.buffer.clear();
+            }
+            Action::BackwardChar => {
+                self.buffer.move_cursor_relative(-1);
+            }
+            Action::ForwardChar | Action::EndOfLine if self.suggestion.is_some() => {
+                self.accept_suggestion(false);
+            }
+            Action::ForwardChar => {
+                self.buffer.move_cursor_relative(1);
+            }
+            Action::BackwardWord => {
+                let pos = self.buffer.previous_word_start(false);
+                self.buffer.move_cursor_to(pos);
+            }
+            Action::ForwardWord if self.suggestion.is_some() => {
+                self.accept_suggestion(true);
+            }
+            Action::ForwardWord => {
+                let pos = self.buffer.next_word_end();
+                self.buffer.move_cursor_to(pos);
+            }
+            Action::BeginningOfLine => {
+                self.buffer.move_to_start_of_line();
+            }
+            Action::EndOfLine => {
+                self.buffer.move_to_end_of_line();
+            }
+            Action::PreviousLine => {
+                self.move_vertically(false);
+            }
+            Action::NextLine => {
+                self.move_vertically(true);
+            }
+            Action::BackwardDeleteChar => {
+                self.buffer.delete_before_cursor();
+            }
+            Action::DeleteChar => {
+                self.buffer.delete_after_cursor();
+            }
+            Action::BackwardKillLine => {
+                self.kill(self.buffer.line_start(), true);
+            }
+            Action::KillLine => {
+                self.kill(self.buffer.line_end(), false);
+            }
+            Action::BackwardKillWord => {
+                self.kill(self.buffer.previous_word_start(false), true);
+            }
+            Action::KillWord => {
+                self.kill(self.buffer.next_word_end(), false);
+            }
+            Action::UnixWordRubout => {
+                self.kill(self.buffer.previous_word_start(true), true);
+            }
+            Action::TransposeChars => {
+                self.buffer.transpose();
+            }
+            Action::Yank => {
+                if let Some(text) = self.kill_ring.entries.front() {
+                    self.kill_ring.yanked = Some((self.buffer.cursor(), 0));
+                    self.buffer.insert_str(text);
+                }
+            }
+            // Replace the text just yanked with the next older killed text.
+            Action::YankPop => match self.kill_ring.yanked {
+                Some((start, index)) if [Some(Action::Yank), Some(Action::YankPop)].contains(&self.last_action) => {
+                    let index = (index + 1) % self.kill_ring.entries.len();
+                    self.kill_ring.yanked = Some((start, index));
+                    self.buffer.replace_before_cursor(start, &self.kill_ring.entries[index]);
+                }
+                _ => self.bell().await,
+            },
+            Action::Undo => {
+                if !self.buffer.undo() {
+                    self.bell().await;
+                }
+            }
+            Action::Redo => {
+                if !self.buffer.redo() {
+                    self.bell().await;
+                }
+            }
+            Action::Complete => {
+                self.complete(true).await;
+            }
+            Action::CompleteBackward => {
+                self.complete(false).await;
+            }
+            Action::HistorySearch => {
+                self.navigation = None;
+                self.search = Some(Search::new(&self.history, self.buffer.text().to_owned()));
+            }
+            Action::PreviousDirectory => {
+                self.change_directory(false).await;
+            }
+            Action::NextDirectory => {
+                self.change_directory(true).await;
+            }
+        }
+
+        false
+    }
+
+    /// Remove the text between the cursor and the given position into the kill
+    /// ring. Text killed by consecutive kill actions is joined together.
+    fn kill(&mut self, pos: usize, backward: bool) {
+        let cursor = self.buffer.cursor();
+        let text = if backward {
+            self.buffer.remove(pos..cursor)
+        } else {
+            self.buffer.remove(cursor..pos)
+        };
+
+        if !text.is_empty() {
+            let merge = self.last_action.map(Action::is_kill).unwrap_or(false);
+            self.kill_ring.push(text, merge, backward);
+        }
+    }
+
+    /// Ring the terminal bell.
+    async fn bell(&mut self) {
+        self.stdout.write_all(b"\x07").await.unwrap();
+    }
+
+    /// Insert pasted text at the cursor, or add it to the query while searching
+    /// history. Nothing in it is looked up in the keymap, so a pasted line
+    /// break doesn't run the command line.
+    fn paste(&mut self, text: String) {
+        if let Some(search) = self.search.as_mut() {
+            search.query.push_str(&text);
+            search.restart(&self.history);
+            return;
+        }
+
+        self.chord.clear();
+        self.completion = None;
+        self.last_action = None;
+
+        // Pasting in insert mode is part of the change that vi repeats.
+        match self.vi.as_mut() {
+            Some(vi) if vi.mode() == Mode::Insert => {
+                vi.handle(Event::Paste(text), &mut self.buffer);
+            }
+            _ => self.buffer.paste(&text),
+        }
+    }
+
+    /// Handle an input event during an incremental history search.
+    ///
+    /// If the event ends the search and should also be handled as a normal
+    /// editing event, it is returned.
+    fn search_event(&mut self, event: Event) -> Option<Event> {
+        let search = self.search.as_mut()?;
+
+        match event {
+            Event::Char('\n') => {}
+            Event::Char(c) => {
+                search.query.push(c);
+                search.restart(&self.history);
+                return None;
+            }
+            Event::Backspace => {
+                search.query.pop();
+                search.restart(&self.history);
+                return None;
+            }
+            Event::Ctrl('r') => {
+                search.next();
+                return None;
+            }
+            Event::Ctrl('s') => {
+                search.prev();
+                return None;
+            }
+            Event::Ctrl('t') => {
+                search.ranking = search.ranking.next();
+                search.restart(&self.history);
+                return None;
+            }
+            // Cancel the search and restore the original line.
+            Event::Ctrl('c') | Event::Ctrl('g') => {
+                let search = self.search.take().unwrap();
+                self.buffer.clear();
+                self.buffer.insert_str(search.draft);
+                return None;
+            }
+            _ => {}
+        }
+
+        // Any other key accepts the current match into the buffer.
+        let search = self.search.take().unwrap();
+        self.buffer.clear();
+        self.buffer.insert_str(match search.current {
+            Some(entry) => entry.command().to_owned(),
+            None => search.draft,
+        });
+
+        Some(event)
+    }
+
+    /// Look up a suggestion for completing the current line from history.
+    ///
+    /// Suggestions are only offered while the cursor is at the end of a
+    /// non-empty line, and never during a search.
+    fn update_suggestion(&mut self) {
+        let text = self.buffer.text();
+
+        self.suggestion = if self.search.is_none() && !text.is_empty() && self.buffer.cursor() == text.len() {
+            self.history.frequent_commands_starting_with(text)
+                .next()
+                .map(|summary| summary.command()[text.len()..].to_owned())
+                .filter(|suggestion| !suggestion.contains('\n'))
+        } else {
+            None
+        };
+    }
+
+    /// Insert the current suggestion into the buffer. If `word` is true, only
+    /// the next word of the suggestion is inserted.
+    fn accept_suggestion(&mut self, word: bool) {
+        if let Some(suggestion) = self.suggestion.take() {
+            let len = if word {
+                let start = suggestion.len() - suggestion.trim_start().len();
+                suggestion[start..].find(char::is_whitespace)
+                    .map(|end| start + end)
+                    .unwrap_or_else(|| suggestion.len())
+            } else {
+                suggestion.len()
+            };
+
+            self.buffer.move_to_end_of_line();
+            self.buffer.insert_str(&suggestion[..len]);
+        }
+    }
+
+    /// Complete the word before the cursor.
+    ///
+    /// A single candidate replaces the word. If there are several, the word is
+    /// extended to their longest common prefix, or if that adds nothing, the
+    /// candidates are listed below the line. Once listed, each press inserts
+    /// the next candidate, or the previous one if `forward` is false.
+    async fn complete(&mut self, forward: bool) {
+        if let Some(completion) = self.completion.as_mut() {
+            let count = completion.candidates.len();
+            let selected = match completion.selected {
+                Some(i) if forward => (i + 1) % count,
+                Some(i) => (i + count - 1) % count,
+                None if forward => 0,
+                None => count - 1,
+            };
+
+            completion.selected = Some(selected);
+            self.buffer.replace_before_cursor(completion.start, &completion.candidates[selected].text);
+            return;
+        }
+
+        let cursor = self.buffer.cursor();
+        let completions = self.completer.complete(self.buffer.text(), cursor).await;
+        let start = completions.start;
+
+        match completions.candidates.as_slice() {
+            [] => {
+                self.bell().await;
+            }
+            [candidate] => {
+                self.buffer.replace_before_cursor(start, &candidate.text);
+                if candidate.complete {
+                    self.buffer.insert_char(' ');
+                }
+            }
+            candidates => {
+                let prefix = common_prefix(candidates);
+
+                if prefix.len() > cursor - start {
+                    self.buffer.replace_before_cursor(start, prefix);
+                } else {
+                    self.list_candidates(candidates).await;
+                    self.completion = Some(Completion {
+                        start,
+                        candidates: completions.candidates,
+                        selected: None,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Print completion candidates in columns below the command line.
+    async fn list_candidates(&mut self, candidates: &[Candidate]) {
+        let width = self.columns();
+        let column_width = candidates.iter().map(|c| display_width(&c.text)).max().unwrap_or(0) + 2;
+        let columns = (width / column_width).max(1);
+        let rows = candidates.len().div_ceil(columns);
+
+        self.move_below_input().await;
+
+        let mut output = String::new();
+
+        for row in 0..rows {
+            let line = (0..columns)
+                .filter_map(|column| candidates.get(column * rows + row))
+                .map(|candidate| {
+                    let padding = column_width - display_width(&candidate.text);
+                    format!("{}{}", candidate.text, " ".repeat(padding))
+                })
+                .collect::<String>();
+
+            output.push_str(line.trim_end());
+            output.push_str("\r\n");
+        }
+
+        self.stdout.write_all(output.as_bytes()).await.unwrap();
+    }
+
+    /// Change to the previous working directory, or to the next one if
+    /// `forward` is true, and show the new directory above the command line.
+    ///
+    /// Directories that can no longer be changed to are skipped.
+    async fn change_directory(&mut self, forward: bool) {
+        let directories = &mut self.directories;
+
+        let (from, to) = if forward {
+            (&mut directories.forward, &mut directories.back)
+        } else {
+            (&mut directories.back, &mut directories.forward)
+        };
+
+        while let Some(path) = from.pop() {
+            if change_dir(&path).is_ok() {
+                if let Some(current) = directories.current.replace(path.clone()) {
+                    to.push(current);
                 }
-                Event::Ctrl('c') => {
+
+                self.history.add_directory(path.to_string_lossy());
+
+                self.clear_input().await;
+                self.stdout.write_all(format!("{}\r\n", path.display()).as_bytes()).await.unwrap();
+                return;
+            }
+        }
+    }
+
+    /// Move the cursor to the previous line of the buffer, or to the next line
+    /// if `down` is true. Moving past the first or last line browses history
+    /// instead.
+    fn move_vertically(&mut self, down: bool) {
+        if self.buffer.move_to_adjacent_line(down) {
+            return;
+        }
+
+        if down {
+            self.history_newer();
+        } else {
+            self.history_older();
+        }
+    }
+
+    /// Replace the buffer with the next older command in history, skipping
+    /// commands identical to the one currently shown.
+    fn history_older(&mut self) {
+        let history = &self.history;
+        let buffer = &self.buffer;
+
+        let navigation = self.navigation.get_or_insert_with(|| Navigation {
+            cursor: history.command_history(),
+            draft: buffer.text().to_owned(),
+        });
+
+        for entry in &mut navigation.cursor {
+            if entry.command() != self.buffer.text() {
+                self.buffer.clear();
+                self.buffer.insert_str(entry.command());
+                break;
+            }
+        }
+    }
+
+    /// Replace the buffer with the next newer command in history, or restore
+    /// the line that was being edited once past the most recent command.
+    fn history_newer(&mut self) {
+        if let Some(navigation) = self.navigation.as_mut() {
+            while let Some(entry) = navigation.cursor.prev() {
+                if entry.command() != self.buffer.text() {
                     self.buffer.clear();
+                    self.buffer.insert_str(entry.command());
+                    return;
                 }
-                _ => {}
             }
 
-            self.redraw().await;
+            let draft = self.navigation.take().unwrap().draft;
+            self.buffer.clear();
+            self.buffer.insert_str(draft);
         }
+    }
 
-        self.stdout.set_raw_mode(false).unwrap();
+    /// Move the terminal cursor back to the start of the command line and clear
+    /// everything after it.
+    async fn clear_input(&mut self) {
+        if self.cursor_row > 0 {
+            self.stdout.command(Command::MoveCursorUp(self.cursor_row)).await.unwrap();
+        }
 
-        // Move the command line out of out buffer and return it.
-        self.buffer.take_text()
+        self.stdout.write_all(b"\r").await.unwrap();
+        self.stdout.command(Command::ClearAfterCursor).await.unwrap();
+
+        self.cursor_row = 0;
+        self.rows = 1;
+    }
+
+    /// Move the terminal cursor to the start of the row below the command line,
+    /// leaving the command line as it was last drawn.
+    async fn move_below_input(&mut self) {
+        let rows_down = self.rows - 1 - self.cursor_row;
+
+        if rows_down > 0 {
+            self.stdout.command(Command::MoveCursorDown(rows_down)).await.unwrap();
+        }
+
+        self.stdout.write_all(b"\r\n").await.unwrap();
+
+        self.cursor_row = 0;
+        self.rows = 1;
+    }
+
+    /// Draw the right prompt at the end of the first row of the command line,
+    /// unless the command line reaches it. The terminal cursor is left at the
+    /// start of the row.
+    async fn draw_right_prompt(&mut self) {
+        let columns = self.columns();
+        let width = display_width(&self.right_prompt);
+
+        let text = self.buffer.text();
+        let mut used = display_width(&self.prompt) + display_width(text.split('\n').next().unwrap_or(""));
+        if !text.contains('\n') {
+            used += self.suggestion.as_ref().map_or(0, |suggestion| display_width(suggestion));
+        }
+
+        // Keep a space before the right prompt, and leave the last column
+        // empty so that the terminal doesn't wrap onto the next row.
+        if used + 1 + width + 1 > columns {
+            return;
+        }
+
+        self.stdout.command(Command::MoveCursorRight(columns - width - 1)).await.unwrap();
+        self.stdout.write_all(self.right_prompt.as_bytes()).await.unwrap();
+        self.stdout.write_all(b"\r").await.unwrap();
+    }
+
+    /// Draw the command line again after the terminal is resized.
+    async fn resize(&mut self) {
+        // Most terminals wrap the rows already drawn again to fit the new
+        // width, so find the row the cursor has moved to before clearing them.
+        let view = self.view();
+        self.cursor_row = Layout::of(&view.before_cursor, self.columns()).position().row;
+
+        self.redraw().await;
+    }
+
+    /// Get what the command line looks like, split at the cursor.
+    fn view(&self) -> View {
+        if let Some(search) = self.search.as_ref() {
+            let command = search.current.as_ref().map(CommandEntry::command).unwrap_or_default();
+
+            let line = format!(
+                "({}search {}) '{}': {}",
+                if search.failed { "failed " } else { "" },
+                search.ranking,
+                search.query,
+                command,
+            );
+
+            // Place the cursor at the start of the matched text.
+            let cursor = match command.find(search.query.as_str()) {
+                Some(index) => line.len() - command.len() + index,
+                None => line.len(),
+            };
+
+            return View {
+                before_cursor: line[..cursor].to_owned(),
+                after_cursor: line[cursor..].to_owned(),
+                output: line,
+            };
+        }
+
+        let continuation = self.get_continuation_prompt_str();
+        let with_continuation = |text: &str| text.replace('\n', &format!("\n{}", continuation));
+
+        // Show the visual mode selection in reverse video instead of
+        // highlighting syntax.
+        let text = self.buffer.text();
+        let line = match self.vi.as_ref().and_then(|vi| vi.selection(&self.buffer)) {
+            Some(selection) => format!(
+                "{}\x1b[7m{}\x1b[0m{}",
+                &text[..selection.start],
+                text[selection.clone()].replace('\n', "\x1b[0m\n\x1b[7m"),
+                &text[selection.end..],
+            ),
+            None => self.highlighter.highlight(text),
+        }.replace('\n', &format!("\r\n{}", continuation));
+
+        let cursor = self.buffer.cursor();
+
+        View {
+            output: format!("{}{}", self.prompt, line),
+            before_cursor: format!("{}{}", self.prompt, with_continuation(&text[..cursor])),
+            after_cursor: format!(
+                "{}{}",
+                with_continuation(&text[cursor..]),
+                self.suggestion.as_deref().unwrap_or(""),
+            ),
+        }
     }
 
     /// Redraw the buffer.
     pub async fn redraw(&mut self) {
-        let prompt = self.get_prompt_str();
+        let view = self.view();
+        self.clear_input().await;
+
+        if self.search.is_none() && !self.right_prompt.is_empty() {
+            self.draw_right_prompt().await;
+        }
+
+        self.stdout.write_all(view.output.as_bytes()).await.unwrap();
+
+        if self.search.is_none() {
+            if let Some(suggestion) = self.suggestion.as_ref() {
+                self.stdout.command(Command::SetDim).await.unwrap();
+                self.stdout.write_all(suggestion.as_bytes()).await.unwrap();
+                self.stdout.command(Command::ResetStyle).await.unwrap();
+            }
+        }
+
+        // Work out where the cursor belongs and where drawing ended, counting
+        // the rows that the terminal wrapped.
+        let columns = self.columns();
+        let cursor = Layout::of(&view.before_cursor, columns);
+        let mut end = cursor;
+        end.advance(&view.after_cursor);
+
+        // If the last row was filled to the edge, move onto the next row so
+        // that the terminal cursor is where the layout expects.
+        if end.is_wrap_pending() {
+            self.stdout.write_all(b"\r\n").await.unwrap();
+        }
+
+        let rows_up = end.position().row - cursor.position().row;
+
+        if rows_up > 0 {
+            self.stdout.command(Command::MoveCursorUp(rows_up)).await.unwrap();
+        }
+
         self.stdout.write_all(b"\r").await.unwrap();
-        self.stdout.command(Command::ClearAfterCursor).await.unwrap();
-        self.stdout.write_all(format!("{}{}", prompt, self.buffer.text()).as_bytes()).await.unwrap();
 
-        // Update the cursor position.
-        let diff = self.buffer.text().len() - self.buffer.cursor();
-        if diff > 0 {
-            self.stdout.command(Command::MoveCursorLeft(diff)).await.unwrap();
+        if cursor.position().column > 0 {
+            self.stdout.command(Command::MoveCursorRight(cursor.position().column)).await.unwrap();
         }
 
+        self.rows = end.position().row + 1;
+        self.cursor_row = cursor.position().row;
+
         // Flush all changes from the IO buffer.
         self.stdout.flush().await.unwrap();
     }
 }
+
+/// The command line as it is drawn.
+struct View {
+    /// The text to draw, including escape sequences for styles.
+    output: String,
+
+    /// The text drawn before the cursor and after it, without styles, for
+    /// working out where the cursor goes.
+    before_cursor: String,
+    after_cursor: String,
+}
+
+/// Wait for a signal that the terminal has been resized, or forever if they
+/// can't be received.
+async fn window_changed(signal: &mut Option<Signal>) {
+    if let Some(window_change) = signal.as_mut() {
+        if window_change.recv().await.is_some() {
+            return;
+        }
+    }
+
+    *signal = None;
+    futures::future::pending().await
+}
+
+/// Find the longest prefix shared by the text of all candidates.
+fn common_prefix(candidates: &[Candidate]) -> &str {
+    let first = candidates[0].text.as_str();
+    let mut len = first.len();
+
+    for candidate in &candidates[1..] {
+        len = first.char_indices()
+            .zip(candidate.text.chars())
+            .take_while(|((i, a), b)| *i < len && a == b)
+            .last()
+            .map(|((i, a), _)| i + a.len_utf8())
+            .unwrap_or(0);
+    }
+
+    &first[..len]
+}
@@ -1,16 +1,30 @@
 /// Enumeration of possible input events that could be received from the user.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Event {
     Char(char),
     Up,
     Down,
     Left,
     Right,
+    AltLeft,
+    AltRight,
+    CtrlLeft,
+    CtrlRight,
     PageUp,
     PageDown,
     Home,
     End,
     Insert,
+    Escape,
+    Tab,
+    BackTab,
     Backspace,
+    AltBackspace,
     Delete,
     Ctrl(char),
+    Alt(char),
+
+    /// Text pasted into the terminal, which is inserted as it is rather than
+    /// being treated as keys.
+    Paste(String),
 }
@@ -0,0 +1,186 @@
+//! Syntax highlighting for the interactive editor.
+//!
+//! Colours come from the `syntax` table of the global `theme` table, which maps
+//! the name of each kind of token to a style. A style is a list of words
+//! separated by spaces, such as `bold blue`, made up of a colour, a bright
+//! colour such as `bright-blue`, and any of `bold`, `dim`, `italic`, and
+//! `underline`. Kinds without a style are not highlighted.
+
+use super::completion::is_executable;
+use crate::runtime::prelude::*;
+use crate::runtime::syntax::tokens::{tokenize, TokenKind};
+use std::env;
+use std::path::Path;
+
+/// Name of the global table holding theme data.
+pub(crate) const THEME_GLOBAL: &str = "theme";
+
+/// Styles used for each kind of token unless the theme is changed.
+const DEFAULT_SYNTAX_STYLES: &[(&str, &str)] = &[
+    ("command", "bold"),
+    ("unknown-command", "red"),
+    ("word", ""),
+    ("string", "green"),
+    ("substitution", "cyan"),
+    ("number", "magenta"),
+    ("comment", "bright-black"),
+    ("block", "yellow"),
+    ("operator", "bright-black"),
+];
+
+const COLORS: &[&str] = &["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+/// Create the default theme table.
+pub fn default_theme() -> Table {
+    table! {
+        "syntax" => DEFAULT_SYNTAX_STYLES.iter().cloned().collect::<Table>(),
+    }
+}
+
+/// Highlights command lines using the theme and bindings of an interactive
+/// session.
+pub struct Highlighter {
+    globals: Table,
+
+    /// The scope that command lines are executed in.
+    scope: Table,
+}
+
+impl Highlighter {
+    pub fn new(globals: Table, scope: Table) -> Self {
+        Self {
+            globals,
+            scope,
+        }
+    }
+
+    /// Render a command line with escape sequences for the theme's colours.
+    pub fn highlight(&self, line: &str) -> String {
+        let styles = self.globals.get(THEME_GLOBAL).get("syntax");
+        let mut output = String::with_capacity(line.len());
+        let mut pos = 0;
+
+        for token in tokenize(line) {
+            let name = match token.kind {
+                TokenKind::Command if !self.is_command(&line[token.span.clone()]) => "unknown-command",
+                TokenKind::Command => "command",
+                TokenKind::Word => "word",
+                TokenKind::String => "string",
+                TokenKind::Substitution => "substitution",
+                TokenKind::Number => "number",
+                TokenKind::Comment => "comment",
+                TokenKind::Block => "block",
+                TokenKind::Operator => "operator",
+            };
+
+            output.push_str(&line[pos..token.span.start]);
+
+            match sgr_params(&styles.get(name).to_string()) {
+                // Styles are ended before each newline so that whatever is
+                // drawn at the start of the next line is left unstyled.
+                Some(params) => {
+                    for (i, part) in line[token.span.clone()].split('\n').enumerate() {
+                        if i > 0 {
+                            output.push('\n');
+                        }
+
+                        if !part.is_empty() {
+                            output.push_str(&format!("\x1b[{}m{}\x1b[0m", params, part));
+                        }
+                    }
+                }
+                None => output.push_str(&line[token.span.clone()]),
+            }
+
+            pos = token.span.end;
+        }
+
+        output.push_str(&line[pos..]);
+        output
+    }
+
+    /// Check whether a call to the given name would find something to run,
+    /// either a binding or an executable.
+    fn is_command(&self, name: &str) -> bool {
+        if !self.scope.get(name).is_nil() || !self.globals.get(name).is_nil() {
+            return true;
+        }
+
+        if name.contains('/') {
+            return is_executable(Path::new(name));
+        }
+
+        match env::var_os("PATH") {
+            Some(path) => env::split_paths(&path).any(|dir| is_executable(&dir.join(name))),
+            None => false,
+        }
+    }
+}
+
+/// Wrap text in the escape sequences for a style.
+pub fn style(style: &str, text: &str) -> String {
+    match sgr_params(style) {
+        Some(params) => format!("\x1b[{}m{}\x1b[0m", params, text),
+        None => text.to_owned(),
+    }
+}
+
+/// Convert a style into the parameters of an SGR escape sequence, or `None` if
+/// it has no effect.
+fn sgr_params(style: &str) -> Option<String> {
+    let params = style.split_whitespace()
+        .filter_map(|word| {
+            let (word, base) = match word.strip_prefix("bright-") {
+                Some(color) => (color, 90),
+                None => (word, 30),
+            };
+
+            match word {
+                "bold" if base == 30 => Some(1),
+                "dim" if base == 30 => Some(2),
+                "italic" if base == 30 => Some(3),
+                "underline" if base == 30 => Some(4),
+                color => COLORS.iter().position(|c| *c == color).map(|i| base + i),
+            }
+        })
+        .map(|param| param.to_string())
+        .collect::<Vec<_>>();
+
+    if params.is_empty() {
+        None
+    } else {
+        Some(params.join(";"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn styles_are_converted_to_sgr_params() {
+        assert_eq!(sgr_params("bold blue").as_deref(), Some("1;34"));
+        assert_eq!(sgr_params("bright-black").as_deref(), Some("90"));
+        assert_eq!(sgr_params("sparkly"), None);
+        assert_eq!(sgr_params(""), None);
+    }
+
+    #[test]
+    fn text_is_wrapped_in_style() {
+        assert_eq!(style("green", "ok"), "\x1b[32mok\x1b[0m");
+        assert_eq!(style("", "ok"), "ok");
+    }
+
+    #[test]
+    fn unknown_commands_are_highlighted() {
+        let globals = table!();
+        globals.set(THEME_GLOBAL, default_theme());
+        globals.set("echo", "bound");
+
+        let highlighter = Highlighter::new(globals, table!());
+
+        assert_eq!(highlighter.highlight("echo 'hi'"), "\x1b[1mecho\x1b[0m \x1b[32m'hi'\x1b[0m");
+        assert_eq!(highlighter.highlight("no-such-command"), "\x1b[31mno-such-command\x1b[0m");
+        assert_eq!(highlighter.highlight("'a\nb'"), "\x1b[32m'a\x1b[0m\n\x1b[32mb'\x1b[0m");
+    }
+}
@@ -20,31 +20,146 @@
 //! - `pid`: The PID of the shell process that added the command. This is used
 //!   for segregating the history by session in some queries.
 //! - `timestamp`: Timestamp of when the command was run.
+//! - `exit_status`: The exit status of the command, once it has finished.
+//! - `duration`: How long the command took to run in milliseconds, once it has
+//!   finished.
 //!
-//! ## Directory history
+//! ## `directory_history`
+//!
+//! Whenever the shell changes to a different working directory, the new
+//! directory is recorded in this table. Visits are used to rank directories by
+//! _frecency_, a combination of how frequently and how recently they were
+//! visited, when jumping to a directory.
+//!
+//! Contains the following columns:
+//!
+//! - `rowid`: Auto-incrementing integer ID of the visit.
+//! - `path`: The absolute path of the directory.
+//! - `pid`: The PID of the shell process that visited the directory.
+//! - `timestamp`: Timestamp of when the directory was visited.
+//!
+//! ## `bookmarks`
+//!
+//! Directories bookmarked by the user, which can be jumped to by name.
+//!
+//! Contains the following columns:
+//!
+//! - `name`: The unique name of the bookmark.
+//! - `path`: The absolute path of the directory.
 
-use rusqlite::{params, Connection, Statement, Rows, Row};
+use ::directories::ProjectDirs;
+use rusqlite::{params, Connection, OpenFlags, Statement, Rows, Row, ToSql};
 use std::env;
 use std::error::Error;
+use std::fmt;
+use std::fs;
 use std::mem;
 use std::path::{Path, PathBuf};
 use std::process;
 use std::rc::Rc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+pub use self::formats::{ImportedCommand, Shell};
+
+mod directories;
+mod formats;
+
+pub(crate) use self::formats::to_json;
+
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
 /// A connection to a history database.
+#[derive(Clone)]
 pub struct History {
     db: Rc<Connection>,
+
+    /// Timestamp of when the history was opened. Together with the PID this
+    /// identifies commands from the current session.
+    started: i64,
 }
 
 /// A single entry in the history.
 #[derive(Clone)]
 pub struct CommandEntry {
+    id: i64,
     command: String,
     cwd: Option<String>,
     timestamp: SystemTime,
+    exit_status: Option<i32>,
+    duration: Option<Duration>,
+}
+
+/// Criteria for selecting entries in the command history. Criteria that are
+/// not set match every entry.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Only match commands containing this text.
+    pub text: Option<String>,
+
+    /// Only match commands run in the current session.
+    pub session: bool,
+
+    /// Only match commands run in this working directory.
+    pub cwd: Option<String>,
+
+    /// Only match commands run at or after this time.
+    pub since: Option<SystemTime>,
+
+    /// Only match commands run before this time.
+    pub until: Option<SystemTime>,
+
+    /// Only match commands that exited with this status.
+    pub exit_status: Option<i32>,
+
+    /// Only match commands that exited with a non-zero status.
+    pub failed: bool,
+}
+
+impl Filter {
+    /// Check if no criteria are set, meaning that every entry matches.
+    pub fn is_empty(&self) -> bool {
+        self.text.is_none()
+            && !self.session
+            && self.cwd.is_none()
+            && self.since.is_none()
+            && self.until.is_none()
+            && self.exit_status.is_none()
+            && !self.failed
+    }
+}
+
+/// Order in which history search results are returned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ranking {
+    /// Most recently run commands first.
+    Recency,
+
+    /// Most frequently run commands first.
+    Frequency,
+
+    /// Commands that were run in the current directory first, then by recency.
+    Directory,
+}
+
+impl Ranking {
+    /// Get the ranking that follows this one, wrapping around after the last.
+    pub fn next(self) -> Self {
+        match self {
+            Ranking::Recency => Ranking::Frequency,
+            Ranking::Frequency => Ranking::Directory,
+            Ranking::Directory => Ranking::Recency,
+        }
+    }
+}
+
+impl fmt::Display for Ranking {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Ranking::Recency => "recent",
+            Ranking::Frequency => "frequent",
+            Ranking::Directory => "directory",
+        })
+    }
 }
 
 /// Aggregated information about a particular command string.
@@ -60,19 +175,50 @@ impl History {
         Self::from_connection(Connection::open(path)?)
     }
 
+    /// Open the history file of the current user, creating it if necessary.
+    pub fn open_default() -> Result<Self> {
+        let path = Self::default_path()?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        Self::open(path)
+    }
+
+    /// Get the path of the history file of the current user, which is stored in
+    /// the user's local data directory for Riptide.
+    pub fn default_path() -> Result<PathBuf> {
+        let dirs = ProjectDirs::from("", "", "Riptide").ok_or("unable to locate the user's home directory")?;
+
+        Ok(dirs.data_dir().join("history.db"))
+    }
+
     /// Create a temporary in-memory history database.
+    ///
+    /// Nothing is ever written to disk for an in-memory history, including any
+    /// temporary files SQLite would otherwise create for large queries.
     pub fn in_memory() -> Result<Self> {
-        Self::from_connection(Connection::open_in_memory()?)
+        let connection = Connection::open_in_memory()?;
+        connection.execute_batch("PRAGMA temp_store = MEMORY")?;
+
+        Self::from_connection(connection)
     }
 
     fn from_connection(connection: Connection) -> Result<Self> {
         let history = Self {
             db: Rc::new(connection),
+            started: now(),
         };
 
         match history.get_version() {
             0 => history.instrument()?,
-            1 => {},
+            1 => {
+                history.upgrade_to_v2()?;
+                history.upgrade_to_v3()?;
+            }
+            2 => history.upgrade_to_v3()?,
+            3 => {},
             version => return Err(format!("unknown version: {}", version).into()),
         }
 
@@ -85,93 +231,443 @@ impl History {
 
     fn instrument(&self) -> Result<()> {
         self.db.execute_batch("
-            PRAGMA user_version = 1;
+            PRAGMA user_version = 3;
 
             CREATE TABLE command_history (
                 command TEXT NOT NULL,
                 cwd TEXT,
                 pid INTEGER,
+                timestamp INTEGER NOT NULL,
+                exit_status INTEGER,
+                duration INTEGER
+            );
+
+            CREATE TABLE directory_history (
+                path TEXT NOT NULL,
+                pid INTEGER,
                 timestamp INTEGER NOT NULL
             );
+
+            CREATE TABLE bookmarks (
+                name TEXT NOT NULL PRIMARY KEY,
+                path TEXT NOT NULL
+            );
         ")?;
 
         Ok(())
     }
 
+    /// Add the exit status and duration columns to a version 1 history.
+    fn upgrade_to_v2(&self) -> Result<()> {
+        self.db.execute_batch("
+            BEGIN;
+
+            ALTER TABLE command_history ADD COLUMN exit_status INTEGER;
+            ALTER TABLE command_history ADD COLUMN duration INTEGER;
+
+            PRAGMA user_version = 2;
+
+            COMMIT;
+        ")?;
+
+        Ok(())
+    }
+
+    /// Add the directory history and bookmark tables to a version 2 history.
+    fn upgrade_to_v3(&self) -> Result<()> {
+        self.db.execute_batch("
+            BEGIN;
+
+            CREATE TABLE directory_history (
+                path TEXT NOT NULL,
+                pid INTEGER,
+                timestamp INTEGER NOT NULL
+            );
+
+            CREATE TABLE bookmarks (
+                name TEXT NOT NULL PRIMARY KEY,
+                path TEXT NOT NULL
+            );
+
+            PRAGMA user_version = 3;
+
+            COMMIT;
+        ")?;
+
+        Ok(())
+    }
+
+    /// Query all commands for browsing, with commands from the current session
+    /// first and then commands from all other sessions, each most recent first.
     pub fn command_history(&self) -> Cursor<CommandEntry> {
-        let mut statement = self.db.prepare("
-            SELECT command, cwd, timestamp FROM command_history
-            ORDER BY timestamp DESC
+        let statement = self.db.prepare("
+            SELECT rowid AS id, command, cwd, timestamp, exit_status, duration FROM command_history
+            ORDER BY (pid IS ?1 AND timestamp >= ?2) DESC, rowid DESC
         ").unwrap();
-        let rows = statement.query(params![]).unwrap();
+        Cursor::new(&self.db, statement, params![process::id(), self.started])
+    }
+
+    /// Query the commands matching a filter, most recent first.
+    pub fn entries(&self, filter: &Filter) -> Cursor<CommandEntry> {
+        let (condition, params) = self.filter_condition(filter);
+
+        let statement = self.db.prepare(&format!("
+            SELECT rowid AS id, command, cwd, timestamp, exit_status, duration FROM command_history
+            WHERE {}
+            ORDER BY rowid DESC
+        ", condition)).unwrap();
+
+        Cursor::new(&self.db, statement, params)
+    }
+
+    /// Delete all commands matching a filter, returning how many were deleted.
+    pub fn delete(&self, filter: &Filter) -> Result<usize> {
+        let (condition, params) = self.filter_condition(filter);
+
+        Ok(self.db.execute(&format!("DELETE FROM command_history WHERE {}", condition), params)?)
+    }
+
+    /// Delete the commands with the given IDs that match a filter.
+    ///
+    /// Either all of the commands are deleted or none are. If any of them
+    /// doesn't exist or doesn't match, nothing is deleted and its ID is
+    /// returned.
+    pub fn delete_entries(&self, ids: &[i64], filter: &Filter) -> Result<Option<i64>> {
+        let (condition, params) = self.filter_condition(filter);
+        let sql = format!("DELETE FROM command_history WHERE rowid = ? AND {}", condition);
+
+        let mut ids = ids.to_vec();
+        ids.sort();
+        ids.dedup();
+
+        self.db.execute_batch("BEGIN")?;
+
+        for id in ids {
+            let values = Some(&id as &dyn ToSql).into_iter().chain(params.iter().map(AsRef::as_ref));
+
+            match self.db.execute(&sql, values) {
+                Ok(0) => {
+                    self.db.execute_batch("ROLLBACK")?;
+                    return Ok(Some(id));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.db.execute_batch("ROLLBACK")?;
+                    return Err(e.into());
+                }
+            }
+        }
+
+        self.db.execute_batch("COMMIT")?;
+
+        Ok(None)
+    }
+
+    /// Build an SQL condition and its parameters for selecting the commands
+    /// matching a filter.
+    fn filter_condition(&self, filter: &Filter) -> (String, Vec<Box<dyn ToSql>>) {
+        let mut conditions = vec!["1".to_owned()];
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+        let mut add = |condition: &str, values: Vec<Box<dyn ToSql>>| {
+            conditions.push(condition.to_owned());
+            params.extend(values);
+        };
+
+        if let Some(text) = filter.text.as_ref() {
+            add("instr(command, ?) > 0", vec![Box::new(text.clone())]);
+        }
+
+        if filter.session {
+            add("pid IS ? AND timestamp >= ?", vec![Box::new(process::id()), Box::new(self.started)]);
+        }
+
+        if let Some(cwd) = filter.cwd.as_ref() {
+            add("cwd IS ?", vec![Box::new(cwd.clone())]);
+        }
+
+        if let Some(since) = filter.since {
+            add("timestamp >= ?", vec![Box::new(seconds_since_epoch(since))]);
+        }
+
+        if let Some(until) = filter.until {
+            add("timestamp < ?", vec![Box::new(seconds_since_epoch(until))]);
+        }
+
+        if let Some(exit_status) = filter.exit_status {
+            add("exit_status IS ?", vec![Box::new(exit_status)]);
+        }
+
+        if filter.failed {
+            add("exit_status != 0", Vec::new());
+        }
+
+        (conditions.join(" AND "), params)
+    }
 
-        Cursor::new(&self.db, statement, rows)
+    /// Search for distinct commands containing the given text, ordered by the
+    /// given ranking.
+    pub fn search(&self, text: &str, ranking: Ranking) -> Cursor<CommandEntry> {
+        let cwd = env::current_dir().ok()
+            .and_then(|path| path.to_str()
+                .map(String::from));
+
+        let order = match ranking {
+            Ranking::Recency => "id DESC",
+            Ranking::Frequency => "count DESC, id DESC",
+            Ranking::Directory => "here DESC, id DESC",
+        };
+
+        let statement = self.db.prepare(&format!("
+            SELECT
+                command,
+                cwd,
+                timestamp,
+                max(rowid) AS id,
+                exit_status,
+                duration,
+                count(*) AS count,
+                max(cwd IS ?2) AS here
+            FROM command_history
+            WHERE instr(command, ?1) > 0
+            GROUP BY command
+            ORDER BY {}
+        ", order)).unwrap();
+
+        Cursor::new(&self.db, statement, params![text, cwd])
     }
 
     /// Query for frequent commands.
     pub fn frequent_commands(&self) -> Cursor<CommandSummary> {
-        let mut statement = self.db.prepare("
+        let statement = self.db.prepare("
             SELECT command, count(*) AS count FROM command_history
             GROUP BY command
             ORDER BY count DESC
         ").unwrap();
-        let rows = statement.query(params![]).unwrap();
-
-        Cursor::new(&self.db, statement, rows)
+        Cursor::new(&self.db, statement, params![])
     }
 
     /// Query for frequent commands with a prefix.
+    ///
+    /// Commands that have been run in the current working directory are
+    /// returned before others. The prefix is matched case-sensitively, and the
+    /// prefix itself is not included.
     pub fn frequent_commands_starting_with(&self, prefix: impl Into<String>) -> Cursor<CommandSummary> {
-        let pattern = prefix.into()
-            .replace("%", "\\%")
-            .replace("\\", "\\\\") + "%";
+        let cwd = env::current_dir().ok()
+            .and_then(|path| path.to_str()
+                .map(String::from));
 
-        let mut statement = self.db.prepare(r#"
-            SELECT command, count(*) AS count FROM command_history
-            WHERE command LIKE ? ESCAPE "\"
+        let statement = self.db.prepare("
+            SELECT command, count(*) AS count, max(cwd IS ?2) AS here FROM command_history
+            WHERE instr(command, ?1) = 1 AND command != ?1
             GROUP BY command
-            ORDER BY count DESC
-        "#).unwrap();
+            ORDER BY here DESC, count DESC, max(rowid) DESC
+        ").unwrap();
+
+        Cursor::new(&self.db, statement, params![prefix.into(), cwd])
+    }
+
+    /// Copy all commands from another history file into this history.
+    ///
+    /// The other file is opened read-only, and is never modified. Nothing is
+    /// imported if the file does not exist.
+    pub fn import(&self, path: impl AsRef<Path>) -> Result<()> {
+        if !path.as_ref().exists() {
+            return Ok(());
+        }
+
+        let source = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let version: i64 = source.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+
+        // Older files can't be upgraded while read-only, so fill in the
+        // missing columns instead.
+        let mut select = source.prepare(if version < 2 {
+            "SELECT command, cwd, pid, timestamp, NULL, NULL FROM command_history ORDER BY rowid"
+        } else {
+            "SELECT command, cwd, pid, timestamp, exit_status, duration FROM command_history ORDER BY rowid"
+        })?;
+        let mut rows = select.query(params![])?;
+
+        self.transaction(|| {
+            while let Some(row) = rows.next()? {
+                self.db.execute(
+                    "INSERT INTO command_history (command, cwd, pid, timestamp, exit_status, duration) VALUES (?, ?, ?, ?, ?, ?)",
+                    params![
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Option<i64>>(2)?,
+                        row.get::<_, i64>(3)?,
+                        row.get::<_, Option<i64>>(4)?,
+                        row.get::<_, Option<i64>>(5)?,
+                    ],
+                )?;
+            }
+
+            Ok(())
+        })
+    }
 
-        let rows = statement.query(params![pattern]).unwrap();
+    /// Add commands imported from another shell to the history, returning how
+    /// many were added.
+    ///
+    /// Commands that were already in the history with the same timestamp before
+    /// the import are skipped, so importing the same file again adds nothing.
+    /// Repeats of a command among those imported are all kept, even without
+    /// timestamps, so that the imported history keeps how often each command
+    /// was run. Commands without a timestamp are treated as having been run at
+    /// the Unix epoch.
+    ///
+    /// Imported commands are assumed to predate everything already in the
+    /// history, and are placed before all existing commands in order of their
+    /// timestamps. Existing commands keep their IDs.
+    pub fn import_commands(&self, commands: &[ImportedCommand]) -> Result<usize> {
+        let mut commands = commands.iter().collect::<Vec<_>>();
+        commands.sort_by_key(|command| command.timestamp.unwrap_or(0));
+
+        self.transaction(|| {
+            // Imported commands are inserted before this row, so only rows
+            // from here on existed before the import.
+            let existing: i64 = self.db.query_row("SELECT coalesce(min(rowid), 1) FROM command_history", params![], |row| row.get(0))?;
+
+            let mut added = 0;
+
+            // Insert from newest to oldest, each one before the current first
+            // row.
+            for command in commands.into_iter().rev() {
+                added += self.db.execute("
+                    INSERT INTO command_history (rowid, command, timestamp, duration)
+                    SELECT (SELECT coalesce(min(rowid), 1) - 1 FROM command_history), ?1, ?2, ?3
+                    WHERE NOT EXISTS (
+                        SELECT 1 FROM command_history WHERE command = ?1 AND timestamp = ?2 AND rowid >= ?4
+                    )
+                ", params![
+                    command.command,
+                    command.timestamp.unwrap_or(0),
+                    command.duration.map(|duration| duration.as_millis() as i64),
+                    existing,
+                ])?;
+            }
 
-        Cursor::new(&self.db, statement, rows)
+            Ok(added)
+        })
     }
 
-    /// Record a command and add it to the history.
-    pub fn add(&self, command: impl Into<String>) {
+    /// Run a function in a transaction, which is committed if the function
+    /// succeeds and rolled back otherwise.
+    fn transaction<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.db.execute_batch("BEGIN")?;
+
+        let result = f().and_then(|value| {
+            self.db.execute_batch("COMMIT")?;
+            Ok(value)
+        });
+
+        // A failed commit leaves the transaction open too.
+        if result.is_err() {
+            self.db.execute_batch("ROLLBACK")?;
+        }
+
+        result
+    }
+
+    /// Record a command and add it to the history, returning the ID of the new
+    /// entry.
+    pub fn add(&self, command: impl Into<String>) -> i64 {
         let cwd = env::current_dir().ok()
             .and_then(|path| path.to_str()
                 .map(String::from));
 
         let pid = process::id();
 
-        let timestamp = UNIX_EPOCH
-            .elapsed()
-            .unwrap_or(Duration::from_secs(0))
-            .as_secs() as i64;
-
         self.db.execute(
             "INSERT INTO command_history (command, cwd, pid, timestamp) VALUES (?, ?, ?, ?)",
-            params![command.into(), cwd, pid, timestamp],
+            params![command.into(), cwd, pid, now()],
+        ).unwrap();
+
+        self.db.last_insert_rowid()
+    }
+
+    /// Record the exit status and duration of a command once it has finished.
+    pub fn finish(&self, id: i64, exit_status: i32, duration: Duration) {
+        self.db.execute(
+            "UPDATE command_history SET exit_status = ?, duration = ? WHERE rowid = ?",
+            params![exit_status, duration.as_millis() as i64, id],
         ).unwrap();
     }
 }
 
+/// Get the current time as seconds since the Unix epoch.
+fn now() -> i64 {
+    seconds_since_epoch(SystemTime::now())
+}
+
+fn seconds_since_epoch(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs() as i64
+}
+
 pub trait FromRow: Sized {
     fn from_row(row: &Row) -> std::result::Result<Self, rusqlite::Error>;
 }
 
+impl CommandEntry {
+    /// Get the ID of this entry in the history.
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Get the command line that was executed.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Get the working directory the command was executed in, if known.
+    pub fn cwd(&self) -> Option<&str> {
+        self.cwd.as_deref()
+    }
+
+    /// Get the time at which the command was executed.
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    /// Get the exit status of the command, if it has finished.
+    pub fn exit_status(&self) -> Option<i32> {
+        self.exit_status
+    }
+
+    /// Get how long the command took to run, if it has finished.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
+
 impl FromRow for CommandEntry {
     fn from_row(row: &Row) -> std::result::Result<Self, rusqlite::Error> {
         Ok(Self {
+            id: row.get("id")?,
             command: row.get("command")?,
             cwd: row.get("cwd")?,
             timestamp: UNIX_EPOCH + Duration::from_secs(row.get::<_, i64>("timestamp")? as u64),
+            exit_status: row.get("exit_status")?,
+            duration: row.get::<_, Option<i64>>("duration")?.map(|ms| Duration::from_millis(ms as u64)),
         })
     }
 }
 
+impl CommandSummary {
+    /// Get the command line.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Get the number of times the command was run.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
 impl FromRow for CommandSummary {
     fn from_row(row: &Row) -> std::result::Result<Self, rusqlite::Error> {
         Ok(Self {
@@ -184,24 +680,37 @@ impl FromRow for CommandSummary {
 /// A mutable, movable cursor into a sequence of search results for command
 /// history.
 pub struct Cursor<T> {
+    // Fields are dropped in declaration order, so the rows are dropped before
+    // the statement they borrow, which is dropped before the connection.
     rows: Rows<'static>,
     buffer: Vec<T>,
     index: usize,
-    _statement: Statement<'static>,
+    _statement: Box<Statement<'static>>,
     _db: Rc<Connection>,
 }
 
 impl<T> Cursor<T> {
-    fn new<'a>(db: &'a Rc<Connection>, statement: Statement<'a>, rows: Rows<'a>) -> Self {
+    fn new<'a, P>(db: &'a Rc<Connection>, statement: Statement<'a>, params: P) -> Self
+    where
+        P: IntoIterator,
+        P::Item: ToSql,
+    {
+        // The statement is boxed so that the rows can keep borrowing it after
+        // it is moved into the cursor, and the connection is kept alive by
+        // holding a reference to it.
+        let mut statement = unsafe {
+            mem::transmute::<Box<Statement<'a>>, Box<Statement<'static>>>(Box::new(statement))
+        };
+
+        let rows = unsafe {
+            mem::transmute::<Rows<'_>, Rows<'static>>(statement.query(params).unwrap())
+        };
+
         Self {
-            rows: unsafe {
-                mem::transmute(rows)
-            },
+            rows,
             buffer: Vec::new(),
             index: 0,
-            _statement: unsafe {
-                mem::transmute(statement)
-            },
+            _statement: statement,
             _db: db.clone(),
         }
     }
@@ -231,12 +740,18 @@ impl<T: Clone + FromRow> Iterator for Cursor<T> {
 }
 
 impl<T: Clone + FromRow> Cursor<T> {
-    fn prev(&mut self) -> Option<T> {
+    /// Move back to the item returned before the most recent one, and return it.
+    ///
+    /// This undoes a call to `next`. Returns `None` once the cursor is moved
+    /// back before the first item.
+    pub fn prev(&mut self) -> Option<T> {
         if self.index > 0 {
             self.index -= 1;
-            self.buffer.get(self.index).cloned()
-        } else {
-            None
+        }
+
+        match self.index {
+            0 => None,
+            index => self.buffer.get(index - 1).cloned(),
         }
     }
 }
@@ -259,4 +774,248 @@ mod tests {
             assert_eq!(cursor.next().unwrap().command, format!("echo {}", 8 - i));
         }
     }
+
+    #[test]
+    fn import_copies_without_modifying_source() {
+        let path = env::temp_dir().join(format!("riptide-history-test-{}.db", process::id()));
+
+        {
+            let source = History::open(&path).unwrap();
+            source.add("echo a");
+            source.add("echo b");
+        }
+
+        let history = History::in_memory().unwrap();
+        history.import(&path).unwrap();
+        history.add("echo c");
+
+        let commands = history.command_history().map(|entry| entry.command).collect::<Vec<_>>();
+        assert_eq!(commands, vec!["echo c", "echo b", "echo a"]);
+
+        let source = History::open(&path).unwrap();
+        assert_eq!(source.command_history().count(), 2);
+
+        drop(source);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_copies_are_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.db");
+
+        {
+            let source = History::open(&path).unwrap();
+            source.add("echo a");
+            source.add("echo b");
+            source.db.execute("UPDATE command_history SET command = CAST(X'FF' AS TEXT) WHERE command = 'echo b'", params![]).unwrap();
+        }
+
+        let history = History::in_memory().unwrap();
+        assert!(history.import(&path).is_err());
+        assert_eq!(history.command_history().count(), 0);
+
+        // The history is no longer in a transaction.
+        history.add("echo c");
+        assert!(history.delete_entries(&[1], &Filter::default()).is_ok());
+    }
+
+    #[test]
+    fn search_by_substring() {
+        let history = History::in_memory().unwrap();
+
+        history.add("cargo build");
+        history.add("cargo test");
+        history.add("git status");
+        history.add("cargo test");
+        history.add("cargo build --release");
+
+        let recent = history.search("cargo", Ranking::Recency).map(|entry| entry.command).collect::<Vec<_>>();
+        assert_eq!(recent, vec!["cargo build --release", "cargo test", "cargo build"]);
+
+        let frequent = history.search("cargo", Ranking::Frequency).next().unwrap();
+        assert_eq!(frequent.command, "cargo test");
+
+        assert_eq!(history.search("build", Ranking::Recency).count(), 2);
+        assert_eq!(history.search("BUILD", Ranking::Recency).count(), 0);
+    }
+
+    #[test]
+    fn frequent_commands_with_prefix() {
+        let history = History::in_memory().unwrap();
+
+        history.add("cargo test");
+        history.add("cargo build");
+        history.add("cargo build");
+        history.add("Cargo");
+        history.add("car");
+
+        let commands = history.frequent_commands_starting_with("car")
+            .map(|summary| summary.command)
+            .collect::<Vec<_>>();
+
+        assert_eq!(commands, vec!["cargo build", "cargo test"]);
+        assert_eq!(history.frequent_commands_starting_with("50%").count(), 0);
+    }
+
+    #[test]
+    fn current_session_is_browsed_first() {
+        let history = History::in_memory().unwrap();
+
+        history.add("echo mine");
+        history.db.execute(
+            "INSERT INTO command_history (command, pid, timestamp) VALUES ('echo theirs', 0, ?)",
+            params![now()],
+        ).unwrap();
+
+        let commands = history.command_history().map(|entry| entry.command).collect::<Vec<_>>();
+        assert_eq!(commands, vec!["echo mine", "echo theirs"]);
+    }
+
+    #[test]
+    fn filter_and_delete_entries() {
+        let history = History::in_memory().unwrap();
+
+        let ok = history.add("true");
+        let failed = history.add("false");
+        history.add("echo running");
+        history.finish(ok, 0, Duration::from_millis(5));
+        history.finish(failed, 1, Duration::from_millis(1500));
+
+        let failures = history.entries(&Filter {
+            failed: true,
+            ..Filter::default()
+        }).collect::<Vec<_>>();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].id(), failed);
+        assert_eq!(failures[0].duration(), Some(Duration::from_millis(1500)));
+
+        let future = Filter {
+            since: Some(SystemTime::now() + Duration::from_secs(60)),
+            ..Filter::default()
+        };
+        assert_eq!(history.entries(&future).count(), 0);
+        assert_eq!(history.entries(&Filter::default()).count(), 3);
+
+        let deleted = history.delete(&Filter {
+            text: Some("u".into()),
+            ..Filter::default()
+        }).unwrap();
+        assert_eq!(deleted, 2);
+        assert_eq!(history.delete_entries(&[failed], &Filter::default()).unwrap(), None);
+        assert_eq!(history.delete_entries(&[ok], &Filter::default()).unwrap(), Some(ok));
+        assert_eq!(history.command_history().count(), 0);
+    }
+
+    #[test]
+    fn entries_are_deleted_together() {
+        let history = History::in_memory().unwrap();
+
+        let ok = history.add("true");
+        let failed = history.add("false");
+        let other = history.add("false");
+        history.finish(ok, 0, Duration::from_millis(5));
+        history.finish(failed, 1, Duration::from_millis(5));
+        history.finish(other, 1, Duration::from_millis(5));
+
+        let filter = Filter {
+            failed: true,
+            ..Filter::default()
+        };
+
+        // Nothing is deleted if any of the entries doesn't match.
+        assert_eq!(history.delete_entries(&[failed, ok], &filter).unwrap(), Some(ok));
+        assert_eq!(history.delete_entries(&[failed, 1000], &filter).unwrap(), Some(1000));
+        assert_eq!(history.entries(&Filter::default()).count(), 3);
+
+        assert_eq!(history.delete_entries(&[other, failed, failed], &filter).unwrap(), None);
+        assert_eq!(history.entries(&Filter::default()).map(|entry| entry.id()).collect::<Vec<_>>(), vec![ok]);
+    }
+
+    #[test]
+    fn old_versions_are_upgraded() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch("
+            PRAGMA user_version = 1;
+
+            CREATE TABLE command_history (
+                command TEXT NOT NULL,
+                cwd TEXT,
+                pid INTEGER,
+                timestamp INTEGER NOT NULL
+            );
+
+            INSERT INTO command_history VALUES ('echo old', NULL, 1, 0);
+        ").unwrap();
+
+        let history = History::from_connection(connection).unwrap();
+        assert_eq!(history.get_version(), 3);
+        assert!(history.bookmarks().is_empty());
+
+        let entry = history.command_history().next().unwrap();
+        assert_eq!(entry.command, "echo old");
+        assert_eq!(entry.exit_status, None);
+    }
+
+    #[test]
+    fn imported_commands_are_deduplicated_and_sorted() {
+        let history = History::in_memory().unwrap();
+        let now = history.add("echo now");
+
+        let commands = Shell::Zsh.parse(b": 1580000001:0;echo b\n: 1580000000:0;echo a\nls\n");
+        assert_eq!(history.import_commands(&commands).unwrap(), 3);
+        assert_eq!(history.import_commands(&commands).unwrap(), 0);
+
+        let commands = history.entries(&Filter::default()).map(|entry| entry.command).collect::<Vec<_>>();
+        assert_eq!(commands, vec!["echo now", "echo b", "echo a", "ls"]);
+        assert_eq!(history.entries(&Filter::default()).next().unwrap().id, now);
+    }
+
+    #[test]
+    fn repeated_commands_without_timestamps_are_all_imported() {
+        let history = History::in_memory().unwrap();
+
+        let commands = Shell::Bash.parse(b"ls\ncd src\nls\nls\n");
+        assert_eq!(history.import_commands(&commands).unwrap(), 4);
+        assert_eq!(history.import_commands(&commands).unwrap(), 0);
+
+        let commands = history.entries(&Filter::default()).map(|entry| entry.command).collect::<Vec<_>>();
+        assert_eq!(commands, vec!["ls", "ls", "cd src", "ls"]);
+        assert_eq!(history.frequent_commands_starting_with("l").next().unwrap().count, 3);
+    }
+
+    #[test]
+    fn failed_imports_are_rolled_back() {
+        let history = History::in_memory().unwrap();
+        history.db.execute_batch("
+            CREATE TEMP TRIGGER reject BEFORE INSERT ON command_history WHEN NEW.command = 'rejected'
+            BEGIN SELECT RAISE(ABORT, 'rejected'); END
+        ").unwrap();
+
+        let commands = Shell::Bash.parse(b"ls\nrejected\npwd\n");
+        assert!(history.import_commands(&commands).is_err());
+        assert_eq!(history.entries(&Filter::default()).count(), 0);
+
+        // The history is no longer in a transaction.
+        history.add("echo after");
+        let commands = Shell::Bash.parse(b"ls\n");
+        assert_eq!(history.import_commands(&commands).unwrap(), 1);
+    }
+
+    #[test]
+    fn cursor_prev_undoes_next() {
+        let history = History::in_memory().unwrap();
+
+        for i in 0..3 {
+            history.add(format!("echo {}", i));
+        }
+
+        let mut cursor = history.command_history();
+
+        assert_eq!(cursor.next().unwrap().command, "echo 2");
+        assert_eq!(cursor.next().unwrap().command, "echo 1");
+        assert_eq!(cursor.prev().unwrap().command, "echo 2");
+        assert!(cursor.prev().is_none());
+        assert_eq!(cursor.next().unwrap().command, "echo 2");
+    }
 }
@@ -0,0 +1,248 @@
+//! Directory history and bookmarks.
+
+use super::{now, Cursor, FromRow, History, Result};
+use rusqlite::{params, OptionalExtension, Row};
+use std::env;
+use std::path::Path;
+use std::process;
+
+/// Aggregated information about visits to a particular directory.
+#[derive(Clone)]
+pub struct DirectorySummary {
+    path: String,
+    score: f64,
+}
+
+impl History {
+    /// Record a visit to a directory.
+    pub fn add_directory(&self, path: impl AsRef<str>) {
+        self.db.execute(
+            "INSERT INTO directory_history (path, pid, timestamp) VALUES (?, ?, ?)",
+            params![path.as_ref(), process::id(), now()],
+        ).unwrap();
+    }
+
+    /// Query visited directories, ordered by frecency.
+    ///
+    /// A directory's frecency is the number of times it was visited, weighted
+    /// by how long ago it was last visited.
+    pub fn frecent_directories(&self) -> Cursor<DirectorySummary> {
+        let statement = self.db.prepare("
+            SELECT
+                path,
+                count(*) * CASE
+                    WHEN ?1 - max(timestamp) < 3600 THEN 4.0
+                    WHEN ?1 - max(timestamp) < 86400 THEN 2.0
+                    WHEN ?1 - max(timestamp) < 604800 THEN 0.5
+                    ELSE 0.25
+                END AS score
+            FROM directory_history
+            GROUP BY path
+            ORDER BY score DESC, max(rowid) DESC
+        ").unwrap();
+
+        Cursor::new(&self.db, statement, params![now()])
+    }
+
+    /// Find the best existing directory matching all of the given terms, other
+    /// than the current directory.
+    ///
+    /// Terms are matched case-insensitively and in order. A term matches if it
+    /// appears in the path, or failing that, if all of its characters appear in
+    /// the path in the same order. Directories whose last component matches the
+    /// last term are preferred, then directories where every term appears as
+    /// is, and then directories with a higher frecency.
+    pub fn find_directory(&self, terms: &[&str]) -> Option<String> {
+        let cwd = env::current_dir().ok();
+        let mut best: Option<(Rank, String)> = None;
+
+        for directory in self.frecent_directories() {
+            if let Some(rank) = rank(&directory.path, terms) {
+                let better = match best.as_ref() {
+                    Some((best_rank, _)) => rank > *best_rank,
+                    None => true,
+                };
+
+                let path = Path::new(&directory.path);
+
+                if better && cwd.as_deref() != Some(path) && path.is_dir() {
+                    best = Some((rank, directory.path));
+                }
+            }
+        }
+
+        best.map(|(_, path)| path)
+    }
+
+    /// Add a bookmark with the given name, replacing any existing bookmark with
+    /// the same name.
+    pub fn set_bookmark(&self, name: &str, path: &str) -> Result<()> {
+        self.db.execute(
+            "INSERT OR REPLACE INTO bookmarks (name, path) VALUES (?, ?)",
+            params![name, path],
+        )?;
+
+        Ok(())
+    }
+
+    /// Remove a bookmark, returning whether it existed.
+    pub fn remove_bookmark(&self, name: &str) -> Result<bool> {
+        Ok(self.db.execute("DELETE FROM bookmarks WHERE name = ?", params![name])? > 0)
+    }
+
+    /// Get the path of a bookmark by name.
+    pub fn bookmark(&self, name: &str) -> Option<String> {
+        self.db.query_row("SELECT path FROM bookmarks WHERE name = ?", params![name], |row| row.get(0))
+            .optional()
+            .unwrap()
+    }
+
+    /// Get all bookmarks as pairs of names and paths, ordered by name.
+    pub fn bookmarks(&self) -> Vec<(String, String)> {
+        let mut statement = self.db.prepare("SELECT name, path FROM bookmarks ORDER BY name").unwrap();
+
+        statement.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .filter_map(std::result::Result::ok)
+            .collect()
+    }
+}
+
+impl DirectorySummary {
+    /// Get the path of the directory.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Get the frecency score of the directory.
+    pub fn score(&self) -> f64 {
+        self.score
+    }
+}
+
+impl FromRow for DirectorySummary {
+    fn from_row(row: &Row) -> std::result::Result<Self, rusqlite::Error> {
+        Ok(Self {
+            path: row.get("path")?,
+            score: row.get("score")?,
+        })
+    }
+}
+
+/// How well a path matches a list of terms, ordered from worst to best.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+struct Rank {
+    last_component: bool,
+    exact: bool,
+}
+
+fn rank(path: &str, terms: &[&str]) -> Option<Rank> {
+    let path = path.to_lowercase();
+    let last_component_start = path.trim_end_matches('/').rfind('/').map_or(0, |i| i + 1);
+
+    let mut position = 0;
+    let mut rank = Rank {
+        last_component: false,
+        exact: true,
+    };
+
+    for term in terms.iter().map(|term| term.to_lowercase()) {
+        let start = match path[position..].find(&term) {
+            Some(offset) => {
+                let start = position + offset;
+                position = start + term.len();
+                start
+            }
+            None => {
+                rank.exact = false;
+
+                let mut chars = term.chars().peekable();
+                let mut start = None;
+
+                for (i, c) in path[position..].char_indices() {
+                    if chars.peek() == Some(&c) {
+                        chars.next();
+                        start = start.or(Some(position + i));
+
+                        if chars.peek().is_none() {
+                            position += i + c.len_utf8();
+                            break;
+                        }
+                    }
+                }
+
+                if chars.peek().is_some() {
+                    return None;
+                }
+
+                start.unwrap_or(position)
+            }
+        };
+
+        rank.last_component = start >= last_component_start;
+    }
+
+    Some(rank)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terms_match_in_order() {
+        assert!(rank("/home/me/src/riptide", &["src", "rip"]).is_some());
+        assert!(rank("/home/me/src/riptide", &["rip", "src"]).is_none());
+        assert!(rank("/home/me/Downloads", &["dwn"]).is_some());
+        assert!(rank("/home/me/Downloads", &["xyz"]).is_none());
+    }
+
+    #[test]
+    fn better_matches_rank_higher() {
+        let last = rank("/home/me/src", &["src"]).unwrap();
+        let middle = rank("/home/me/src/riptide", &["src"]).unwrap();
+        let fuzzy = rank("/home/me/sources", &["src"]).unwrap();
+
+        assert!(last > middle);
+        assert!(last > fuzzy);
+        assert!(!fuzzy.exact);
+    }
+
+    #[test]
+    fn frecent_directories_are_found() {
+        let history = History::in_memory().unwrap();
+
+        // The current directory is never found, so use one that can't be it.
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("tmp");
+        std::fs::create_dir(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+
+        history.add_directory("/no/such/tmp");
+        history.add_directory("/no/such/tmp");
+        history.add_directory(dir);
+
+        let top = history.frecent_directories().next().unwrap();
+        assert_eq!(top.path(), "/no/such/tmp");
+        assert_eq!(top.score(), 8.0);
+
+        // Directories that no longer exist are skipped.
+        assert_eq!(history.find_directory(&["tmp"]).as_deref(), Some(dir));
+    }
+
+    #[test]
+    fn bookmarks_can_be_replaced_and_removed() {
+        let history = History::in_memory().unwrap();
+
+        history.set_bookmark("src", "/a").unwrap();
+        history.set_bookmark("src", "/b").unwrap();
+        history.set_bookmark("docs", "/c").unwrap();
+
+        assert_eq!(history.bookmark("src").unwrap(), "/b");
+        assert_eq!(history.bookmarks(), vec![("docs".into(), "/c".into()), ("src".into(), "/b".into())]);
+
+        assert!(history.remove_bookmark("src").unwrap());
+        assert!(!history.remove_bookmark("src").unwrap());
+        assert!(history.bookmark("src").is_none());
+    }
+}
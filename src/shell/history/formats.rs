@@ -0,0 +1,301 @@
+//! Reading and writing history in the formats used by other shells.
+
+use super::CommandEntry;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// A shell whose history file can be imported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// A command read from the history file of another shell.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedCommand {
+    pub command: String,
+
+    /// When the command was run in seconds since the Unix epoch, if recorded.
+    pub timestamp: Option<i64>,
+
+    /// How long the command took to run, if recorded.
+    pub duration: Option<Duration>,
+}
+
+impl Shell {
+    /// Get the usual location of the shell's history file for the current user.
+    pub fn default_path(self) -> Option<PathBuf> {
+        let dirs = directories::BaseDirs::new()?;
+
+        Some(match self {
+            Shell::Bash => dirs.home_dir().join(".bash_history"),
+            Shell::Zsh => dirs.home_dir().join(".zsh_history"),
+            Shell::Fish => dirs.data_local_dir().join("fish").join("fish_history"),
+        })
+    }
+
+    /// Parse the contents of a history file, returning the commands in the
+    /// order they appear.
+    pub fn parse(self, bytes: &[u8]) -> Vec<ImportedCommand> {
+        match self {
+            Shell::Bash => parse_bash(&String::from_utf8_lossy(bytes)),
+            Shell::Zsh => parse_zsh(&String::from_utf8_lossy(&unmetafy(bytes))),
+            Shell::Fish => parse_fish(&String::from_utf8_lossy(bytes)),
+        }
+    }
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!("unsupported shell: {}", s)),
+        }
+    }
+}
+
+/// Parse a Bash history file.
+///
+/// If `HISTTIMEFORMAT` was set, each command is preceded by a `#` comment
+/// containing its timestamp, and every line up to the next timestamp belongs to
+/// the same command. Otherwise each line is a separate command.
+fn parse_bash(text: &str) -> Vec<ImportedCommand> {
+    let mut commands: Vec<ImportedCommand> = Vec::new();
+    let mut continues = false;
+
+    for line in text.lines() {
+        if line.starts_with('#') && line.len() > 1 && line[1..].bytes().all(|b| b.is_ascii_digit()) {
+            commands.push(ImportedCommand {
+                command: String::new(),
+                timestamp: line[1..].parse().ok(),
+                duration: None,
+            });
+            continues = false;
+            continue;
+        }
+
+        match commands.last_mut() {
+            Some(last) if last.timestamp.is_some() => {
+                if continues {
+                    last.command.push('\n');
+                }
+                last.command.push_str(line);
+                continues = true;
+            }
+            _ => commands.push(ImportedCommand {
+                command: line.to_owned(),
+                timestamp: None,
+                duration: None,
+            }),
+        }
+    }
+
+    commands.retain(|command| !command.command.trim().is_empty());
+    commands
+}
+
+/// Parse a Zsh history file, in either the plain or the extended format.
+///
+/// Extended entries look like `: <start>:<elapsed>;<command>`. In both formats
+/// a line ending with a backslash continues onto the next line.
+fn parse_zsh(text: &str) -> Vec<ImportedCommand> {
+    let mut commands = Vec::new();
+    let mut lines = text.lines();
+
+    while let Some(line) = lines.next() {
+        let mut command = ImportedCommand {
+            command: line.to_owned(),
+            timestamp: None,
+            duration: None,
+        };
+
+        if let Some((timestamp, duration, rest)) = parse_zsh_extended(line) {
+            command.timestamp = Some(timestamp);
+            command.duration = Some(Duration::from_secs(duration));
+            command.command = rest.to_owned();
+        }
+
+        while command.command.ends_with('\\') {
+            command.command.pop();
+
+            match lines.next() {
+                Some(line) => {
+                    command.command.push('\n');
+                    command.command.push_str(line);
+                }
+                None => break,
+            }
+        }
+
+        if !command.command.trim().is_empty() {
+            commands.push(command);
+        }
+    }
+
+    commands
+}
+
+fn parse_zsh_extended(line: &str) -> Option<(i64, u64, &str)> {
+    let line = line.strip_prefix(": ")?;
+    let (metadata, command) = line.split_at(line.find(';')?);
+    let mut fields = metadata.splitn(2, ':');
+
+    let timestamp = fields.next()?.trim().parse().ok()?;
+    let duration = fields.next()?.trim().parse().ok()?;
+
+    Some((timestamp, duration, &command[1..]))
+}
+
+/// Undo the encoding Zsh uses for special bytes in its history file, where a
+/// byte is written as `0x83` followed by the byte XOR 32.
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+
+    while let Some(&byte) = bytes.next() {
+        if byte == 0x83 {
+            if let Some(&next) = bytes.next() {
+                output.push(next ^ 32);
+            }
+        } else {
+            output.push(byte);
+        }
+    }
+
+    output
+}
+
+/// Parse a Fish history file.
+///
+/// Fish writes a YAML-like list of entries, each with a `cmd` and a `when`
+/// field, and possibly a list of `paths` which is ignored.
+fn parse_fish(text: &str) -> Vec<ImportedCommand> {
+    let mut commands: Vec<ImportedCommand> = Vec::new();
+
+    for line in text.lines() {
+        if let Some(command) = line.strip_prefix("- cmd: ") {
+            commands.push(ImportedCommand {
+                command: unescape_fish(command),
+                timestamp: None,
+                duration: None,
+            });
+        } else if let Some(when) = line.strip_prefix("  when: ") {
+            if let Some(last) = commands.last_mut() {
+                last.timestamp = when.trim().parse().ok();
+            }
+        }
+    }
+
+    commands.retain(|command| !command.command.trim().is_empty());
+    commands
+}
+
+/// Undo the escaping of newlines and backslashes in a Fish history entry.
+fn unescape_fish(s: &str) -> String {
+    let mut output = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => output.push('\n'),
+                Some(c) => output.push(c),
+                None => output.push('\\'),
+            }
+        } else {
+            output.push(c);
+        }
+    }
+
+    output
+}
+
+/// Format an entry as a single-line JSON object.
+pub fn to_json(entry: &CommandEntry) -> String {
+    let timestamp = entry.timestamp()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    format!(
+        "{{\"command\":{},\"cwd\":{},\"timestamp\":{},\"exit_status\":{},\"duration_ms\":{}}}",
+        json_string(entry.command()),
+        entry.cwd().map(json_string).unwrap_or_else(|| String::from("null")),
+        timestamp,
+        entry.exit_status().map(|status| status.to_string()).unwrap_or_else(|| String::from("null")),
+        entry.duration().map(|duration| duration.as_millis().to_string()).unwrap_or_else(|| String::from("null")),
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut output = String::with_capacity(s.len() + 2);
+    output.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(output, "\\u{:04x}", c as u32).unwrap(),
+            c => output.push(c),
+        }
+    }
+
+    output.push('"');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(parsed: Vec<ImportedCommand>) -> Vec<String> {
+        parsed.into_iter().map(|command| command.command).collect()
+    }
+
+    #[test]
+    fn bash_with_and_without_timestamps() {
+        assert_eq!(commands(parse_bash("ls\ncd /tmp\n\nls\n")), vec!["ls", "cd /tmp", "ls"]);
+
+        let parsed = parse_bash("#1580000000\nfor x in a b\ndo echo $x\ndone\n#1580000100\nls\n");
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].command, "for x in a b\ndo echo $x\ndone");
+        assert_eq!(parsed[0].timestamp, Some(1_580_000_000));
+        assert_eq!(parsed[1].command, "ls");
+    }
+
+    #[test]
+    fn zsh_extended_history() {
+        let parsed = Shell::Zsh.parse(b": 1580000000:3;make\n: 1580000010:0;echo a\\\nb\nplain\n");
+
+        assert_eq!(commands(parsed.clone()), vec!["make", "echo a\nb", "plain"]);
+        assert_eq!(parsed[0].timestamp, Some(1_580_000_000));
+        assert_eq!(parsed[0].duration, Some(Duration::from_secs(3)));
+        assert_eq!(parsed[2].timestamp, None);
+
+        assert_eq!(commands(Shell::Zsh.parse(b"echo \xe2\x80\x83\xb4\n")), vec!["echo \u{2014}"]);
+    }
+
+    #[test]
+    fn fish_history() {
+        let parsed = parse_fish("- cmd: echo a\\nb\n  when: 1580000000\n  paths:\n    - b\n- cmd: ls\n  when: 1580000001\n");
+
+        assert_eq!(commands(parsed.clone()), vec!["echo a\nb", "ls"]);
+        assert_eq!(parsed[1].timestamp, Some(1_580_000_001));
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("a \"b\"\n\\\u{1}"), "\"a \\\"b\\\"\\n\\\\\\u0001\"");
+    }
+}
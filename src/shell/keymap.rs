@@ -0,0 +1,297 @@
+//! Key bindings for the line editor.
+//!
+//! A keymap is a table that maps keys to what they do, either the name of an
+//! editor action or a function. Keys are named like `a`, `ctrl-a`, `alt-a`, or
+//! `up`, and a sequence of keys pressed one after another, such as
+//! `ctrl-x ctrl-e`, is named by joining their names with spaces.
+//!
+//! The keymaps are kept in the `keymaps` table of the global `editor` table,
+//! with one keymap for each editing mode.
+
+use super::editor::Action;
+use super::event::Event;
+use crate::runtime::prelude::*;
+
+/// Name of the key in the editor settings holding the keymaps.
+pub(crate) const KEYMAPS_KEY: &str = "keymaps";
+
+/// Names of the keymaps used in each editing mode.
+pub(crate) const KEYMAPS: &[&str] = &["emacs", "vi-insert", "vi-normal"];
+
+/// Keys bound in the `emacs` keymap by default, following readline.
+const EMACS_BINDINGS: &[(&str, &str)] = &[
+    ("enter", "accept-line"),
+    ("ctrl-c", "cancel-line"),
+    ("left", "backward-char"),
+    ("ctrl-b", "backward-char"),
+    ("right", "forward-char"),
+    ("ctrl-f", "forward-char"),
+    ("ctrl-left", "backward-word"),
+    ("alt-b", "backward-word"),
+    ("ctrl-right", "forward-word"),
+    ("alt-f", "forward-word"),
+    ("home", "beginning-of-line"),
+    ("ctrl-a", "beginning-of-line"),
+    ("end", "end-of-line"),
+    ("ctrl-e", "end-of-line"),
+    ("up", "previous-line"),
+    ("ctrl-p", "previous-line"),
+    ("down", "next-line"),
+    ("ctrl-n", "next-line"),
+    ("backspace", "backward-delete-char"),
+    ("ctrl-h", "backward-delete-char"),
+    ("delete", "delete-char"),
+    ("ctrl-d", "delete-char"),
+    ("ctrl-u", "backward-kill-line"),
+    ("ctrl-k", "kill-line"),
+    ("alt-backspace", "backward-kill-word"),
+    ("alt-d", "kill-word"),
+    ("ctrl-w", "unix-word-rubout"),
+    ("ctrl-t", "transpose-chars"),
+    ("ctrl-y", "yank"),
+    ("alt-y", "yank-pop"),
+    ("ctrl-_", "undo"),
+    ("alt-z", "redo"),
+    ("tab", "complete"),
+    ("backtab", "complete-backward"),
+    ("ctrl-r", "history-search"),
+    ("alt-left", "previous-directory"),
+    ("alt-right", "next-directory"),
+];
+
+/// Keys bound in the `vi-normal` keymap by default. Other keys are vi
+/// commands.
+const VI_NORMAL_BINDINGS: &[(&str, &str)] = &[
+    ("enter", "accept-line"),
+    ("ctrl-c", "cancel-line"),
+    ("up", "previous-line"),
+    ("down", "next-line"),
+    ("tab", "complete"),
+    ("backtab", "complete-backward"),
+    ("alt-left", "previous-directory"),
+    ("alt-right", "next-directory"),
+];
+
+/// What a sequence of keys is bound to.
+pub enum Binding {
+    /// The keys are bound to an action name or function.
+    Found(Value),
+
+    /// The keys are the start of longer sequences that are bound.
+    Prefix,
+
+    /// The keys are not bound.
+    Unbound,
+}
+
+/// Create the default keymaps table.
+pub fn default_keymaps() -> Table {
+    // Vi handles deleting characters in insert mode itself, so that they can
+    // be repeated as part of a change.
+    let vi_insert = EMACS_BINDINGS.iter()
+        .filter(|(_, action)| *action != "backward-delete-char" && *action != "delete-char")
+        .cloned()
+        .collect::<Table>();
+
+    table! {
+        "emacs" => EMACS_BINDINGS.iter().cloned().collect::<Table>(),
+        "vi-insert" => vi_insert,
+        "vi-normal" => VI_NORMAL_BINDINGS.iter().cloned().collect::<Table>(),
+    }
+}
+
+/// Look up what a sequence of keys is bound to in a keymap.
+pub fn lookup(keymap: &Value, keys: &[Event]) -> Binding {
+    let name = keys_name(keys);
+    let binding = keymap.get(&name);
+
+    if !binding.is_nil() {
+        return Binding::Found(binding);
+    }
+
+    let prefix = format!("{} ", name);
+    let is_prefix = keymap.as_table()
+        .map(|keymap| {
+            keymap.keys().any(|key| key.as_utf8().map(|key| key.starts_with(&prefix)).unwrap_or(false))
+        })
+        .unwrap_or(false);
+
+    if is_prefix {
+        Binding::Prefix
+    } else {
+        Binding::Unbound
+    }
+}
+
+/// Check whether a binding is something that keys can be bound to.
+pub fn is_valid_binding(binding: &Value) -> bool {
+    match binding {
+        Value::Block(_) | Value::ForeignFn(_) => true,
+        Value::String(name) => name.as_utf8().and_then(Action::from_name).is_some(),
+        _ => false,
+    }
+}
+
+/// Parse a sequence of key names separated by spaces into the name they are
+/// bound with, or `None` if any of them is not a valid key.
+pub fn parse_keys(keys: &str) -> Option<String> {
+    let keys = keys.split_whitespace()
+        .map(parse_key)
+        .collect::<Option<Vec<_>>>()?;
+
+    if keys.is_empty() {
+        None
+    } else {
+        Some(keys_name(&keys))
+    }
+}
+
+fn keys_name(keys: &[Event]) -> String {
+    keys.iter()
+        .map(key_name)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Get the name of a key.
+pub fn key_name(key: &Event) -> String {
+    match key {
+        Event::Char('\n') => String::from("enter"),
+        Event::Char(' ') => String::from("space"),
+        Event::Char('\0') => String::from("ctrl-space"),
+        Event::Char(c) => c.to_string(),
+        Event::Up => String::from("up"),
+        Event::Down => String::from("down"),
+        Event::Left => String::from("left"),
+        Event::Right => String::from("right"),
+        Event::AltLeft => String::from("alt-left"),
+        Event::AltRight => String::from("alt-right"),
+        Event::CtrlLeft => String::from("ctrl-left"),
+        Event::CtrlRight => String::from("ctrl-right"),
+        Event::PageUp => String::from("pageup"),
+        Event::PageDown => String::from("pagedown"),
+        Event::Home => String::from("home"),
+        Event::End => String::from("end"),
+        Event::Insert => String::from("insert"),
+        Event::Escape => String::from("escape"),
+        Event::Tab => String::from("tab"),
+        Event::BackTab => String::from("backtab"),
+        Event::Backspace => String::from("backspace"),
+        Event::AltBackspace => String::from("alt-backspace"),
+        Event::Delete => String::from("delete"),
+        // Control characters after Ctrl-Z are received as Ctrl and a digit.
+        Event::Ctrl('4') => String::from("ctrl-\\"),
+        Event::Ctrl('5') => String::from("ctrl-]"),
+        Event::Ctrl('6') => String::from("ctrl-^"),
+        Event::Ctrl('7') => String::from("ctrl-_"),
+        Event::Ctrl(c) => format!("ctrl-{}", c),
+        Event::Alt(c) => format!("alt-{}", key_name(&Event::Char(*c))),
+        // Pastes are inserted without being looked up.
+        Event::Paste(_) => String::from("paste"),
+    }
+}
+
+/// Parse the name of a key.
+fn parse_key(name: &str) -> Option<Event> {
+    if let Some(key) = name.strip_prefix("ctrl-") {
+        return match key {
+            "space" => Some(Event::Char('\0')),
+            "left" => Some(Event::CtrlLeft),
+            "right" => Some(Event::CtrlRight),
+            "i" => Some(Event::Tab),
+            "j" | "m" => Some(Event::Char('\n')),
+            "\\" => Some(Event::Ctrl('4')),
+            "]" => Some(Event::Ctrl('5')),
+            "^" => Some(Event::Ctrl('6')),
+            "_" => Some(Event::Ctrl('7')),
+            _ => match single_char(key) {
+                Some(c) if c.is_ascii_alphabetic() => Some(Event::Ctrl(c.to_ascii_lowercase())),
+                _ => None,
+            },
+        };
+    }
+
+    if let Some(key) = name.strip_prefix("alt-") {
+        return match key {
+            "left" => Some(Event::AltLeft),
+            "right" => Some(Event::AltRight),
+            "backspace" => Some(Event::AltBackspace),
+            _ => match parse_key(key)? {
+                Event::Char(c) if c.is_ascii_graphic() || c == ' ' => Some(Event::Alt(c)),
+                _ => None,
+            },
+        };
+    }
+
+    Some(match name {
+        "enter" | "return" => Event::Char('\n'),
+        "space" => Event::Char(' '),
+        "up" => Event::Up,
+        "down" => Event::Down,
+        "left" => Event::Left,
+        "right" => Event::Right,
+        "pageup" => Event::PageUp,
+        "pagedown" => Event::PageDown,
+        "home" => Event::Home,
+        "end" => Event::End,
+        "insert" => Event::Insert,
+        "escape" | "esc" => Event::Escape,
+        "tab" => Event::Tab,
+        "backtab" | "shift-tab" => Event::BackTab,
+        "backspace" => Event::Backspace,
+        "delete" => Event::Delete,
+        _ => Event::Char(single_char(name)?),
+    })
+}
+
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_names_are_normalized() {
+        assert_eq!(parse_keys("ctrl-X  ctrl-e").as_deref(), Some("ctrl-x ctrl-e"));
+        assert_eq!(parse_keys("alt-. esc return").as_deref(), Some("alt-. escape enter"));
+        assert_eq!(parse_keys("ctrl-_ shift-tab").as_deref(), Some("ctrl-_ backtab"));
+        assert_eq!(parse_keys("alt-space é").as_deref(), Some("alt-space é"));
+        assert_eq!(parse_keys("ctrl-1"), None);
+        assert_eq!(parse_keys("hyper-a"), None);
+        assert_eq!(parse_keys(""), None);
+    }
+
+    #[test]
+    fn default_bindings_are_valid() {
+        for (key, action) in EMACS_BINDINGS.iter().chain(VI_NORMAL_BINDINGS) {
+            assert_eq!(parse_keys(key).as_deref(), Some(*key));
+            assert!(Action::from_name(action).is_some(), "{}", action);
+        }
+    }
+
+    #[test]
+    fn chords_are_looked_up_by_prefix() {
+        let keymap = Value::from(table! {
+            "ctrl-x ctrl-e" => "undo",
+            "a" => "redo",
+        });
+
+        let bound = |keys: &[Event]| match lookup(&keymap, keys) {
+            Binding::Found(binding) => binding.to_string(),
+            Binding::Prefix => String::from("prefix"),
+            Binding::Unbound => String::from("unbound"),
+        };
+
+        assert_eq!(bound(&[Event::Ctrl('x')]), "prefix");
+        assert_eq!(bound(&[Event::Ctrl('x'), Event::Ctrl('e')]), "undo");
+        assert_eq!(bound(&[Event::Ctrl('e')]), "unbound");
+        assert_eq!(bound(&[Event::Char('a')]), "redo");
+    }
+}
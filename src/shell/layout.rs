@@ -0,0 +1,143 @@
+//! Working out where text ends up when drawn in a terminal.
+//!
+//! Terminals wrap text that reaches the right edge onto the next row, so the
+//! editor needs to know how many rows the command line takes up to move the
+//! cursor around it and to draw it again.
+
+use super::buffer::{grapheme_width, strip_escapes};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A row and column in the terminal, counting from where drawing started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    pub row: usize,
+    pub column: usize,
+}
+
+/// Follows the position of the terminal cursor as text is drawn.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    columns: usize,
+    position: Position,
+
+    /// Whether the last row drawn was filled to the right edge.
+    ///
+    /// The terminal leaves the cursor in the last column until more text is
+    /// drawn, but the position is taken to be at the start of the next row
+    /// already.
+    wrap_pending: bool,
+}
+
+impl Layout {
+    /// Start following text drawn in a terminal with the given number of
+    /// columns.
+    pub fn new(columns: usize) -> Self {
+        Self {
+            columns: columns.max(1),
+            position: Position::default(),
+            wrap_pending: false,
+        }
+    }
+
+    /// Find where the cursor ends up after drawing some text.
+    pub fn of(text: &str, columns: usize) -> Self {
+        let mut layout = Self::new(columns);
+        layout.advance(text);
+        layout
+    }
+
+    /// Get the position of the cursor after the text drawn so far.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Check whether the text drawn so far fills the last row to the right
+    /// edge, leaving the terminal cursor at the end of the row rather than at
+    /// the position.
+    pub fn is_wrap_pending(&self) -> bool {
+        self.wrap_pending
+    }
+
+    /// Move past text drawn at the current position. Newlines are taken to be
+    /// drawn as a carriage return and a line feed, and escape sequences take up
+    /// no space.
+    pub fn advance(&mut self, text: &str) {
+        for grapheme in strip_escapes(text).graphemes(true) {
+            if grapheme == "\n" || grapheme == "\r\n" {
+                self.newline();
+            } else {
+                self.advance_columns(grapheme_width(grapheme));
+            }
+        }
+    }
+
+    fn advance_columns(&mut self, width: usize) {
+        if width == 0 {
+            return;
+        }
+
+        // Wide characters that don't fit at the end of a row are drawn at the
+        // start of the next one instead.
+        if self.position.column + width > self.columns && self.position.column > 0 {
+            self.position.row += 1;
+            self.position.column = 0;
+        }
+
+        self.position.column += width;
+        self.wrap_pending = false;
+
+        if self.position.column >= self.columns {
+            self.position.row += 1;
+            self.position.column = 0;
+            self.wrap_pending = true;
+        }
+    }
+
+    fn newline(&mut self) {
+        // A pending wrap has already moved the position to the next row, and
+        // the terminal moves there with the line feed.
+        if !self.wrap_pending {
+            self.position.row += 1;
+        }
+
+        self.position.column = 0;
+        self.wrap_pending = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(text: &str, columns: usize) -> (usize, usize) {
+        let position = Layout::of(text, columns).position();
+        (position.row, position.column)
+    }
+
+    #[test]
+    fn long_lines_wrap() {
+        assert_eq!(position("", 10), (0, 0));
+        assert_eq!(position("$ echo", 10), (0, 6));
+        assert_eq!(position("$ echo hello", 10), (1, 2));
+        assert_eq!(position("$ echo hello world", 10), (1, 8));
+        assert_eq!(position("\x1b[1m$ echo\x1b[0m hello", 10), (1, 2));
+    }
+
+    #[test]
+    fn filling_a_row_moves_to_the_next() {
+        let layout = Layout::of("$ echo abc", 10);
+        assert_eq!(layout.position(), Position { row: 1, column: 0 });
+        assert!(layout.is_wrap_pending());
+
+        // The newline only moves to the row the position is already on.
+        assert_eq!(position("$ echo abc\n> x", 10), (1, 3));
+        assert_eq!(position("$ echo ab\n> x", 10), (1, 3));
+        assert_eq!(position("$ echo ab\n\n", 10), (2, 0));
+    }
+
+    #[test]
+    fn wide_characters_do_not_split_across_rows() {
+        assert_eq!(position("$ echo a漢", 10), (1, 0));
+        assert_eq!(position("$ echo ab漢", 10), (1, 2));
+    }
+}
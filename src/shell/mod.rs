@@ -1,8 +1,19 @@
 mod buffer;
+pub mod builtins;
 mod command;
+mod completion;
 mod editor;
 mod event;
-// mod history;
+mod highlight;
+mod history;
+mod keymap;
+mod layout;
 mod os;
+pub mod plugins;
+mod prompt;
+mod vi;
 
+pub use self::completion::Completer;
 pub use self::editor::Editor;
+pub use self::highlight::Highlighter;
+pub use self::history::History;
@@ -1,14 +1,41 @@
-use crate::shell::event::Event;
+use crate::{pipes::nix_error, shell::event::Event};
+use nix::{
+    fcntl::{fcntl, FcntlArg, OFlag},
+    poll::{poll, PollFd, PollFlags},
+};
 use std::{
     collections::VecDeque,
     io,
+    os::unix::io::AsRawFd,
+    time::Duration,
 };
 use tokio::io::{AsyncRead, AsyncReadExt};
 
+/// How long to wait for the rest of an escape sequence before taking an escape
+/// character to be the Escape key.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(25);
+
 pub struct TerminalInput<I> {
     stdin: I,
     events: VecDeque<Event>,
     parser: vte::Parser,
+    escape: EscapeState,
+
+    /// Text pasted so far, while a paste is being received.
+    paste: Option<String>,
+}
+
+/// Progress through a key prefixed with an escape character.
+#[derive(Clone, Copy, PartialEq)]
+enum EscapeState {
+    None,
+
+    /// An escape character was received.
+    Escape,
+
+    /// An escape character and `O` were received, which begin the sequences
+    /// some terminals send for arrow keys.
+    SingleShift,
 }
 
 impl<I> TerminalInput<I> {
@@ -17,16 +44,109 @@ impl<I> TerminalInput<I> {
             stdin,
             events: VecDeque::default(),
             parser: vte::Parser::new(),
+            escape: EscapeState::None,
+            paste: None,
         }
     }
 
+    /// Parse a byte of input.
+    ///
+    /// Keys pressed with Alt are sent as the key prefixed with an escape
+    /// character, which is handled here rather than by the parser so that any
+    /// key can be combined with Alt.
     fn parse_input(&mut self, byte: u8) {
+        // Pasted text is not made up of keys, so nothing in it is pressed with
+        // Alt.
+        if self.paste.is_some() {
+            self.advance(byte);
+            return;
+        }
+
+        match (self.escape, byte) {
+            (EscapeState::None, 0x1b) => {
+                self.escape = EscapeState::Escape;
+                return;
+            }
+            (EscapeState::None, _) => {}
+            (EscapeState::Escape, b'[') => {
+                self.escape = EscapeState::None;
+                self.advance(0x1b);
+            }
+            (EscapeState::Escape, b'O') => {
+                self.escape = EscapeState::SingleShift;
+                return;
+            }
+            (EscapeState::Escape, 0x1b) => {
+                self.events.push_back(Event::Escape);
+                return;
+            }
+            (EscapeState::Escape, 0x7f) => {
+                self.escape = EscapeState::None;
+                self.events.push_back(Event::AltBackspace);
+                return;
+            }
+            (EscapeState::Escape, 0x20..=0x7e) => {
+                self.escape = EscapeState::None;
+                self.events.push_back(Event::Alt(byte as char));
+                return;
+            }
+            (EscapeState::SingleShift, _) => {
+                self.escape = EscapeState::None;
+
+                let event = match byte {
+                    b'A' => Event::Up,
+                    b'B' => Event::Down,
+                    b'C' => Event::Right,
+                    b'D' => Event::Left,
+                    b'H' => Event::Home,
+                    b'F' => Event::End,
+                    _ => {
+                        self.events.push_back(Event::Alt('O'));
+                        return self.parse_input(byte);
+                    }
+                };
+
+                self.events.push_back(event);
+                return;
+            }
+            // Any other key after an escape is not pressed with Alt.
+            (EscapeState::Escape, _) => {
+                self.escape = EscapeState::None;
+                self.events.push_back(Event::Escape);
+            }
+        }
+
+        self.advance(byte);
+    }
+
+    /// Finish a key prefixed with an escape character once no more input is
+    /// coming.
+    fn finish_escape(&mut self) {
+        match self.escape {
+            EscapeState::None => {}
+            EscapeState::Escape => self.events.push_back(Event::Escape),
+            EscapeState::SingleShift => self.events.push_back(Event::Alt('O')),
+        }
+
+        self.escape = EscapeState::None;
+    }
+
+    /// Feed a byte to the parser for escape sequences and characters.
+    fn advance(&mut self, byte: u8) {
         struct Perform<'a> {
             events: &'a mut VecDeque<Event>,
+            paste: &'a mut Option<String>,
         }
 
         impl<'a> vte::Perform for Perform<'a> {
             fn print(&mut self, c: char) {
+                if let Some(paste) = self.paste.as_mut() {
+                    if c != '\x7f' {
+                        paste.push(c);
+                    }
+                    return;
+                }
+
                 self.events.push_back(match c {
                     '\x7f' => Event::Backspace,
                     c => Event::Char(c),
@@ -34,9 +154,19 @@ impl<I> TerminalInput<I> {
             }
 
             fn execute(&mut self, byte: u8) {
+                // Line breaks and tabs are the only control characters kept in
+                // pasted text.
+                if let Some(paste) = self.paste.as_mut() {
+                    if byte == b'\r' || byte == b'\n' || byte == b'\t' {
+                        paste.push(byte as char);
+                    }
+                    return;
+                }
+
                 let event = match byte {
                     0 => Some(Event::Char('\0')),
                     b'\r' | b'\n' => Some(Event::Char('\n')),
+                    b'\t' => Some(Event::Tab),
                     0x01..=0x1a => Some(Event::Ctrl((byte - 0x01 + b'a') as char)),
                     0x1c..=0x1f => Some(Event::Ctrl((byte - 0x1c + b'4') as char)),
                     _ => None,
@@ -70,17 +200,35 @@ impl<I> TerminalInput<I> {
                 ignore: bool,
                 c: char
             ) {
+                // Pastes are sent between the sequences `CSI 200 ~` and
+                // `CSI 201 ~` once bracketed paste mode is enabled.
+                if self.paste.is_some() {
+                    if c == '~' && params == [201] {
+                        let text = self.paste.take().unwrap();
+                        self.events.push_back(Event::Paste(text.replace("\r\n", "\n").replace('\r', "\n")));
+                    }
+                    return;
+                }
+
                 match (c, params) {
+                    // Arrow keys with a modifier parameter of 3 are pressed
+                    // with Alt, and with 5 are pressed with Ctrl.
+                    ('C', [1, 3]) => self.events.push_back(Event::AltRight),
+                    ('D', [1, 3]) => self.events.push_back(Event::AltLeft),
+                    ('C', [1, 5]) => self.events.push_back(Event::CtrlRight),
+                    ('D', [1, 5]) => self.events.push_back(Event::CtrlLeft),
                     ('A', _) => self.events.push_back(Event::Up),
                     ('B', _) => self.events.push_back(Event::Down),
                     ('C', _) => self.events.push_back(Event::Right),
                     ('D', _) => self.events.push_back(Event::Left),
                     ('F', _) | ('~', [4]) | ('~', [8]) => self.events.push_back(Event::End),
                     ('H', _) | ('~', [1]) | ('~', [7]) => self.events.push_back(Event::Home),
+                    ('Z', _) => self.events.push_back(Event::BackTab),
                     ('~', [2]) => self.events.push_back(Event::Insert),
                     ('~', [3]) => self.events.push_back(Event::Delete),
                     ('~', [5]) => self.events.push_back(Event::PageUp),
                     ('~', [6]) => self.events.push_back(Event::PageDown),
+                    ('~', [200]) => *self.paste = Some(String::new()),
                     _ => log::info!("CSI {:?} / {:?} / {} / {}", params, intermediates, ignore, c),
                 }
             }
@@ -98,13 +246,35 @@ impl<I> TerminalInput<I> {
 
         let mut perform = Perform {
             events: &mut self.events,
+            paste: &mut self.paste,
         };
 
         self.parser.advance(&mut perform, byte);
     }
 }
 
-impl<I: AsyncRead + Unpin> TerminalInput<I> {
+impl<I: AsRawFd> TerminalInput<I> {
+    /// Set whether reading waits for input to arrive.
+    ///
+    /// Input is read asynchronously only once it doesn't block, so that other
+    /// things can be waited for at the same time.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let fd = self.stdin.as_raw_fd();
+        let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL).map_err(nix_error)?);
+
+        let flags = if nonblocking {
+            flags | OFlag::O_NONBLOCK
+        } else {
+            flags - OFlag::O_NONBLOCK
+        };
+
+        fcntl(fd, FcntlArg::F_SETFL(flags)).map_err(nix_error)?;
+
+        Ok(())
+    }
+}
+
+impl<I: AsyncRead + AsRawFd + Unpin> TerminalInput<I> {
     pub async fn next_event(&mut self) -> io::Result<Event> {
         let mut buf = [0; 1024];
 
@@ -114,6 +284,13 @@ impl<I: AsyncRead + Unpin> TerminalInput<I> {
                 return Ok(event);
             }
 
+            // An escape character on its own is the Escape key, unless more of
+            // a sequence follows soon after.
+            if self.escape != EscapeState::None && !self.input_ready(ESCAPE_TIMEOUT) {
+                self.finish_escape();
+                continue;
+            }
+
             // Grab some more input.
             let count = self.stdin.read(&mut buf).await?;
 
@@ -123,4 +300,37 @@ impl<I: AsyncRead + Unpin> TerminalInput<I> {
             }
         }
     }
+
+    /// Wait up to a timeout for input to be available to read.
+    fn input_ready(&self, timeout: Duration) -> bool {
+        let mut fds = [PollFd::new(self.stdin.as_raw_fd(), PollFlags::POLLIN)];
+
+        // Assume input is ready if polling fails, so that reading reports the
+        // error.
+        poll(&mut fds, timeout.as_millis() as i32).map(|count| count > 0).unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[u8]) -> Vec<Event> {
+        let mut terminal = TerminalInput::new(());
+
+        for byte in input {
+            terminal.parse_input(*byte);
+        }
+
+        terminal.events.into_iter().collect()
+    }
+
+    #[test]
+    fn pastes_are_not_keys() {
+        assert_eq!(parse(b"a\x1b[200~echo 1\r\x1bb\tx\x1b[A\r\n\x1b[201~\r"), vec![
+            Event::Char('a'),
+            Event::Paste(String::from("echo 1\n\tx\n")),
+            Event::Char('\n'),
+        ]);
+    }
 }
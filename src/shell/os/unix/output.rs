@@ -1,6 +1,8 @@
 use crate::shell::command::Command;
+use nix::libc;
 use std::{
     io,
+    mem,
     pin::Pin,
     os::unix::io::{AsRawFd, RawFd},
     task::{Context, Poll},
@@ -34,6 +36,17 @@ impl<O: AsRawFd> TerminalOutput<O> {
             termios::tcsetattr(self.as_raw_fd(), 0, &self.normal_termios)
         }
     }
+
+    /// Get the size of the terminal as a number of columns and rows.
+    pub fn size(&self) -> io::Result<(usize, usize)> {
+        let mut size: libc::winsize = unsafe { mem::zeroed() };
+
+        if unsafe { libc::ioctl(self.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((size.ws_col as usize, size.ws_row as usize))
+    }
 }
 
 impl<O: AsyncWrite + AsRawFd + Unpin> TerminalOutput<O> {
@@ -41,6 +54,13 @@ impl<O: AsyncWrite + AsRawFd + Unpin> TerminalOutput<O> {
         self.write_all(match command {
             Command::ClearAfterCursor => String::from("\x1b[J"),
             Command::MoveCursorLeft(n) => format!("\x1b[{}D", n),
+            Command::MoveCursorRight(n) => format!("\x1b[{}C", n),
+            Command::MoveCursorUp(n) => format!("\x1b[{}A", n),
+            Command::MoveCursorDown(n) => format!("\x1b[{}B", n),
+            Command::SetDim => String::from("\x1b[2m"),
+            Command::ResetStyle => String::from("\x1b[0m"),
+            Command::EnableBracketedPaste => String::from("\x1b[?2004h"),
+            Command::DisableBracketedPaste => String::from("\x1b[?2004l"),
         }.as_bytes()).await
     }
 }
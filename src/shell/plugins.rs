@@ -0,0 +1,270 @@
+//! Plugins that extend the interactive shell.
+//!
+//! A plugin is a module in the `plugins` directory of the user's configuration
+//! directory, such as `~/.config/riptide/plugins/git.rip` for a plugin named
+//! `git`. Plugins export callback functions, which the shell calls at certain
+//! times. Loaded plugins are kept in the global `plugins` table, keyed by name.
+//!
+//! Exceptions raised by a callback are logged and otherwise ignored, so that a
+//! broken plugin cannot stop the shell from working.
+
+use super::completion::is_executable;
+use crate::runtime::foreign::ForeignFn;
+use crate::runtime::prelude::*;
+use crate::runtime::syntax::source::SourceFile;
+use directories::ProjectDirs;
+use futures::future::FutureExt;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the global table holding loaded plugins.
+pub(crate) const PLUGINS_GLOBAL: &str = "plugins";
+
+/// Called with no arguments once the plugin is loaded.
+pub(crate) const ON_INIT: &str = "on_init";
+
+/// Called with the same table as prompt functions before the prompt is shown.
+pub(crate) const ON_PROMPT: &str = "on_prompt";
+
+/// Called with the command line before it is run.
+pub(crate) const ON_COMMAND: &str = "on_command";
+
+/// Called with the name and arguments of a command that was not found.
+pub(crate) const ON_COMMAND_NOT_FOUND: &str = "on_command_not_found";
+
+/// Get the directory plugins are loaded from.
+pub fn plugins_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "Riptide").map(|dirs| dirs.config_dir().join("plugins"))
+}
+
+/// Load every plugin in the plugins directory, in order of name.
+pub async fn load_all(fiber: &mut Fiber) {
+    if let Some(dir) = plugins_dir() {
+        load_dir(fiber, &dir).await;
+    }
+}
+
+/// Load every plugin in a directory, in order of name.
+async fn load_dir(fiber: &mut Fiber, dir: &Path) {
+    let mut paths = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map(|extension| extension == "rip").unwrap_or(false))
+            .collect::<Vec<_>>(),
+        Err(_) => return,
+    };
+    paths.sort();
+
+    for path in paths {
+        if let Err(e) = load(fiber, &path).await {
+            log::error!("failed to load plugin {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Load a plugin from a file and call its `on_init` callback.
+pub async fn load(fiber: &mut Fiber, path: &Path) -> Result<(), Exception> {
+    let name = match path.file_stem().and_then(|name| name.to_str()) {
+        Some(name) => name.to_owned(),
+        None => throw!("invalid plugin name"),
+    };
+
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => throw!("{}", e),
+    };
+
+    // Run the plugin as a module named after it, so that its exports can also
+    // be found with `require`.
+    fiber.execute(Some(&name), SourceFile::named(name.as_str(), source)).await?;
+
+    let module = fiber.globals().get("modules").get("loaded").get(&name);
+
+    if let Some(plugins) = fiber.globals().get(PLUGINS_GLOBAL).as_table() {
+        plugins.set(name.as_str(), module.clone());
+    }
+
+    call(fiber, &name, ON_INIT, &module.get(ON_INIT), &[]).await;
+
+    Ok(())
+}
+
+/// Call a callback of every plugin that has one.
+pub async fn run_hook(fiber: &mut Fiber, hook: &str, args: &[Value]) {
+    for (name, callback) in callbacks(fiber, hook) {
+        call(fiber, &name, hook, &callback, args).await;
+    }
+}
+
+/// Call a callback of each plugin that has one until one of them returns
+/// something, and return it.
+pub async fn first_result(fiber: &mut Fiber, hook: &str, args: &[Value]) -> Option<Value> {
+    for (name, callback) in callbacks(fiber, hook) {
+        match call(fiber, &name, hook, &callback, args).await {
+            Value::Nil => {}
+            value => return Some(value),
+        }
+    }
+
+    None
+}
+
+/// Get the callbacks plugins have for a hook, in order of plugin name.
+fn callbacks(fiber: &Fiber, hook: &str) -> Vec<(String, Value)> {
+    let plugins = match fiber.globals().get(PLUGINS_GLOBAL).as_table() {
+        Some(plugins) => plugins,
+        None => return Vec::new(),
+    };
+
+    let mut callbacks = plugins.keys()
+        .map(|name| (name.to_string(), plugins.get(&name).get(hook)))
+        .filter(|(_, callback)| !callback.is_nil())
+        .collect::<Vec<_>>();
+    callbacks.sort_by(|a, b| a.0.cmp(&b.0));
+
+    callbacks
+}
+
+/// Call a plugin's callback, logging any exception it raises.
+async fn call(fiber: &mut Fiber, name: &str, hook: &str, callback: &Value, args: &[Value]) -> Value {
+    if callback.is_nil() {
+        return Value::Nil;
+    }
+
+    match fiber.invoke(callback, args).await {
+        Ok(value) => value,
+        Err(e) => {
+            log::error!("plugin {}: {} failed: {}", name, hook, e);
+            Value::Nil
+        }
+    }
+}
+
+/// Wrap the `command` function, which runs external commands, so that plugins
+/// can handle commands that don't exist.
+///
+/// If a plugin's `on_command_not_found` callback returns something other than
+/// nil, it is returned instead of running the command.
+pub fn command(command: Value) -> ForeignFn {
+    ForeignFn::from_closure(move |fiber, args| {
+        let command = command.clone();
+
+        async move {
+            let name = args.first().map(Value::to_string).unwrap_or_default();
+
+            if !name.is_empty() && !exists(&name) {
+                let args = [Value::from(name.as_str()), Value::from(&args[1..])];
+
+                if let Some(result) = first_result(fiber, ON_COMMAND_NOT_FOUND, &args).await {
+                    return Ok(result);
+                }
+            }
+
+            fiber.invoke(&command, args).await
+        }.boxed_local()
+    })
+}
+
+/// Check whether an external command exists.
+fn exists(name: &str) -> bool {
+    if name.contains('/') {
+        return is_executable(Path::new(name));
+    }
+
+    match env::var_os("PATH") {
+        Some(path) => env::split_paths(&path).any(|dir| is_executable(&dir.join(name))),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::testing::{block_on, execute, fiber};
+    use crate::shell::{builtins, History};
+
+    /// Create a shell fiber with plugins loaded from the given files.
+    async fn load_plugins(plugins: &[(&str, &str)]) -> Fiber {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, source) in plugins {
+            fs::write(dir.path().join(name), source).unwrap();
+        }
+
+        let mut fiber = fiber();
+        builtins::register(&fiber, &History::in_memory().unwrap());
+        fiber.globals().set("calls", "");
+
+        load_dir(&mut fiber, dir.path()).await;
+
+        fiber
+    }
+
+    /// Plugin source that records calls to a callback in the `calls` global.
+    fn recording(hook: &str, name: &str) -> String {
+        format!("export {} {{ table-set $GLOBALS calls \"$calls {}\" }}", hook, name)
+    }
+
+    #[test]
+    fn plugins_are_loaded_in_order_of_name() {
+        block_on(async {
+            let fiber = load_plugins(&[
+                ("b.rip", &recording(ON_INIT, "b")),
+                ("a.rip", &recording(ON_INIT, "a")),
+                ("c.txt", &recording(ON_INIT, "c")),
+            ]).await;
+
+            assert_eq!(fiber.globals().get("calls"), " a b");
+            assert!(!fiber.globals().get(PLUGINS_GLOBAL).get("a").is_nil());
+            assert!(fiber.globals().get(PLUGINS_GLOBAL).get("c").is_nil());
+        });
+    }
+
+    #[test]
+    fn exceptions_do_not_stop_other_plugins_from_loading() {
+        block_on(async {
+            let fiber = load_plugins(&[
+                ("a.rip", "export on_init { throw broken }"),
+                ("b.rip", "throw broken"),
+                ("c.rip", &recording(ON_INIT, "c")),
+            ]).await;
+
+            assert_eq!(fiber.globals().get("calls"), " c");
+            assert!(!fiber.globals().get(PLUGINS_GLOBAL).get("a").is_nil());
+            assert!(fiber.globals().get(PLUGINS_GLOBAL).get("b").is_nil());
+        });
+    }
+
+    #[test]
+    fn hooks_are_called_in_order_of_plugin_name() {
+        block_on(async {
+            let mut fiber = load_plugins(&[
+                ("b.rip", &recording(ON_COMMAND, "b")),
+                ("a.rip", &recording(ON_COMMAND, "a")),
+                ("c.rip", "export on_command { throw broken }"),
+                ("d.rip", &recording(ON_COMMAND, "d")),
+            ]).await;
+
+            run_hook(&mut fiber, ON_COMMAND, &[Value::from("ls")]).await;
+
+            assert_eq!(fiber.globals().get("calls"), " a b d");
+        });
+    }
+
+    #[test]
+    fn missing_commands_are_substituted() {
+        block_on(async {
+            let mut fiber = load_plugins(&[
+                ("a.rip", "export on_command_not_found <name args> { nil }"),
+                ("b.rip", "export on_command_not_found <name args> { list $name $args }"),
+            ]).await;
+
+            let (result, _) = execute(&mut fiber, "riptide-missing-command arg").await;
+            assert_eq!(result.unwrap(), Value::from(vec![
+                Value::from("riptide-missing-command"),
+                Value::from(vec![Value::from("arg")]),
+            ]));
+        });
+    }
+}
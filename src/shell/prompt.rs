@@ -0,0 +1,117 @@
+//! Information about the session shown in command prompts.
+//!
+//! Prompts are set with the `prompt` and `right-prompt` keys of the global
+//! `editor` table, either to a string or to a function that returns one. A
+//! function is called before each command line with a table describing the
+//! state of the session.
+
+use crate::runtime::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Name of the key in the editor settings holding the prompt.
+pub(crate) const PROMPT_KEY: &str = "prompt";
+
+/// Name of the key in the editor settings holding the prompt shown at the
+/// right edge of the terminal.
+pub(crate) const RIGHT_PROMPT_KEY: &str = "right-prompt";
+
+/// How the previous command line finished.
+#[derive(Clone, Copy, Debug)]
+pub struct LastCommand {
+    pub exit_status: i32,
+    pub duration: Duration,
+}
+
+/// Create the table passed to prompt functions.
+pub fn context(last_command: Option<LastCommand>, cwd: Option<&Path>, vi_mode: Option<&str>) -> Table {
+    let context = table!();
+
+    if let Some(last_command) = last_command {
+        context.set("status", last_command.exit_status as f64);
+        context.set("duration", last_command.duration.as_secs_f64());
+    }
+
+    if let Some(cwd) = cwd {
+        context.set("cwd", abbreviate_home(cwd));
+
+        if let Some(branch) = git_branch(cwd) {
+            context.set("git-branch", branch);
+        }
+    }
+
+    if let Some(vi_mode) = vi_mode {
+        context.set("vi-mode", vi_mode);
+    }
+
+    context
+}
+
+/// Format a path with the home directory replaced by `~`.
+fn abbreviate_home(path: &Path) -> String {
+    let home = directories::UserDirs::new().map(|dirs| dirs.home_dir().to_owned());
+
+    match home.as_ref().and_then(|home| path.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => String::from("~"),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Get the branch checked out in the Git repository containing a directory, or
+/// the start of the commit hash if no branch is checked out.
+///
+/// The repository files are read directly rather than running `git`, so that
+/// drawing the prompt stays fast.
+pub fn git_branch(dir: &Path) -> Option<String> {
+    let git_dir = find_git_dir(dir)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(reference.strip_prefix("refs/heads/").unwrap_or(reference).to_owned()),
+        None => head.get(..7).map(String::from),
+    }
+}
+
+/// Find the Git directory of the repository containing a directory.
+fn find_git_dir(dir: &Path) -> Option<PathBuf> {
+    for dir in dir.ancestors() {
+        let path = dir.join(".git");
+
+        if path.is_dir() {
+            return Some(path);
+        }
+
+        // Worktrees and submodules have a file pointing to the Git directory
+        // instead.
+        if let Ok(contents) = fs::read_to_string(&path) {
+            return contents.trim().strip_prefix("gitdir: ").map(|git_dir| dir.join(git_dir));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn git_branch_is_read_from_head() {
+        let dir = env::temp_dir().join(format!("riptide-prompt-test-{}", std::process::id()));
+        let nested = dir.join("src").join("shell");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+
+        fs::write(dir.join(".git").join("HEAD"), "ref: refs/heads/feature/prompt\n").unwrap();
+        assert_eq!(git_branch(&nested).as_deref(), Some("feature/prompt"));
+
+        fs::write(dir.join(".git").join("HEAD"), "2b79e45ac1f0d3b1c0a4e9b8f6d1e2c3a4b5c6d7\n").unwrap();
+        assert_eq!(git_branch(&dir).as_deref(), Some("2b79e45"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
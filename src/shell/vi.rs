@@ -0,0 +1,977 @@
+//! Modal editing with vi-style key bindings.
+//!
+//! Each command line starts in insert mode, where keys are typed into the
+//! buffer as in the default mode. Pressing Escape switches to normal mode, in
+//! which keys are commands made up of an optional count, an operator, and a
+//! motion or text object that the operator applies to, such as `2dw` or `ci"`.
+//! Visual mode selects text with motions for an operator to apply to.
+
+use super::buffer::Buffer;
+use super::editor::Action;
+use super::event::Event;
+use std::iter::Peekable;
+use std::mem;
+use std::ops::Range;
+use std::str::Chars;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The largest count accepted before a command.
+const MAX_COUNT: usize = 9999;
+
+/// Characters that name a text object after `i` or `a`.
+const TEXT_OBJECTS: &str = "wW\"'`()b{}B[]<>";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Insert,
+    Normal,
+    Visual,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Insert => "insert",
+            Mode::Normal => "normal",
+            Mode::Visual => "visual",
+        }
+    }
+}
+
+/// What the editor should do with a key after vi has handled it.
+pub enum Outcome {
+    /// The key was handled, or is part of a command still being typed.
+    Handled,
+
+    /// The key performs an editor action.
+    Action(Action),
+
+    /// The key has no meaning in vi, and is handled as it would be by default.
+    Unhandled,
+
+    /// The key is not a valid command.
+    Invalid,
+}
+
+/// State of vi-style editing, kept between command lines.
+pub struct Vi {
+    mode: Mode,
+
+    /// Keys typed so far of a command that isn't complete.
+    pending: String,
+
+    /// Position where the visual mode selection started.
+    anchor: usize,
+
+    /// Text last deleted or yanked, for putting with `p`.
+    register: Register,
+
+    /// The last character searched for in the line with `f`, `F`, `t`, or `T`,
+    /// and which of those was used.
+    last_find: Option<(char, char)>,
+
+    /// Keys of the last change, for repeating with `.`.
+    last_change: Vec<Event>,
+
+    /// Keys of the change in progress while inserting text for it.
+    recording: Option<Vec<Event>>,
+}
+
+#[derive(Default)]
+struct Register {
+    text: String,
+
+    /// Whether the text is made up of whole lines.
+    linewise: bool,
+}
+
+/// A complete normal or visual mode command.
+struct Command {
+    count: usize,
+    operator: Option<char>,
+    target: Target,
+}
+
+/// What a command moves over or applies to.
+#[derive(Clone, Copy)]
+enum Target {
+    Motion(Motion),
+
+    /// A text object, and whether it is the inner object.
+    Object(char, bool),
+
+    /// Whole lines, from an operator typed twice.
+    Line,
+
+    /// A command that is not an operator or motion.
+    Key(char),
+
+    /// Replace characters with the given character.
+    Replace(char),
+}
+
+#[derive(Clone, Copy)]
+enum Motion {
+    Left,
+    Right,
+    WordForward(bool),
+    WordBackward(bool),
+    WordEnd(bool),
+
+    /// The end of the word the cursor is in, as changed by `cw`.
+    CurrentWordEnd(bool),
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+
+    /// Find a character in the line with `f`, `F`, `t`, or `T`.
+    Find(char, char),
+
+    /// Repeat the last find, in the opposite direction if true.
+    RepeatFind(bool),
+}
+
+/// The result of parsing keys typed so far.
+enum Parse<T> {
+    Incomplete,
+    Invalid,
+    Complete(T),
+}
+
+impl Vi {
+    pub fn new() -> Self {
+        Self {
+            mode: Mode::Insert,
+            pending: String::new(),
+            anchor: 0,
+            register: Register::default(),
+            last_find: None,
+            last_change: Vec::new(),
+            recording: None,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Start editing a new command line in insert mode.
+    pub fn reset(&mut self) {
+        self.mode = Mode::Insert;
+        self.pending.clear();
+        self.recording = None;
+    }
+
+    /// Check whether some of the keys of a command have been typed.
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Get the range of text selected in visual mode.
+    pub fn selection(&self, buffer: &Buffer) -> Option<Range<usize>> {
+        if self.mode != Mode::Visual {
+            return None;
+        }
+
+        let text = buffer.text();
+        let anchor = self.anchor.min(text.len());
+        let cursor = buffer.cursor();
+
+        Some(anchor.min(cursor)..next_boundary(text, anchor.max(cursor)))
+    }
+
+    /// Handle a key, editing the buffer if it completes a command.
+    pub fn handle(&mut self, event: Event, buffer: &mut Buffer) -> Outcome {
+        let outcome = match self.mode {
+            Mode::Insert => self.insert_mode(event, buffer),
+            Mode::Normal | Mode::Visual => self.command_mode(event, buffer),
+        };
+
+        self.adjust_cursor(buffer);
+        outcome
+    }
+
+    /// Keep the cursor on a character in normal mode, rather than after the
+    /// end of a line.
+    pub fn adjust_cursor(&self, buffer: &mut Buffer) {
+        if self.mode == Mode::Normal && buffer.cursor() == buffer.line_end() && buffer.cursor() > buffer.line_start() {
+            buffer.move_cursor_relative(-1);
+        }
+    }
+
+    fn insert_mode(&mut self, event: Event, buffer: &mut Buffer) -> Outcome {
+        if let Some(keys) = self.recording.as_mut() {
+            keys.push(event.clone());
+        }
+
+        match event {
+            Event::Escape => {
+                if let Some(keys) = self.recording.take() {
+                    self.last_change = keys;
+                }
+
+                buffer.end_change();
+
+                if buffer.cursor() > buffer.line_start() {
+                    buffer.move_cursor_relative(-1);
+                }
+
+                self.mode = Mode::Normal;
+            }
+            Event::Char('\n') => return Outcome::Unhandled,
+            Event::Char(c) => buffer.insert_char(c),
+            Event::Backspace => buffer.delete_before_cursor(),
+            Event::Delete => buffer.delete_after_cursor(),
+            Event::Paste(text) => buffer.paste(&text),
+            _ => return Outcome::Unhandled,
+        }
+
+        Outcome::Handled
+    }
+
+    fn command_mode(&mut self, event: Event, buffer: &mut Buffer) -> Outcome {
+        let key = match event {
+            Event::Char(c) if c != '\n' => c,
+            Event::Left | Event::Backspace => 'h',
+            Event::Right => 'l',
+            Event::Home => '0',
+            Event::End => '$',
+            Event::Escape => {
+                self.pending.clear();
+                self.mode = Mode::Normal;
+                return Outcome::Handled;
+            }
+            Event::Ctrl('r') if self.pending.is_empty() => {
+                return if buffer.redo() {
+                    Outcome::Handled
+                } else {
+                    Outcome::Invalid
+                };
+            }
+            _ if self.pending.is_empty() => return Outcome::Unhandled,
+            _ => {
+                self.pending.clear();
+                return Outcome::Invalid;
+            }
+        };
+
+        self.pending.push(key);
+
+        match parse(&self.pending, self.mode == Mode::Visual) {
+            Parse::Incomplete => Outcome::Handled,
+            Parse::Invalid => {
+                self.pending.clear();
+                Outcome::Invalid
+            }
+            Parse::Complete(command) => {
+                let keys = mem::take(&mut self.pending);
+                let is_change = command.is_change();
+
+                let outcome = if self.mode == Mode::Visual {
+                    self.visual_command(command, buffer)
+                } else {
+                    self.normal_command(command, buffer)
+                };
+
+                if is_change {
+                    let keys = keys.chars().map(Event::Char).collect();
+
+                    // A change that inserts text is only complete once
+                    // insert mode is left.
+                    if self.mode == Mode::Insert {
+                        self.recording = Some(keys);
+                    } else {
+                        self.last_change = keys;
+                    }
+                }
+
+                outcome.unwrap_or(Outcome::Invalid)
+            }
+        }
+    }
+
+    fn normal_command(&mut self, command: Command, buffer: &mut Buffer) -> Option<Outcome> {
+        let count = command.count;
+
+        if let Some(operator) = command.operator {
+            let (range, linewise) = self.operator_range(operator, command.target, count, buffer)?;
+            self.operate(operator, range, linewise, buffer);
+            return Some(Outcome::Handled);
+        }
+
+        match command.target {
+            Target::Motion(motion) => {
+                let (pos, _) = self.motion_target(motion, count, buffer.text(), buffer.cursor())?;
+                buffer.move_cursor_to(pos);
+            }
+            Target::Key('i') => self.start_insert(buffer),
+            Target::Key('a') => {
+                if buffer.cursor() < buffer.line_end() {
+                    buffer.move_cursor_relative(1);
+                }
+                self.start_insert(buffer);
+            }
+            Target::Key('I') => {
+                buffer.move_cursor_to(first_non_blank(buffer.text(), buffer.cursor()));
+                self.start_insert(buffer);
+            }
+            Target::Key('A') => {
+                buffer.move_to_end_of_line();
+                self.start_insert(buffer);
+            }
+            Target::Key('p') => return self.put(true, count, buffer),
+            Target::Key('P') => return self.put(false, count, buffer),
+            Target::Key('u') => {
+                if !buffer.undo() {
+                    return None;
+                }
+                for _ in 1..count {
+                    buffer.undo();
+                }
+            }
+            Target::Key('~') => {
+                let end = (0..count).fold(buffer.cursor(), |pos, _| {
+                    next_boundary(buffer.text(), pos).min(buffer.line_end())
+                });
+                toggle_case(buffer.cursor()..end, buffer);
+            }
+            Target::Key('v') => {
+                self.mode = Mode::Visual;
+                self.anchor = buffer.cursor();
+            }
+            Target::Key('.') => {
+                if self.last_change.is_empty() {
+                    return None;
+                }
+
+                for _ in 0..count {
+                    for event in self.last_change.clone() {
+                        self.handle(event, buffer);
+                    }
+                }
+            }
+            Target::Key('j') => return Some(Outcome::Action(Action::NextLine)),
+            Target::Key('k') => return Some(Outcome::Action(Action::PreviousLine)),
+            Target::Key('/') => return Some(Outcome::Action(Action::HistorySearch)),
+            Target::Replace(c) => {
+                let start = buffer.cursor();
+                let mut end = start;
+
+                for _ in 0..count {
+                    if end == buffer.line_end() {
+                        return None;
+                    }
+                    end = next_boundary(buffer.text(), end);
+                }
+
+                buffer.begin_change();
+                buffer.remove(start..end);
+                buffer.insert_str(c.to_string().repeat(count));
+                buffer.move_cursor_relative(-1);
+                buffer.end_change();
+            }
+            _ => return None,
+        }
+
+        Some(Outcome::Handled)
+    }
+
+    fn visual_command(&mut self, command: Command, buffer: &mut Buffer) -> Option<Outcome> {
+        let range = self.selection(buffer)?;
+
+        match command.target {
+            Target::Motion(motion) => {
+                let (pos, _) = self.motion_target(motion, command.count, buffer.text(), buffer.cursor())?;
+                buffer.move_cursor_to(pos);
+            }
+            Target::Key('o') => {
+                let cursor = buffer.cursor();
+                buffer.move_cursor_to(self.anchor);
+                self.anchor = cursor;
+            }
+            Target::Key('v') => self.mode = Mode::Normal,
+            Target::Key('~') => {
+                self.mode = Mode::Normal;
+                toggle_case(range.clone(), buffer);
+                buffer.move_cursor_to(range.start);
+            }
+            Target::Key(key) => {
+                let operator = match key {
+                    'x' => 'd',
+                    's' => 'c',
+                    key => key,
+                };
+
+                self.mode = Mode::Normal;
+                self.operate(operator, range, false, buffer);
+            }
+            _ => return None,
+        }
+
+        Some(Outcome::Handled)
+    }
+
+    /// Switch to insert mode for a change, which is undone as a whole along
+    /// with the text inserted.
+    fn start_insert(&mut self, buffer: &mut Buffer) {
+        buffer.begin_change();
+        self.mode = Mode::Insert;
+    }
+
+    /// Get the range of text that an operator applies to, and whether it is
+    /// made up of whole lines.
+    fn operator_range(&mut self, operator: char, target: Target, count: usize, buffer: &Buffer) -> Option<(Range<usize>, bool)> {
+        let text = buffer.text();
+        let cursor = buffer.cursor();
+
+        match target {
+            Target::Line => {
+                let start = line_start(text, cursor);
+                let mut end = line_end(text, cursor);
+
+                for _ in 1..count {
+                    if end < text.len() {
+                        end = line_end(text, end + 1);
+                    }
+                }
+
+                Some((start..end, true))
+            }
+            Target::Object(object, inner) => text_object(text, cursor, object, inner).map(|range| (range, false)),
+            Target::Motion(motion) => {
+                // Changing a word leaves the whitespace after it.
+                let motion = match motion {
+                    Motion::WordForward(big) if operator == 'c' && !text[cursor..].starts_with(char::is_whitespace) => {
+                        Motion::CurrentWordEnd(big)
+                    }
+                    motion => motion,
+                };
+
+                let (pos, inclusive) = self.motion_target(motion, count, text, cursor)?;
+                let start = pos.min(cursor);
+                let mut end = pos.max(cursor);
+
+                if inclusive {
+                    end = next_boundary(text, end);
+                }
+
+                // Words are only deleted up to the end of the line.
+                if let Motion::WordForward(_) = motion {
+                    if line_end(text, cursor) > start {
+                        end = end.min(line_end(text, cursor));
+                    }
+                }
+
+                Some((start..end, false))
+            }
+            _ => None,
+        }
+    }
+
+    /// Apply an operator to a range of text.
+    fn operate(&mut self, operator: char, mut range: Range<usize>, linewise: bool, buffer: &mut Buffer) {
+        self.register = Register {
+            text: buffer.text()[range.clone()].to_owned(),
+            linewise,
+        };
+
+        match operator {
+            'd' => {
+                // Deleting lines also deletes the newline after them, or
+                // before them if they are last.
+                if linewise {
+                    if range.end < buffer.text().len() {
+                        range.end += 1;
+                    } else if range.start > 0 {
+                        range.start -= 1;
+                    }
+                }
+
+                buffer.begin_change();
+                buffer.remove(range);
+                buffer.end_change();
+
+                if linewise {
+                    buffer.move_to_start_of_line();
+                }
+            }
+            'c' => {
+                buffer.begin_change();
+                buffer.remove(range);
+                self.mode = Mode::Insert;
+            }
+            _ => {
+                buffer.move_cursor_to(range.start);
+            }
+        }
+    }
+
+    /// Put the text from the register after the cursor, or before it if
+    /// `after` is false.
+    fn put(&mut self, after: bool, count: usize, buffer: &mut Buffer) -> Option<Outcome> {
+        if self.register.text.is_empty() {
+            return None;
+        }
+
+        buffer.begin_change();
+
+        if self.register.linewise {
+            let lines = vec![self.register.text.as_str(); count].join("\n");
+
+            if after {
+                buffer.move_to_end_of_line();
+                buffer.insert_str(format!("\n{}", lines));
+            } else {
+                buffer.move_to_start_of_line();
+                let start = buffer.cursor();
+                buffer.insert_str(format!("{}\n", lines));
+                buffer.move_cursor_to(start);
+            }
+
+            buffer.move_to_start_of_line();
+        } else {
+            if after && buffer.cursor() < buffer.line_end() {
+                buffer.move_cursor_relative(1);
+            }
+
+            buffer.insert_str(self.register.text.repeat(count));
+            buffer.move_cursor_relative(-1);
+        }
+
+        buffer.end_change();
+        Some(Outcome::Handled)
+    }
+
+    /// Get the position a motion moves the cursor to, and whether an operator
+    /// applied with the motion includes the character at that position.
+    fn motion_target(&mut self, motion: Motion, count: usize, text: &str, cursor: usize) -> Option<(usize, bool)> {
+        let repeat = |f: &dyn Fn(usize) -> usize| (0..count).fold(cursor, |pos, _| f(pos));
+
+        Some(match motion {
+            Motion::Left => (repeat(&|pos| if pos > line_start(text, pos) { prev_boundary(text, pos) } else { pos }), false),
+            Motion::Right => (repeat(&|pos| if pos < line_end(text, pos) { next_boundary(text, pos) } else { pos }), false),
+            Motion::WordForward(big) => (repeat(&|pos| word_forward(text, pos, big)), false),
+            Motion::WordBackward(big) => (repeat(&|pos| word_backward(text, pos, big)), false),
+            Motion::WordEnd(big) => (repeat(&|pos| word_end(text, pos, big)), true),
+            Motion::CurrentWordEnd(big) => {
+                let next = next_boundary(text, cursor);
+                let in_word = text[next..].chars().next()
+                    .map(|c| class(c, big) == text[cursor..].chars().next().map_or(0, |c| class(c, big)))
+                    .unwrap_or(false);
+
+                let first = if in_word { word_end(text, cursor, big) } else { cursor };
+                ((1..count).fold(first, |pos, _| word_end(text, pos, big)), true)
+            }
+            Motion::LineStart => (line_start(text, cursor), false),
+            Motion::FirstNonBlank => (first_non_blank(text, cursor), false),
+            Motion::LineEnd => (line_end(text, cursor), false),
+            Motion::Find(kind, c) => {
+                self.last_find = Some((kind, c));
+                find(text, cursor, kind, c, count)?
+            }
+            Motion::RepeatFind(reverse) => {
+                let (kind, c) = self.last_find?;
+                let kind = match kind {
+                    'f' if reverse => 'F',
+                    'F' if reverse => 'f',
+                    't' if reverse => 'T',
+                    'T' if reverse => 't',
+                    kind => kind,
+                };
+
+                // Repeating `t` or `T` skips over the character it stopped
+                // next to.
+                let from = match kind {
+                    't' => next_boundary(text, cursor),
+                    'T' => prev_boundary(text, cursor),
+                    _ => cursor,
+                };
+
+                find(text, from, kind, c, count)?
+            }
+        })
+    }
+}
+
+impl Command {
+    /// Check whether the command changes the buffer, and so is repeated by `.`.
+    fn is_change(&self) -> bool {
+        match (self.operator, self.target) {
+            (Some(operator), _) => operator != 'y',
+            (None, Target::Key(key)) => "iaIApP~".contains(key),
+            (None, Target::Replace(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Parse the keys of a normal mode command, or of a visual mode command if
+/// `visual` is true.
+fn parse(keys: &str, visual: bool) -> Parse<Command> {
+    let mut keys = keys.chars().peekable();
+    let mut count = parse_count(&mut keys);
+
+    let key = match keys.next() {
+        Some(key) => key,
+        None => return Parse::Incomplete,
+    };
+
+    let (operator, target) = match key {
+        key if visual && "dcyxs~ov".contains(key) => (None, Target::Key(key)),
+        'd' | 'c' | 'y' if !visual => {
+            count = (count * parse_count(&mut keys)).min(MAX_COUNT);
+
+            let target = match keys.next() {
+                None => return Parse::Incomplete,
+                Some(next) if next == key => Target::Line,
+                Some(next @ 'i') | Some(next @ 'a') => match keys.next() {
+                    None => return Parse::Incomplete,
+                    Some(object) if TEXT_OBJECTS.contains(object) => Target::Object(object, next == 'i'),
+                    Some(_) => return Parse::Invalid,
+                },
+                Some(next) => match parse_motion(next, &mut keys) {
+                    Parse::Complete(motion) => Target::Motion(motion),
+                    Parse::Incomplete => return Parse::Incomplete,
+                    Parse::Invalid => return Parse::Invalid,
+                },
+            };
+
+            (Some(key), target)
+        }
+        'x' => (Some('d'), Target::Motion(Motion::Right)),
+        'X' => (Some('d'), Target::Motion(Motion::Left)),
+        's' => (Some('c'), Target::Motion(Motion::Right)),
+        'S' => (Some('c'), Target::Line),
+        'C' => (Some('c'), Target::Motion(Motion::LineEnd)),
+        'D' => (Some('d'), Target::Motion(Motion::LineEnd)),
+        'Y' => (Some('y'), Target::Line),
+        'r' if !visual => match keys.next() {
+            Some(c) => (None, Target::Replace(c)),
+            None => return Parse::Incomplete,
+        },
+        key if !visual && "iaIApPu~v.jk/".contains(key) => (None, Target::Key(key)),
+        key => match parse_motion(key, &mut keys) {
+            Parse::Complete(motion) => (None, Target::Motion(motion)),
+            Parse::Incomplete => return Parse::Incomplete,
+            Parse::Invalid => return Parse::Invalid,
+        },
+    };
+
+    Parse::Complete(Command {
+        count,
+        operator,
+        target,
+    })
+}
+
+/// Parse a count before a command or motion, which is 1 if there isn't one.
+fn parse_count(keys: &mut Peekable<Chars>) -> usize {
+    let mut count = None;
+
+    while let Some(digit) = keys.peek().and_then(|c| c.to_digit(10)) {
+        // A leading zero is the motion to the start of the line.
+        if digit == 0 && count.is_none() {
+            break;
+        }
+
+        keys.next();
+        count = Some((count.unwrap_or(0) * 10 + digit as usize).min(MAX_COUNT));
+    }
+
+    count.unwrap_or(1)
+}
+
+fn parse_motion(key: char, keys: &mut Peekable<Chars>) -> Parse<Motion> {
+    Parse::Complete(match key {
+        'h' => Motion::Left,
+        'l' | ' ' => Motion::Right,
+        'w' => Motion::WordForward(false),
+        'W' => Motion::WordForward(true),
+        'b' => Motion::WordBackward(false),
+        'B' => Motion::WordBackward(true),
+        'e' => Motion::WordEnd(false),
+        'E' => Motion::WordEnd(true),
+        '0' => Motion::LineStart,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::LineEnd,
+        'f' | 'F' | 't' | 'T' => match keys.next() {
+            Some(c) => Motion::Find(key, c),
+            None => return Parse::Incomplete,
+        },
+        ';' => Motion::RepeatFind(false),
+        ',' => Motion::RepeatFind(true),
+        _ => return Parse::Invalid,
+    })
+}
+
+/// Swap the case of letters in a range of text, moving the cursor after them.
+fn toggle_case(range: Range<usize>, buffer: &mut Buffer) {
+    let toggled = buffer.text()[range.clone()].chars()
+        .map(|c| {
+            if c.is_lowercase() {
+                c.to_uppercase().collect::<String>()
+            } else {
+                c.to_lowercase().collect()
+            }
+        })
+        .collect::<String>();
+
+    buffer.begin_change();
+    buffer.move_cursor_to(range.start);
+    buffer.remove(range);
+    buffer.insert_str(toggled);
+    buffer.end_change();
+}
+
+fn next_boundary(text: &str, pos: usize) -> usize {
+    text[pos..].graphemes(true).next().map_or(pos, |grapheme| pos + grapheme.len())
+}
+
+fn prev_boundary(text: &str, pos: usize) -> usize {
+    text[..pos].graphemes(true).next_back().map_or(pos, |grapheme| pos - grapheme.len())
+}
+
+fn line_start(text: &str, pos: usize) -> usize {
+    text[..pos].rfind('\n').map_or(0, |i| i + 1)
+}
+
+fn line_end(text: &str, pos: usize) -> usize {
+    text[pos..].find('\n').map_or(text.len(), |i| pos + i)
+}
+
+fn first_non_blank(text: &str, pos: usize) -> usize {
+    let start = line_start(text, pos);
+    let line = &text[start..line_end(text, pos)];
+
+    start + line.find(|c: char| !c.is_whitespace()).unwrap_or(line.len())
+}
+
+/// Get the class of a character for word motions: 0 for whitespace, 1 for
+/// word characters, and 2 for punctuation. Big words are anything other than
+/// whitespace.
+fn class(c: char, big: bool) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if big || c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+/// Get the position after a run of characters starting at a position.
+fn run_end(text: &str, pos: usize, f: impl Fn(char) -> bool) -> usize {
+    pos + text[pos..].chars().take_while(|c| f(*c)).map(char::len_utf8).sum::<usize>()
+}
+
+/// Get the start of a run of characters ending at a position.
+fn run_start(text: &str, pos: usize, f: impl Fn(char) -> bool) -> usize {
+    pos - text[..pos].chars().rev().take_while(|c| f(*c)).map(char::len_utf8).sum::<usize>()
+}
+
+fn word_forward(text: &str, pos: usize, big: bool) -> usize {
+    let pos = match text[pos..].chars().next() {
+        Some(c) if !c.is_whitespace() => run_end(text, pos, |d| class(d, big) == class(c, big)),
+        _ => pos,
+    };
+
+    run_end(text, pos, char::is_whitespace)
+}
+
+fn word_backward(text: &str, pos: usize, big: bool) -> usize {
+    let pos = run_start(text, pos, char::is_whitespace);
+
+    match text[..pos].chars().next_back() {
+        Some(c) => run_start(text, pos, |d| class(d, big) == class(c, big)),
+        None => pos,
+    }
+}
+
+/// Get the position of the last character of the next word end after a
+/// position.
+fn word_end(text: &str, pos: usize, big: bool) -> usize {
+    let start = run_end(text, next_boundary(text, pos), char::is_whitespace);
+
+    match text[start..].chars().next() {
+        Some(c) => prev_boundary(text, run_end(text, start, |d| class(d, big) == class(c, big))),
+        None => pos,
+    }
+}
+
+/// Find the nth occurrence of a character in the line, for the `f`, `F`, `t`,
+/// and `T` motions.
+fn find(text: &str, pos: usize, kind: char, c: char, count: usize) -> Option<(usize, bool)> {
+    match kind {
+        'f' | 't' => {
+            let start = next_boundary(text, pos);
+            let found = start + text[start..line_end(text, pos)].match_indices(c).nth(count - 1)?.0;
+
+            Some(if kind == 'f' {
+                (found, true)
+            } else {
+                (prev_boundary(text, found), true)
+            })
+        }
+        _ => {
+            let start = line_start(text, pos);
+            let found = start + text[start..pos].rmatch_indices(c).nth(count - 1)?.0;
+
+            Some(if kind == 'F' {
+                (found, false)
+            } else {
+                (next_boundary(text, found), false)
+            })
+        }
+    }
+}
+
+/// Get the range of a text object around a position.
+fn text_object(text: &str, pos: usize, object: char, inner: bool) -> Option<Range<usize>> {
+    match object {
+        'w' | 'W' => word_object(text, pos, object == 'W', inner),
+        '"' | '\'' | '`' => quote_object(text, pos, object, inner),
+        '(' | ')' | 'b' => bracket_object(text, pos, '(', ')', inner),
+        '{' | '}' | 'B' => bracket_object(text, pos, '{', '}', inner),
+        '[' | ']' => bracket_object(text, pos, '[', ']', inner),
+        '<' | '>' => bracket_object(text, pos, '<', '>', inner),
+        _ => None,
+    }
+}
+
+/// Get the word, or run of whitespace, at a position. Unless `inner` is true,
+/// this includes the whitespace after a word, or before it if there is none
+/// after, or the word after whitespace.
+fn word_object(text: &str, pos: usize, big: bool, inner: bool) -> Option<Range<usize>> {
+    let pos = if pos == text.len() { prev_boundary(text, pos) } else { pos };
+    let class_at = class(text[pos..].chars().next()?, big);
+    let same = |c| class(c, big) == class_at;
+
+    let start = run_start(text, pos, same);
+    let end = run_end(text, pos, same);
+
+    if inner {
+        return Some(start..end);
+    }
+
+    let is_blank = |c| c == ' ' || c == '\t';
+
+    Some(match text[end..].chars().next() {
+        Some(c) if class_at == 0 && c != '\n' => start..run_end(text, end, |d| class(d, big) == class(c, big)),
+        Some(c) if class_at != 0 && is_blank(c) => start..run_end(text, end, is_blank),
+        _ => run_start(text, start, is_blank)..end,
+    })
+}
+
+/// Get the quoted string in the line around or after a position.
+fn quote_object(text: &str, pos: usize, quote: char, inner: bool) -> Option<Range<usize>> {
+    let start = line_start(text, pos);
+    let quotes = text[start..line_end(text, pos)].match_indices(quote)
+        .map(|(i, _)| start + i)
+        .collect::<Vec<_>>();
+
+    let (open, close) = quotes.chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|(_, close)| pos <= *close)?;
+
+    Some(if inner {
+        open + 1..close
+    } else {
+        open..close + 1
+    })
+}
+
+/// Get the brackets enclosing a position and the text between them.
+fn bracket_object(text: &str, pos: usize, open: char, close: char, inner: bool) -> Option<Range<usize>> {
+    let mut depth = 0;
+    let mut start = None;
+
+    for (i, c) in text[..next_boundary(text, pos)].char_indices().rev() {
+        if c == close && i != pos {
+            depth += 1;
+        } else if c == open {
+            if depth == 0 {
+                start = Some(i);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+
+    let start = start?;
+    let mut end = None;
+
+    for (i, c) in text[start + 1..].char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                end = Some(start + 1 + i);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+
+    let end = end?;
+
+    Some(if inner {
+        start + 1..end
+    } else {
+        start..end + 1
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Type keys in vi, with `\x1b` standing for Escape.
+    fn type_keys(vi: &mut Vi, buffer: &mut Buffer, keys: &str) {
+        for c in keys.chars() {
+            let event = if c == '\x1b' { Event::Escape } else { Event::Char(c) };
+            vi.handle(event, buffer);
+        }
+    }
+
+    fn edit(keys: &str) -> (String, usize) {
+        let mut vi = Vi::new();
+        let mut buffer = Buffer::new();
+        type_keys(&mut vi, &mut buffer, keys);
+        (buffer.text().to_owned(), buffer.cursor())
+    }
+
+    #[test]
+    fn motions_and_operators() {
+        assert_eq!(edit("echo one two three\x1b0wdw"), ("echo two three".into(), 5));
+        assert_eq!(edit("echo one two three\x1bb2db"), ("echo three".into(), 5));
+        assert_eq!(edit("echo one two\x1b0cwprint\x1b"), ("print one two".into(), 4));
+        assert_eq!(edit("a-b c\x1b0dW"), ("c".into(), 0));
+        assert_eq!(edit("abcabc\x1b0fcD"), ("ab".into(), 1));
+        assert_eq!(edit("abcabc\x1b0tc;x"), ("abcac".into(), 4));
+        assert_eq!(edit("abc\x1b0xp"), ("bac".into(), 1));
+        assert_eq!(edit("abc\x1b0~~"), ("ABc".into(), 2));
+    }
+
+    #[test]
+    fn text_objects() {
+        assert_eq!(edit("echo \"a b\" c\x1b0fadi\""), ("echo \"\" c".into(), 6));
+        assert_eq!(edit("f (a (b) c)\x1b0fbda("), ("f (a  c)".into(), 5));
+        assert_eq!(edit("one two three\x1b0wdaw"), ("one three".into(), 4));
+        assert_eq!(edit("one two\x1b0wciwsix\x1b"), ("one six".into(), 6));
+    }
+
+    #[test]
+    fn counts_undo_and_repeat() {
+        assert_eq!(edit("abcdef\x1b03x"), ("def".into(), 0));
+        assert_eq!(edit("abcdef\x1b0x.."), ("def".into(), 0));
+        assert_eq!(edit("a b c\x1b0cwx\x1bw.w."), ("x x x".into(), 4));
+        assert_eq!(edit("abc\x1b0cwx\x1bu"), ("abc".into(), 0));
+        assert_eq!(edit("abc\x1bddu"), ("abc".into(), 2));
+    }
+
+    #[test]
+    fn visual_selections() {
+        assert_eq!(edit("one two\x1b0vlld"), (" two".into(), 0));
+        assert_eq!(edit("one two\x1bvbyP"), ("one twotwo".into(), 6));
+    }
+}
@@ -0,0 +1,217 @@
+//! Handles signals sent to the process.
+//!
+//! Scripts can handle a signal by setting a function for it with `trap`, which
+//! is kept in the global `traps` table under the signal's name. Signals without
+//! a handler act much as they normally would: they end the process, except for
+//! interrupts in an interactive session, which only cancel the command line
+//! being run.
+
+use crate::exit;
+use crate::runtime::prelude::*;
+use futures::future::{self, select, select_all, Either, FutureExt};
+use nix::libc::{self, c_int};
+use nix::sys::signal::{raise, SIGINT};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+/// Name of the global table holding signal handlers.
+pub(crate) const TRAPS_GLOBAL: &str = "traps";
+
+/// Whether an interrupt stops what `Signals::run` is running.
+static INTERRUPTIBLE: AtomicBool = AtomicBool::new(false);
+
+/// Signals that can be handled, by name.
+const SIGNALS: &[(&str, c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("TERM", libc::SIGTERM),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+];
+
+/// Look up a signal that can be handled by name, with or without the `SIG`
+/// prefix, and get its usual name.
+pub fn parse(name: &str) -> Option<&'static str> {
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+
+    SIGNALS.iter().find(|(n, _)| *n == name).map(|(n, _)| *n)
+}
+
+/// Get the name of a signal that can be handled.
+fn name(number: c_int) -> &'static str {
+    SIGNALS.iter().find(|(_, n)| *n == number).map(|(name, _)| *name).unwrap_or("")
+}
+
+/// Interrupt what is being run, as if the process had received SIGINT. Never
+/// returns unless a handler is set for the interrupt, or what is being run
+/// can't be interrupted.
+pub async fn interrupt(fiber: &Fiber) {
+    if !INTERRUPTIBLE.load(Ordering::SeqCst) || raise(SIGINT).is_err() {
+        return;
+    }
+
+    // Without a handler, the interrupt stops what is being run once it is
+    // received.
+    if fiber.globals().get(TRAPS_GLOBAL).get(name(libc::SIGINT)).is_nil() {
+        future::pending().await
+    }
+}
+
+/// Get the status a process exits with when ended by a signal, following the
+/// convention of shells.
+pub fn exit_status(signal: c_int) -> i32 {
+    128 + signal
+}
+
+/// Receives the signals sent to the process that can be handled.
+pub struct Signals {
+    streams: Vec<(c_int, Signal)>,
+}
+
+impl Signals {
+    /// Start receiving signals. From now on, they no longer have their default
+    /// effect on the process, so they must be handled with `run`.
+    pub fn new() -> Self {
+        let streams = SIGNALS.iter()
+            .filter_map(|(name, number)| match signal(SignalKind::from_raw(*number)) {
+                Ok(stream) => Some((*number, stream)),
+                Err(e) => {
+                    log::warn!("can't handle SIG{}: {}", name, e);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            streams,
+        }
+    }
+
+    /// Wait for the next signal, and return its number.
+    async fn recv(&mut self) -> c_int {
+        if self.streams.is_empty() {
+            return future::pending().await;
+        }
+
+        let receiving = self.streams.iter_mut().map(|(number, stream)| {
+            let number = *number;
+
+            async move {
+                stream.recv().await;
+                number
+            }.boxed_local()
+        });
+
+        select_all(receiving).await.0
+    }
+
+    /// Run a future to completion, calling the handlers set for signals
+    /// received in the meantime with the given fiber.
+    ///
+    /// A signal without a handler stops the future, and its number is returned
+    /// instead, unless it is an interrupt and `interruptible` is false. The
+    /// same happens if a handler asks for the process to exit.
+    pub async fn run<F: Future>(&mut self, fiber: &mut Fiber, interruptible: bool, future: F) -> Result<F::Output, c_int> {
+        let previous = INTERRUPTIBLE.swap(interruptible, Ordering::SeqCst);
+        let result = self.run_until_stopped(fiber, interruptible, future).await;
+        INTERRUPTIBLE.store(previous, Ordering::SeqCst);
+
+        result
+    }
+
+    async fn run_until_stopped<F: Future>(&mut self, fiber: &mut Fiber, interruptible: bool, future: F) -> Result<F::Output, c_int> {
+        let mut future = future.boxed_local();
+
+        loop {
+            // Signals are checked first, so that an interrupt stops a command
+            // line even if the command it interrupted has just exited.
+            let signal = match select(self.recv().boxed_local(), future).await {
+                Either::Left((signal, unfinished)) => {
+                    future = unfinished;
+                    signal
+                }
+                Either::Right((output, _)) => return Ok(output),
+            };
+
+            let handler = fiber.globals().get(TRAPS_GLOBAL).get(name(signal));
+
+            if handler.is_nil() {
+                if signal != libc::SIGINT || interruptible {
+                    return Err(signal);
+                }
+
+                continue;
+            }
+
+            log::debug!("calling handler for SIG{}", name(signal));
+
+            if let Err(e) = fiber.invoke(&handler, &[Value::from(name(signal))]).await {
+                log::error!("in handler for SIG{}: {}", name(signal), e);
+            }
+
+            if exit::get().is_some() {
+                return Err(signal);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::testing::{block_on, execute, fiber, run};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn signal_names_are_parsed() {
+        assert_eq!(parse("int"), Some("INT"));
+        assert_eq!(parse("SIGTERM"), Some("TERM"));
+        assert_eq!(parse("KILL"), None);
+    }
+
+    #[test]
+    fn handlers_are_called_for_trapped_signals() {
+        block_on(async {
+            let mut signals = Signals::new();
+            let mut fiber = fiber();
+
+            let (result, _) = execute(&mut fiber, "trap sigusr1 <signal> { table-set $GLOBALS trapped $signal }").await;
+            assert!(result.unwrap().is_nil());
+            assert!(!fiber.globals().get(TRAPS_GLOBAL).get("USR1").is_nil());
+
+            let raising = async {
+                raise(nix::sys::signal::SIGUSR1).unwrap();
+                tokio::time::delay_for(Duration::from_millis(100)).await;
+            };
+            assert!(signals.run(&mut fiber, true, raising).await.is_ok());
+            assert_eq!(fiber.globals().get("trapped"), "USR1");
+
+            // Removing the handler returns it.
+            let (result, _) = execute(&mut fiber, "trap USR1").await;
+            assert!(!result.unwrap().is_nil());
+            assert!(fiber.globals().get(TRAPS_GLOBAL).get("USR1").is_nil());
+
+            // Signals without a handler stop what is running, even if it is
+            // sleeping.
+            let started = Instant::now();
+            let mut running = fiber.fork();
+            let sleeping = async {
+                raise(nix::sys::signal::SIGUSR1).unwrap();
+                running.execute(None, "def process (require process); $process->sleep 10").await
+            };
+            assert_eq!(signals.run(&mut fiber, true, sleeping).await.unwrap_err(), libc::SIGUSR1);
+            assert!(started.elapsed() < Duration::from_secs(5));
+        });
+    }
+
+    #[test]
+    fn only_functions_can_handle_signals() {
+        let (result, _) = run("trap KILL {}");
+        assert_eq!(result.unwrap_err().to_string(), "signal can't be trapped: KILL");
+
+        let (result, _) = run("trap TERM handler");
+        assert_eq!(result.unwrap_err().to_string(), "signal handler must be a function");
+    }
+}
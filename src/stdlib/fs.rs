@@ -0,0 +1,88 @@
+use crate::runtime::glob;
+use crate::runtime::prelude::*;
+use crate::runtime::string::RipString;
+use std::env;
+use std::io;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+
+pub fn load() -> Result<Value, Exception> {
+    Ok(table! {
+        "cd" => Value::foreign_fn(cd),
+        "glob" => Value::foreign_fn(glob),
+        "pwd" => Value::foreign_fn(pwd),
+    }
+    .into())
+}
+
+/// Change the current working directory of the process, updating the `PWD` and
+/// `OLDPWD` environment variables to match.
+pub(crate) fn change_dir(path: impl AsRef<Path>) -> io::Result<()> {
+    let previous = env::current_dir().ok();
+
+    env::set_current_dir(path)?;
+
+    if let Some(previous) = previous {
+        env::set_var("OLDPWD", previous);
+    }
+
+    if let Ok(current) = env::current_dir() {
+        env::set_var("PWD", current);
+    }
+
+    Ok(())
+}
+
+/// Returns the current working directory.
+async fn pwd(_: &mut Fiber, _: &[Value]) -> Result<Value, Exception> {
+    match env::current_dir() {
+        Ok(path) => Ok(RipString::from(path.into_os_string().into_vec()).into()),
+        Err(e) => throw!("pwd: {}", e),
+    }
+}
+
+/// Changes the current working directory.
+///
+/// With no arguments, changes to the user's home directory. The argument `-`
+/// changes back to the previous working directory.
+async fn cd(_: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    let path = match args.first().map(Value::to_string) {
+        Some(ref arg) if arg == "-" => match env::var_os("OLDPWD") {
+            Some(path) => PathBuf::from(path),
+            None => throw!("cd: no previous directory"),
+        },
+        Some(arg) => PathBuf::from(arg),
+        None => match directories::UserDirs::new() {
+            Some(dirs) => dirs.home_dir().to_owned(),
+            None => throw!("cd: unable to locate the home directory"),
+        },
+    };
+
+    if let Err(e) = change_dir(&path) {
+        throw!("cd: {}: {}", path.display(), e);
+    }
+
+    Ok(Value::Nil)
+}
+
+/// Returns a list of all existing paths matching a glob pattern.
+///
+/// Brace alternatives and tildes are expanded the same way as in unquoted
+/// command arguments. If nothing matches, an empty list is returned.
+async fn glob(_: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    let pattern = match args.first() {
+        Some(pattern) => pattern.to_string(),
+        None => throw!("glob pattern required"),
+    };
+
+    let paths = match glob::expand(&pattern) {
+        Ok(paths) => paths,
+        Err(e) => throw!("invalid glob pattern {}: {}", pattern, e),
+    };
+
+    Ok(paths
+        .into_iter()
+        .filter(|path| path.symlink_metadata().is_ok())
+        .map(|path| RipString::from(path.into_os_string().into_vec()))
+        .collect())
+}
@@ -1,7 +1,12 @@
 use crate::io::process;
+use crate::pipes;
 use crate::runtime::prelude::*;
-use std::process::Command;
-use std::thread;
+use crate::signals::{self, TRAPS_GLOBAL};
+use futures::future::{join3, select, Either, FutureExt};
+use nix::libc;
+use std::io;
+use std::os::unix::{io::AsRawFd, process::ExitStatusExt};
+use std::process::{ExitStatus, Stdio};
 use std::time::Duration;
 
 pub fn load() -> Result<Value, Exception> {
@@ -10,6 +15,9 @@ pub fn load() -> Result<Value, Exception> {
         "exec" => Value::foreign_fn(exec),
         "sleep" => Value::foreign_fn(sleep),
         "spawn" => Value::foreign_fn(spawn),
+        "strict" => Value::foreign_fn(strict),
+        "trap" => Value::foreign_fn(trap),
+        "unchecked" => Value::foreign_fn(unchecked),
     }
     .into())
 }
@@ -29,34 +37,243 @@ async fn spawn(_: &mut Fiber, _: &[Value]) -> Result<Value, Exception> {
 
 /// Executes a shell command in the foreground, waiting for it to complete.
 ///
-/// Returns the process exit code.
-async fn command(_: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
-    if let Some(command) = args.first() {
-        let command =
-            command.as_string().and_then(|s| s.as_utf8()).ok_or_else(|| Exception::from("invalid command name"))?;
-
-        let mut string_args = Vec::new();
-
-        if args.len() > 1 {
-            for arg in &args[1..] {
-                string_args.push(
-                    arg.as_string()
-                        .and_then(|s| s.as_utf8())
-                        .ok_or_else(|| Exception::from("argument must be UTF-8"))?,
-                );
+/// Returns the process exit code. If the fiber is in strict mode and the
+/// command does not exit successfully, a `command-failed` exception is raised
+/// instead.
+async fn command(fiber: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    let check = fiber.is_strict();
+    run_command(fiber, args, check).await
+}
+
+/// Executes a shell command in the foreground like `command`, but never raises
+/// an exception for an unsuccessful exit, even in strict mode.
+async fn unchecked(fiber: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    run_command(fiber, args, false).await
+}
+
+/// Invokes a block with strict mode enabled or disabled, restoring the previous
+/// setting afterward.
+async fn strict(fiber: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    let enabled = match args.first() {
+        Some(value) => value.is_truthy(),
+        None => throw!("strict mode setting required"),
+    };
+
+    let block = match args.get(1) {
+        Some(value) => value,
+        None => throw!("block to invoke required"),
+    };
+
+    let previous = fiber.is_strict();
+    fiber.set_strict(enabled);
+    let result = fiber.invoke(block, &[]).await;
+    fiber.set_strict(previous);
+
+    result
+}
+
+/// Sets the function called when the process receives a signal, such as `INT`
+/// or `TERM`, or removes it if no function is given.
+///
+/// Returns the function that was set before, if any.
+async fn trap(fiber: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    let name = match args.first() {
+        Some(name) => name.as_string().and_then(|s| s.as_utf8()).ok_or("invalid signal name")?,
+        None => throw!("signal name required"),
+    };
+
+    let signal = match signals::parse(name) {
+        Some(signal) => signal,
+        None => throw!("signal can't be trapped: {}", name),
+    };
+
+    let handler = args.get(1).cloned().unwrap_or(Value::Nil);
+
+    match handler {
+        Value::Nil | Value::Block(_) | Value::ForeignFn(_) => {}
+        _ => throw!("signal handler must be a function"),
+    }
+
+    let traps = match fiber.globals().get(TRAPS_GLOBAL).as_table() {
+        Some(traps) => traps,
+        None => {
+            let traps = table!();
+            fiber.globals().set(TRAPS_GLOBAL, traps.clone());
+            traps
+        }
+    };
+
+    Ok(traps.set(signal, handler))
+}
+
+async fn run_command(fiber: &mut Fiber, args: &[Value], check: bool) -> Result<Value, Exception> {
+    let command = match args.first() {
+        Some(command) => command.as_string().and_then(|s| s.as_utf8()).ok_or("invalid command name")?,
+        None => throw!("command to execute is required"),
+    };
+
+    let mut string_args = Vec::new();
+    for arg in &args[1..] {
+        coalesce_arg(arg, &mut string_args);
+    }
+
+    fiber.commands_started.set(fiber.commands_started.get() + 1);
+
+    let status = match execute(fiber, command, &string_args).await {
+        Ok(status) => status,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => throw!("command not found: {}", command),
+        Err(e) => throw!("{}: {}", command, e),
+    };
+
+    if check && !status.success() {
+        return Err(command_failed(command, &string_args, status));
+    }
+
+    Ok(Value::from(status.code().unwrap_or(0) as f64))
+}
+
+/// Coalesce a value into program arguments. Lists are flattened into one
+/// argument per item, and nil produces no arguments.
+fn coalesce_arg(value: &Value, args: &mut Vec<String>) {
+    match value {
+        Value::Nil => {}
+        Value::List(items) => {
+            for item in items {
+                coalesce_arg(item, args);
             }
         }
+        value => args.push(value.to_string()),
+    }
+}
 
-        Command::new(command)
-            .args(string_args)
-            .status()
-            .map(|status| Value::from(status.code().unwrap_or(0) as f64))
-            .map_err(|e| e.to_string().into())
-    } else {
-        throw!("command to execute is required")
+/// Spawn a child process attached to the standard streams of the given fiber,
+/// and wait for it to exit.
+///
+/// Streams that can't be handed to the child directly are relayed through a
+/// pipe while the child runs.
+async fn execute(fiber: &mut Fiber, command: &str, args: &[String]) -> io::Result<ExitStatus> {
+    let mut child = tokio::process::Command::new(command);
+    child.args(args);
+
+    // The child is killed if the command line running it is cancelled. In
+    // interactive sessions, so is the rest of its process group.
+    child.kill_on_drop(true);
+
+    if let Some(group) = fiber.process_group.as_ref() {
+        group.prepare(&mut child);
+    }
+
+    if !fiber.inherited_fds.is_empty() {
+        let fds = fiber.inherited_fds.clone();
+
+        unsafe {
+            child.pre_exec(move || {
+                fds.iter().cloned().try_for_each(pipes::set_inheritable)
+            });
+        }
+    }
+
+    let stdin = fiber.stdin().map(|s| s.try_clone()).transpose()?;
+    let stdout = fiber.stdout().map(|s| s.try_clone()).transpose()?;
+    let stderr = fiber.stderr().map(|s| s.try_clone()).transpose()?;
+
+    let (stdio, relay_stdin) = attach(stdin)?;
+    child.stdin(stdio);
+    let (stdio, relay_stdout) = attach(stdout)?;
+    child.stdout(stdio);
+    let (stdio, relay_stderr) = attach(stderr)?;
+    child.stderr(stdio);
+
+    let mut child = child.spawn()?;
+
+    let member = match fiber.process_group.as_ref() {
+        Some(group) => Some(group.join(child.id())?),
+        None => None,
+    };
+
+    let child_stdin = child.stdin.take();
+    let child_stdout = child.stdout.take();
+    let child_stderr = child.stderr.take();
+
+    // Input is relayed only for as long as the child is running, since the
+    // child may exit without consuming all of it.
+    let input = async move {
+        if let (Some(mut from), Some(mut to)) = (relay_stdin, child_stdin) {
+            tokio::io::copy(&mut from, &mut to).await.ok();
+        }
+    };
+
+    let output = async move {
+        if let (Some(mut from), Some(mut to)) = (child_stdout, relay_stdout) {
+            tokio::io::copy(&mut from, &mut to).await.ok();
+        }
+    };
+
+    let errors = async move {
+        if let (Some(mut from), Some(mut to)) = (child_stderr, relay_stderr) {
+            tokio::io::copy(&mut from, &mut to).await.ok();
+        }
+    };
+
+    let running = join3(child, output, errors).map(|(status, _, _)| status);
+
+    let status = match select(input.boxed_local(), running.boxed_local()).await {
+        Either::Left((_, running)) => running.await,
+        Either::Right((status, _)) => status,
+    };
+
+    let status = status?;
+
+    if let Some(member) = member {
+        member.finish();
+
+        // Interrupts from the terminal only go to the process group that has
+        // it, so pass them on to cancel the rest of the command line as well.
+        if status.signal() == Some(libc::SIGINT) {
+            signals::interrupt(fiber).await;
+        }
+    }
+
+    Ok(status)
+}
+
+/// Determine how to attach a fiber stream to a child process.
+///
+/// Returns the handle to give to the child, along with the stream if it must
+/// be relayed through a pipe instead of being shared directly.
+fn attach<S: AsRawFd>(stream: Option<S>) -> io::Result<(Stdio, Option<S>)> {
+    match stream {
+        Some(stream) => match pipes::child_stdio(&stream)? {
+            Some(stdio) => Ok((stdio, None)),
+            None => Ok((Stdio::piped(), Some(stream))),
+        },
+        None => Ok((Stdio::null(), None)),
     }
 }
 
+/// Create the exception raised when a command exits unsuccessfully in strict
+/// mode.
+fn command_failed(command: &str, args: &[String], status: ExitStatus) -> Exception {
+    let command_line = Some(command).into_iter()
+        .chain(args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let message = match (status.code(), status.signal()) {
+        (Some(code), _) => format!("command `{}` exited with status {}", command_line, code),
+        (None, Some(signal)) => format!("command `{}` was terminated by signal {}", command_line, signal),
+        (None, None) => format!("command `{}` failed", command_line),
+    };
+
+    Exception::from(Value::from(table! {
+        "type" => "command-failed",
+        "message" => message,
+        "command" => command_line,
+        "status" => status.code().map(|code| Value::from(code as f64)).unwrap_or(Value::Nil),
+        "signal" => status.signal().map(|signal| Value::from(signal as f64)).unwrap_or(Value::Nil),
+    }))
+}
+
 /// Executes a shell command, replacing the current process with the new process.
 ///
 /// Does not return.
@@ -64,7 +281,7 @@ async fn exec(_: &mut Fiber, _: &[Value]) -> Result<Value, Exception> {
     unimplemented!();
 }
 
-/// Puts the current process to sleep for a given number of seconds.
+/// Waits for a given number of seconds.
 async fn sleep(_: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
     if let Some(Value::Number(seconds)) = args.first() {
         let seconds = *seconds;
@@ -79,7 +296,7 @@ async fn sleep(_: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
         };
 
         log::debug!("sleeping for {}ms", duration.as_millis());
-        thread::sleep(duration);
+        tokio::time::delay_for(duration).await;
 
         Ok(Value::Nil)
     } else {
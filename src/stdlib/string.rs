@@ -1,7 +1,9 @@
 use crate::runtime::prelude::*;
+use crate::runtime::string::RipString;
 
 pub fn load() -> Result<Value, Exception> {
     Ok(table! {
+        "lines" => Value::foreign_fn(lines),
         // "len" => Value::foreign_fn(|_, _| {
         //     async {
         //         Ok(Value::Nil)
@@ -19,3 +21,32 @@ pub fn load() -> Result<Value, Exception> {
     }
     .into())
 }
+
+/// Splits a string into a list of lines.
+///
+/// Lines may be terminated by either `\n` or `\r\n`, and the line terminators
+/// are not included in the returned strings.
+async fn lines(_: &mut Fiber, args: &[Value]) -> Result<Value, Exception> {
+    let string = match args.first().and_then(Value::as_string) {
+        Some(string) => string,
+        None => throw!("string to split required"),
+    };
+
+    let mut bytes = string.as_bytes();
+    if bytes.last() == Some(&b'\n') {
+        bytes = &bytes[..bytes.len() - 1];
+    }
+
+    if bytes.is_empty() {
+        return Ok(Value::List(Vec::new()));
+    }
+
+    Ok(bytes
+        .split(|&byte| byte == b'\n')
+        .map(|line| match line.last() {
+            Some(b'\r') => &line[..line.len() - 1],
+            _ => line,
+        })
+        .map(RipString::from)
+        .collect())
+}
@@ -11,6 +11,23 @@ pub struct Block {
 
     /// A list of statements to execute.
     pub statements: Vec<Pipeline>,
+
+    /// Where each statement starts in the source.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub positions: Vec<Position>,
+}
+
+/// A line and column in source code, both counting from 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
 }
 
 /// A pipeline of function calls.
@@ -27,16 +44,69 @@ pub enum Call {
     Named {
         function: String,
         args: Vec<Expr>,
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+        redirections: Vec<Redirection>,
     },
 
     /// A function call on a callable object.
     #[cfg_attr(feature = "serde", serde(rename = "UnnamedCall"))]
     Unnamed {
         function: Box<Expr>,
-        args: Vec<Expr>
+        args: Vec<Expr>,
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+        redirections: Vec<Redirection>,
     },
 }
 
+/// A redirection of one of the standard streams of a function call.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Redirection {
+    /// Read standard input from a file, such as `< input.txt`.
+    ReadStdin(Expr),
+
+    /// Write standard output to a file, replacing its contents, such as `> output.txt`.
+    WriteStdout(Expr),
+
+    /// Append standard output to the end of a file, such as `>> output.txt`.
+    AppendStdout(Expr),
+
+    /// Write standard error to a file, replacing its contents, such as `2> errors.txt`.
+    WriteStderr(Expr),
+
+    /// Append standard error to the end of a file, such as `2>> errors.txt`.
+    AppendStderr(Expr),
+
+    /// Write standard error to wherever standard output is currently written, `2>&1`.
+    StderrToStdout,
+
+    /// Write standard output to wherever standard error is currently written, `>&2`.
+    StdoutToStderr,
+
+    /// Feed the value of an expression followed by a newline to standard input, such as `<<< $text`.
+    HereString(Expr),
+
+    /// Feed the lines of a here document to standard input, such as `<<EOF`.
+    HereDocument(InterpolatedString),
+}
+
+impl Redirection {
+    /// Get the expression for the file or value this redirection refers to, if any.
+    pub fn target(&self) -> Option<&Expr> {
+        match self {
+            Redirection::ReadStdin(expr)
+            | Redirection::HereString(expr)
+            | Redirection::WriteStdout(expr)
+            | Redirection::AppendStdout(expr)
+            | Redirection::WriteStderr(expr)
+            | Redirection::AppendStderr(expr) => Some(expr),
+            Redirection::StderrToStdout
+            | Redirection::StdoutToStderr
+            | Redirection::HereDocument(_) => None,
+        }
+    }
+}
+
 /// Abstract representation of an expression.
 ///
 /// Contains a variant for each different expression type.
@@ -49,8 +119,10 @@ pub enum Call {
 pub enum Expr {
     Block(Block),
     Pipeline(Pipeline),
+    ProcessSubstitution(ProcessSubstitution),
     MemberAccess(MemberAccess),
     Substitution(Substitution),
+    Glob(Glob),
     Table(TableLiteral),
     List(ListLiteral),
     Number(f64),
@@ -58,6 +130,17 @@ pub enum Expr {
     String(String),
 }
 
+/// An unquoted word containing glob patterns, such as `*.rs` or `~/src`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Glob(pub String);
+
+/// A pipeline whose output is passed to a function call as the path of a pipe,
+/// such as `<(ls)`. Only valid as a call argument.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessSubstitution(pub Pipeline);
+
 #[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MemberAccess(pub Box<Expr>, pub String);
@@ -73,7 +156,9 @@ pub enum Substitution {
 
     /// A pipeline substitution, such as `$(add 1 2 3)`.
     ///
-    /// Evaluates to the final return value after executing the pipeline.
+    /// Evaluates to the standard output of the pipeline as a string, with
+    /// trailing newlines removed. If the pipeline produces no output, evaluates
+    /// to the final return value of the pipeline instead.
     Pipeline(Pipeline),
 
     /// A simple variable substitution, such as `$foo`.
@@ -21,6 +21,32 @@ impl ParseError {
             pest::error::InputLocation::Span(span) => span,
         }
     }
+
+    /// Check whether the error was caused by the source ending too early, such as in the middle of a block or string.
+    ///
+    /// Incomplete source might become valid if more is added to the end, while other errors can only be fixed by
+    /// changing what is already there.
+    pub fn is_incomplete(&self) -> bool {
+        let source = self.file.source();
+        let rest = &source[self.position().0.min(source.len())..];
+
+        // Single-quoted strings are parsed atomically, so an unclosed one is reported where it starts.
+        rest.is_empty() || rest == "\\" || (rest.starts_with('\'') && !has_closing_quote(&rest[1..]))
+    }
+}
+
+fn has_closing_quote(s: &str) -> bool {
+    let mut escaped = false;
+
+    for c in s.chars() {
+        match c {
+            '\'' if !escaped => return true,
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+
+    false
 }
 
 impl fmt::Display for ParseError {
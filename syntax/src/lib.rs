@@ -12,6 +12,7 @@ pub mod ast;
 pub mod error;
 mod parser;
 pub mod source;
+pub mod tokens;
 
 /// Attempt to parse a source file into an abstract syntax tree.
 ///
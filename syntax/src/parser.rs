@@ -1,6 +1,6 @@
 use crate::ast::*;
 use pest::error::Error;
-use pest::iterators::Pair;
+use pest::iterators::{Pair, Pairs};
 use std::convert::TryFrom;
 
 #[derive(pest_derive::Parser)]
@@ -19,8 +19,16 @@ impl TryFrom<Pair<'_, Rule>> for Block {
             pairs.pop();
         }
 
-        let statements = pairs.pop().unwrap()
-            .into_inner()
+        let statement_pairs = pairs.pop().unwrap().into_inner().collect::<Vec<_>>();
+
+        let positions = statement_pairs.iter()
+            .map(|pair| {
+                let (line, column) = pair.as_span().start_pos().line_col();
+                Position { line, column }
+            })
+            .collect();
+
+        let statements = statement_pairs.into_iter()
             .map(Pipeline::try_from)
             .collect::<Result<_, Error<Rule>>>()?;
 
@@ -33,6 +41,7 @@ impl TryFrom<Pair<'_, Rule>> for Block {
         Ok(Self {
             named_params,
             statements,
+            positions,
         })
     }
 }
@@ -58,18 +67,24 @@ impl TryFrom<Pair<'_, Rule>> for Call {
         match pair.as_rule() {
             Rule::named_call => {
                 let mut pairs = pair.into_inner();
+                let function = string_literal(pairs.next().unwrap());
+                let (args, redirections) = call_arguments(pairs)?;
 
                 Ok(Call::Named {
-                    function: string_literal(pairs.next().unwrap()),
-                    args: pairs.map(Expr::try_from).collect::<Result<_, _>>()?,
+                    function,
+                    args,
+                    redirections,
                 })
             }
             Rule::unnamed_call => {
                 let mut pairs = pair.into_inner();
+                let function = Box::new(pairs.next().map(Expr::try_from).unwrap()?);
+                let (args, redirections) = call_arguments(pairs)?;
 
                 Ok(Call::Unnamed {
-                    function: Box::new(pairs.next().map(Expr::try_from).unwrap()?),
-                    args: pairs.map(Expr::try_from).collect::<Result<_, _>>()?,
+                    function,
+                    args,
+                    redirections,
                 })
             }
             rule => panic!("unexpected rule: {:?}", rule),
@@ -77,6 +92,110 @@ impl TryFrom<Pair<'_, Rule>> for Call {
     }
 }
 
+/// Separate the arguments of a call from its redirections.
+fn call_arguments(pairs: Pairs<'_, Rule>) -> Result<(Vec<Expr>, Vec<Redirection>), Error<Rule>> {
+    let mut args = Vec::new();
+    let mut redirections = Vec::new();
+
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::redirection => {
+                let following = here_document_redirections(&pair);
+                redirections.push(Redirection::try_from(pair)?);
+
+                for pair in following {
+                    redirections.push(Redirection::try_from(pair)?);
+                }
+            }
+            Rule::process_substitution => {
+                let pipeline = Pipeline::try_from(pair.into_inner().next().unwrap())?;
+                args.push(Expr::ProcessSubstitution(ProcessSubstitution(pipeline)));
+            }
+            _ => args.push(Expr::try_from(pair)?),
+        }
+    }
+
+    Ok((args, redirections))
+}
+
+/// Get the redirections written after a here document, which are parsed as
+/// part of it.
+fn here_document_redirections<'i>(redirection: &Pair<'i, Rule>) -> Vec<Pair<'i, Rule>> {
+    let operator = redirection.clone().into_inner().next().unwrap();
+
+    if operator.as_rule() != Rule::here_document {
+        return Vec::new();
+    }
+
+    operator.into_inner().next().unwrap().into_inner()
+        .filter(|pair| pair.as_rule() == Rule::here_document_redirections)
+        .flat_map(Pair::into_inner)
+        .collect()
+}
+
+impl TryFrom<Pair<'_, Rule>> for Redirection {
+    type Error = Error<Rule>;
+
+    fn try_from(pair: Pair<'_, Rule>) -> Result<Self, Error<Rule>> {
+        assert_eq!(pair.as_rule(), Rule::redirection);
+
+        let mut pairs = pair.into_inner();
+        let operator = pairs.next().unwrap();
+
+        if operator.as_rule() == Rule::here_document {
+            return Ok(Redirection::HereDocument(here_document(operator)?));
+        }
+
+        let operator = operator.as_rule();
+        let target = pairs.next().map(Expr::try_from).transpose()?;
+
+        Ok(match (operator, target) {
+            (Rule::stderr_to_stdout, None) => Redirection::StderrToStdout,
+            (Rule::stdout_to_stderr, None) => Redirection::StdoutToStderr,
+            (Rule::append_stderr, Some(target)) => Redirection::AppendStderr(target),
+            (Rule::write_stderr, Some(target)) => Redirection::WriteStderr(target),
+            (Rule::append_stdout, Some(target)) => Redirection::AppendStdout(target),
+            (Rule::write_stdout, Some(target)) => Redirection::WriteStdout(target),
+            (Rule::read_stdin, Some(target)) => Redirection::ReadStdin(target),
+            (Rule::here_string, Some(target)) => Redirection::HereString(target),
+            (rule, _) => panic!("unexpected rule: {:?}", rule),
+        })
+    }
+}
+
+/// Parse the text of a here document. Adjacent literal text is joined together.
+fn here_document(pair: Pair<'_, Rule>) -> Result<InterpolatedString, Error<Rule>> {
+    let mut parts = Vec::new();
+
+    for pair in pair.into_inner().next().unwrap().into_inner() {
+        let text = match pair.as_rule() {
+            Rule::literal_here_document_text => pair.as_str(),
+            Rule::here_document_newline => "\n",
+            Rule::interpolated_here_document_part => {
+                let pair = pair.into_inner().next().unwrap();
+
+                if pair.as_rule() == Rule::substitution {
+                    parts.push(InterpolatedStringPart::Substitution(Substitution::try_from(pair)?));
+                    continue;
+                }
+
+                pair.as_str()
+            }
+            _ => continue,
+        };
+
+        let text = text.replace("\r\n", "\n");
+
+        match parts.last_mut() {
+            Some(InterpolatedStringPart::String(string)) => string.push_str(&text),
+            _ if text.is_empty() => {}
+            _ => parts.push(InterpolatedStringPart::String(text)),
+        }
+    }
+
+    Ok(InterpolatedString(parts))
+}
+
 impl TryFrom<Pair<'_, Rule>> for Expr {
     type Error = Error<Rule>;
 
@@ -96,6 +215,7 @@ impl TryFrom<Pair<'_, Rule>> for Expr {
                     expr
                 },
                 Rule::substitution => Expr::Substitution(Substitution::try_from(pair)?),
+                Rule::glob => Expr::Glob(Glob(pair.as_str().to_owned())),
                 Rule::table_literal => Expr::Table(TableLiteral::try_from(pair)?),
                 Rule::list_literal => Expr::List(ListLiteral::try_from(pair)?),
                 Rule::interpolated_string => Expr::InterpolatedString(InterpolatedString::try_from(pair)?),
@@ -0,0 +1,421 @@
+//! A lenient tokenizer for highlighting source code.
+//!
+//! Unlike the parser, the tokenizer accepts any input, including programs that are incomplete or invalid, which makes
+//! it suitable for highlighting code as it is being typed. Tokens follow the structure of the grammar closely enough
+//! for highlighting, but no further.
+
+use std::ops::Range;
+
+/// The kind of a token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// The name of the function in a named call.
+    Command,
+
+    /// An unquoted string or glob used as an argument or member name.
+    Word,
+
+    /// A quoted string, the literal parts of an interpolated string, or the body of a here document.
+    String,
+
+    /// The parts of a substitution, other than a nested pipeline.
+    Substitution,
+
+    /// A number literal.
+    Number,
+
+    /// A comment.
+    Comment,
+
+    /// The braces and parameter list of a block.
+    Block,
+
+    /// Pipes, statement separators, brackets, and member access and redirection operators.
+    Operator,
+}
+
+/// A token of source code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+
+    /// The byte range of the token in the source.
+    pub span: Range<usize>,
+}
+
+/// Split source code into tokens, in order.
+///
+/// Whitespace, and any text that cannot begin a token, is not covered by any token.
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut lexer = Lexer {
+        source,
+        pos: 0,
+        tokens: Vec::new(),
+        here_documents: Vec::new(),
+    };
+
+    while lexer.pos < source.len() {
+        lexer.statements();
+
+        // Skip over an unmatched closing bracket.
+        if let Some(c) = lexer.peek() {
+            lexer.token(TokenKind::Operator, c.len_utf8());
+        }
+    }
+
+    lexer.tokens
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    pos: usize,
+    tokens: Vec<Token>,
+
+    /// Delimiters of here documents whose bodies begin on the next line.
+    here_documents: Vec<String>,
+}
+
+impl<'a> Lexer<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    /// Add a token of the given length at the current position.
+    fn token(&mut self, kind: TokenKind, len: usize) {
+        let end = (self.pos + len).min(self.source.len());
+
+        if end > self.pos {
+            self.tokens.push(Token {
+                kind,
+                span: self.pos..end,
+            });
+        }
+
+        self.pos = end;
+    }
+
+    /// Tokenize statements until the end of the source or a closing bracket, which is not consumed.
+    fn statements(&mut self) {
+        self.code(true);
+    }
+
+    /// Tokenize code until the end of the source or a closing bracket, which is not consumed.
+    ///
+    /// If `command` is true, the first word is the name of a called function.
+    fn code(&mut self, mut command: bool) {
+        while let Some(c) = self.peek() {
+            let rest = self.rest();
+
+            match c {
+                ')' | '}' | ']' => return,
+                ' ' | '\t' | '\r' => self.pos += 1,
+                '\\' if rest[1..].starts_with('\n') => self.pos += 2,
+                '\n' => {
+                    self.token(TokenKind::Operator, 1);
+                    self.here_document_bodies();
+                    command = true;
+                }
+                ';' | '|' => {
+                    self.token(TokenKind::Operator, 1);
+                    command = true;
+                }
+                '#' => {
+                    let len = rest.find('\n').unwrap_or(rest.len());
+                    self.token(TokenKind::Comment, len);
+                }
+                '{' => {
+                    self.block();
+                    command = false;
+                }
+                '(' => {
+                    self.nested(TokenKind::Operator, 1, ')');
+                    command = false;
+                }
+                '[' => {
+                    self.token(TokenKind::Operator, 1);
+                    self.code(false);
+                    if self.peek() == Some(']') {
+                        self.token(TokenKind::Operator, 1);
+                    }
+                    command = false;
+                }
+                ':' => self.token(TokenKind::Operator, 1),
+                '<' => {
+                    if let Some(len) = block_params_len(rest) {
+                        self.token(TokenKind::Block, len);
+                    } else if rest.starts_with("<(") {
+                        self.nested(TokenKind::Operator, 2, ')');
+                        command = false;
+                    } else if rest.starts_with("<<<") {
+                        self.token(TokenKind::Operator, 3);
+                    } else if rest.starts_with("<<") {
+                        self.here_document();
+                    } else {
+                        self.token(TokenKind::Operator, 1);
+                    }
+                }
+                '>' | '2' if redirection_len(rest) > 0 => {
+                    self.token(TokenKind::Operator, redirection_len(rest));
+                }
+                '\'' => {
+                    let len = single_quoted_len(rest);
+                    self.token(TokenKind::String, len);
+                    command = false;
+                }
+                '"' => {
+                    self.interpolated_string();
+                    command = false;
+                }
+                '$' => {
+                    self.substitution();
+                    command = false;
+                }
+                '-' if rest.starts_with("->") => {
+                    self.token(TokenKind::Operator, 2);
+
+                    if rest[2..].starts_with('\'') {
+                        let len = single_quoted_len(&rest[2..]);
+                        self.token(TokenKind::String, len);
+                    } else {
+                        let len = symbol_len(&rest[2..]);
+                        self.token(TokenKind::Word, len);
+                    }
+                }
+                _ => {
+                    let len = word_len(rest);
+
+                    if len == 0 {
+                        self.pos += c.len_utf8();
+                    } else if is_number(&rest[..len]) {
+                        self.token(TokenKind::Number, len);
+                    } else if command {
+                        self.token(TokenKind::Command, len);
+                    } else {
+                        self.token(TokenKind::Word, len);
+                    }
+
+                    command = false;
+                }
+            }
+        }
+    }
+
+    /// Tokenize a block, including its closing brace if present.
+    fn block(&mut self) {
+        self.token(TokenKind::Block, 1);
+        self.statements();
+
+        if self.peek() == Some('}') {
+            self.token(TokenKind::Block, 1);
+        }
+    }
+
+    /// Tokenize an opening bracket of the given length, a pipeline inside it, and its closing bracket if present.
+    fn nested(&mut self, kind: TokenKind, len: usize, closer: char) {
+        self.token(kind, len);
+        self.statements();
+
+        if self.peek() == Some(closer) {
+            self.token(kind, 1);
+        }
+    }
+
+    fn substitution(&mut self) {
+        let rest = self.rest();
+
+        if rest.starts_with("$(") {
+            self.nested(TokenKind::Substitution, 2, ')');
+        } else if rest.starts_with("${") {
+            let len = rest.find('}').map(|i| i + 1).unwrap_or(rest.len());
+            self.token(TokenKind::Substitution, len);
+        } else if rest[1..].starts_with('\'') {
+            let len = 1 + single_quoted_len(&rest[1..]);
+            self.token(TokenKind::Substitution, len);
+        } else {
+            let len = 1 + symbol_len(&rest[1..]);
+            self.token(TokenKind::Substitution, len);
+        }
+    }
+
+    fn interpolated_string(&mut self) {
+        // The opening quote belongs to the first literal part.
+        self.token(TokenKind::String, 1);
+
+        loop {
+            let len = literal_part_len(self.rest());
+            self.extend_or_push(TokenKind::String, len);
+
+            match self.peek() {
+                Some('"') => {
+                    self.extend_or_push(TokenKind::String, 1);
+                    return;
+                }
+                Some('$') => self.substitution(),
+                _ => return,
+            }
+        }
+    }
+
+    /// Add a token, merging it into the previous token if it is of the same kind and adjacent.
+    fn extend_or_push(&mut self, kind: TokenKind, len: usize) {
+        let pos = self.pos;
+
+        match self.tokens.last_mut() {
+            Some(last) if last.kind == kind && last.span.end == pos => {
+                last.span.end = (pos + len).min(self.source.len());
+                self.pos = last.span.end;
+            }
+            _ => self.token(kind, len),
+        }
+    }
+
+    /// Tokenize the start of a here document, such as `<<EOF`, and remember its delimiter.
+    fn here_document(&mut self) {
+        let rest = &self.rest()[2..];
+        let quoted = rest.starts_with('\'');
+        let name = &rest[quoted as usize..];
+        let name_len = name.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(name.len());
+
+        let mut len = 2 + quoted as usize + name_len;
+        if quoted && name[name_len..].starts_with('\'') {
+            len += 1;
+        }
+
+        self.here_documents.push(name[..name_len].to_owned());
+        self.token(TokenKind::Operator, len);
+    }
+
+    /// Tokenize the bodies of any here documents started on the line just ended, up to and including their terminator
+    /// lines.
+    fn here_document_bodies(&mut self) {
+        for delimiter in std::mem::take(&mut self.here_documents) {
+            let rest = self.rest();
+            let mut len = 0;
+
+            for line in rest.split_inclusive('\n') {
+                len += line.len();
+
+                if line.trim() == delimiter {
+                    break;
+                }
+            }
+
+            self.token(TokenKind::String, len);
+        }
+    }
+}
+
+/// Get the length of the symbol at the start of some text, stopping before any member operator.
+fn symbol_len(text: &str) -> usize {
+    let mut len = 0;
+
+    for (i, c) in text.char_indices() {
+        if !is_symbol_char(c) || text[i..].starts_with("->") {
+            break;
+        }
+        len = i + c.len_utf8();
+    }
+
+    len
+}
+
+/// Get the length of an unquoted word at the start of some text, including glob wildcards and a leading tilde.
+fn word_len(text: &str) -> usize {
+    let mut len = 0;
+
+    while let Some(c) = text[len..].chars().next() {
+        let rest = &text[len..];
+
+        if rest.starts_with("->") {
+            break;
+        }
+
+        if is_symbol_char(c) || c == '*' || (c == '~' && len == 0) {
+            len += c.len_utf8();
+        } else if (c == '[' || c == '{') && len > 0 {
+            // A character class or brace alternatives inside a word.
+            let closer = if c == '[' { ']' } else { '}' };
+
+            match rest.find(|c: char| c == closer || c.is_whitespace()) {
+                Some(end) if rest[end..].starts_with(closer) && (c == '[' || rest[..end].contains(',')) => len += end + 1,
+                _ => break,
+            }
+        } else {
+            break;
+        }
+    }
+
+    len
+}
+
+/// Get the length of a single-quoted string at the start of some text, or the rest of the text if it is not closed.
+fn single_quoted_len(text: &str) -> usize {
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            '\'' if !escaped => return i + 1,
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+
+    text.len()
+}
+
+/// Get the length of the literal text at the start of the inside of an interpolated string.
+fn literal_part_len(text: &str) -> usize {
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' | '$' if !escaped => return i,
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+
+    text.len()
+}
+
+/// Get the length of a block parameter list at the start of some text, if that is what it is.
+fn block_params_len(text: &str) -> Option<usize> {
+    let end = text.find('>')?;
+    let params = &text[1..end];
+
+    if params.chars().all(|c| is_symbol_char(c) || c == ' ' || c == '\t') && text[end + 1..].trim_start().starts_with('{') {
+        Some(end + 1)
+    } else {
+        None
+    }
+}
+
+/// Get the length of a redirection operator other than those beginning with `<` at the start of some text, or zero if
+/// there isn't one.
+fn redirection_len(text: &str) -> usize {
+    ["2>&1", "2>>", "2>", ">&2", ">>", ">"].iter()
+        .find(|operator| text.starts_with(*operator))
+        .map_or(0, |operator| operator.len())
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_-?!./".contains(c)
+}
+
+fn is_number(word: &str) -> bool {
+    let digits = word.strip_prefix('-').unwrap_or(word);
+    let mut parts = digits.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let fraction = parts.next();
+
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+    match fraction {
+        None => is_digits(whole),
+        Some(fraction) => (whole.is_empty() || is_digits(whole)) && is_digits(fraction),
+    }
+}
@@ -0,0 +1,27 @@
+use riptide_syntax::parse;
+use riptide_syntax::source::SourceFile;
+
+fn is_incomplete(source: &str) -> bool {
+    parse(SourceFile::named("<test>", source.to_owned())).unwrap_err().is_incomplete()
+}
+
+#[test]
+fn unclosed_input_is_incomplete() {
+    assert!(is_incomplete("each $list {"));
+    assert!(is_incomplete("each $list {\n  echo $0\n"));
+    assert!(is_incomplete("echo [a b"));
+    assert!(is_incomplete("echo \"abc"));
+    assert!(is_incomplete("echo 'abc"));
+    assert!(is_incomplete("echo $(ls"));
+    assert!(is_incomplete("ls |"));
+    assert!(is_incomplete("ls \\"));
+    assert!(is_incomplete("cat <<EOF\nabc\n"));
+}
+
+#[test]
+fn invalid_input_is_not_incomplete() {
+    assert!(!is_incomplete("echo }"));
+    assert!(!is_incomplete("echo ]"));
+    assert!(!is_incomplete("echo \"abc\" )"));
+    assert!(!is_incomplete("echo ) 'abc"));
+}
@@ -53,3 +53,14 @@ fn serialize_ast(ast: &ast::Block) -> String {
     ast.serialize(&mut serializer).unwrap();
     serializer.into_output_string()
 }
+
+#[test]
+pub fn statement_positions_are_recorded() {
+    let ast = parse(SourceFile::named("positions", "echo a; echo b\n\n  { echo c\n  }\n")).unwrap();
+
+    assert_eq!(ast.positions, vec![
+        ast::Position { line: 1, column: 1 },
+        ast::Position { line: 1, column: 9 },
+        ast::Position { line: 3, column: 3 },
+    ]);
+}
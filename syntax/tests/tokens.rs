@@ -0,0 +1,69 @@
+use riptide_syntax::tokens::{tokenize, TokenKind::{self, *}};
+
+fn tokens(source: &str) -> Vec<(TokenKind, &str)> {
+    tokenize(source)
+        .into_iter()
+        .map(|token| (token.kind, &source[token.span]))
+        .collect()
+}
+
+#[test]
+fn calls_and_arguments() {
+    assert_eq!(tokens("ls -l *.rs 42 | grep 'a b' # done"), vec![
+        (Command, "ls"),
+        (Word, "-l"),
+        (Word, "*.rs"),
+        (Number, "42"),
+        (Operator, "|"),
+        (Command, "grep"),
+        (String, "'a b'"),
+        (Comment, "# done"),
+    ]);
+}
+
+#[test]
+fn blocks_and_substitutions() {
+    assert_eq!(tokens("each $fs->list <x> { echo \"x is $x!\" $(pwd) }"), vec![
+        (Command, "each"),
+        (Substitution, "$fs"),
+        (Operator, "->"),
+        (Word, "list"),
+        (Block, "<x>"),
+        (Block, "{"),
+        (Command, "echo"),
+        (String, "\"x is "),
+        (Substitution, "$x!"),
+        (String, "\""),
+        (Substitution, "$("),
+        (Command, "pwd"),
+        (Substitution, ")"),
+        (Block, "}"),
+    ]);
+}
+
+#[test]
+fn redirections_and_here_documents() {
+    assert_eq!(tokens("cat <<EOF 2>&1 > out\n$x\nEOF\nls"), vec![
+        (Command, "cat"),
+        (Operator, "<<EOF"),
+        (Operator, "2>&1"),
+        (Operator, ">"),
+        (Word, "out"),
+        (Operator, "\n"),
+        (String, "$x\nEOF\n"),
+        (Command, "ls"),
+    ]);
+}
+
+#[test]
+fn incomplete_source() {
+    assert_eq!(tokens("if { echo \"unterminated $"), vec![
+        (Command, "if"),
+        (Block, "{"),
+        (Command, "echo"),
+        (String, "\"unterminated "),
+        (Substitution, "$"),
+    ]);
+
+    assert_eq!(tokens(") ls"), vec![(Operator, ")"), (Command, "ls")]);
+}